@@ -0,0 +1,21 @@
+/// Animated PNG through the `png` crate.
+
+use crate::generation::RgbImage;
+
+/// Looping APNG of `frames`, all the size of the first one
+pub fn encode(frames:&[&RgbImage], fps:u16) -> Result<Vec<u8>, png::EncodingError> {
+	let Some(first) = frames.first() else { return Ok( vec![] ) };
+	let mut out = Vec::new();
+	{
+		let mut encoder = png::Encoder::new( &mut out, first.width, first.height );
+		encoder.set_color( png::ColorType::Rgb );
+		encoder.set_depth( png::BitDepth::Eight );
+		encoder.set_animated( frames.len() as u32, 0 )?;
+		encoder.set_frame_delay( 1, fps.max(1) )?;
+		let mut writer = encoder.write_header()?;
+		for frame in frames {
+			writer.write_image_data( &frame.pixels )?;
+		}
+	}
+	Ok(out)
+}
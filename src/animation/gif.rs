@@ -0,0 +1,283 @@
+/// Animated GIF encoder.
+///
+/// Frames share one fixed 6x7x6 color cube palette and are ordered dithered into it, so there is
+/// no per frame quantization and colors don't flicker between frames.
+
+use std::collections::HashMap;
+
+use crate::generation::RgbImage;
+
+/// Levels of red, green and blue in the palette
+const LEVELS:[u32;3] = [6, 7, 6];
+
+const BAYER:[[u8;4];4] = [[0, 8, 2, 10], [12, 4, 14, 6], [3, 11, 1, 9], [15, 7, 13, 5]];
+
+const MIN_CODE_SIZE:u8 = 8;
+const CLEAR:u16 = 1 << MIN_CODE_SIZE;
+const END:u16 = CLEAR + 1;
+const MAX_CODES:u16 = 4096;
+
+/// Looping GIF of `frames`, all the size of the first one
+pub fn encode(frames:&[&RgbImage], fps:u16) -> std::io::Result<Vec<u8>> {
+	let Some(first) = frames.first() else { return Ok( vec![] ) };
+	if let Some(frame) = frames.iter().find( |f| (f.width, f.height) != (first.width, first.height) ) {
+		return Err( std::io::Error::new( std::io::ErrorKind::InvalidInput, format!(
+			"{}x{} frame in a {}x{} animation", frame.width, frame.height, first.width, first.height
+		) ) )
+	}
+	let (width, height) = (first.width as u16, first.height as u16);
+	let mut out = b"GIF89a".to_vec();
+	out.extend( width.to_le_bytes() );
+	out.extend( height.to_le_bytes() );
+	// Global color table of 256 entries, 8 bits per channel
+	out.extend( [0xF7, 0, 0] );
+	out.extend( palette() );
+	// Loop forever
+	out.extend( [0x21, 0xFF, 0x0B] );
+	out.extend( b"NETSCAPE2.0" );
+	out.extend( [0x03, 0x01, 0x00, 0x00, 0x00] );
+
+	let delay = ((100. / fps.max(1) as f64).round() as u16).max(2);
+	for frame in frames {
+		out.extend( [0x21, 0xF9, 0x04, 0x00] );
+		out.extend( delay.to_le_bytes() );
+		out.extend( [0x00, 0x00] );
+
+		out.push( 0x2C );
+		out.extend( [0, 0, 0, 0] );
+		out.extend( width.to_le_bytes() );
+		out.extend( height.to_le_bytes() );
+		out.push( 0x00 );
+
+		out.push( MIN_CODE_SIZE );
+		for block in lzw( &indices(frame) ).chunks(255) {
+			out.push( block.len() as u8 );
+			out.extend( block );
+		}
+		out.push( 0x00 );
+	}
+	out.push( 0x3B );
+	Ok(out)
+}
+
+fn palette() -> Vec<u8> {
+	let level = |l:u32, levels:u32| (l * 255 / (levels - 1)) as u8;
+	let mut out = Vec::with_capacity( 256 * 3 );
+	for r in 0..LEVELS[0] {
+		for g in 0..LEVELS[1] {
+			for b in 0..LEVELS[2] {
+				out.extend( [level(r, LEVELS[0]), level(g, LEVELS[1]), level(b, LEVELS[2])] );
+			}
+		}
+	}
+	out.resize( 256 * 3, 0 );
+	out
+}
+
+/// Palette index of every pixel, ordered dithered
+fn indices(image:&RgbImage) -> Vec<u8> {
+	let mut out = Vec::with_capacity( (image.width * image.height) as usize );
+	for y in 0..image.height {
+		for x in 0..image.width {
+			let threshold = (BAYER[(y % 4) as usize][(x % 4) as usize] as f64 + 0.5) / 16. - 0.5;
+			let pixel = image.get(x, y);
+			let level = |c:usize| {
+				let steps = (LEVELS[c] - 1) as f64;
+				(pixel[c] as f64 * steps / 255. + threshold).round().clamp( 0., steps ) as u32
+			};
+			out.push( (level(0) * LEVELS[1] * LEVELS[2] + level(1) * LEVELS[2] + level(2)) as u8 );
+		}
+	}
+	out
+}
+
+/// Variable width LZW codes of `data`, packed least significant bit first
+fn lzw(data:&[u8]) -> Vec<u8> {
+	let mut bits = BitWriter::default();
+	let mut table = HashMap::<(u16, u8), u16>::new();
+	let mut code_size = MIN_CODE_SIZE as u32 + 1;
+	let mut next = END + 1;
+	bits.write( CLEAR, code_size );
+
+	let Some((&first, rest)) = data.split_first() else {
+		bits.write( END, code_size );
+		return bits.finish()
+	};
+	let mut prefix = first as u16;
+	for &k in rest {
+		if let Some(&code) = table.get( &(prefix, k) ) {
+			prefix = code;
+			continue
+		}
+		bits.write( prefix, code_size );
+		table.insert( (prefix, k), next );
+		next += 1;
+		if next > (1 << code_size) && code_size < 12 {
+			code_size += 1;
+		}
+		// Start over when the table is full
+		if next == MAX_CODES {
+			bits.write( CLEAR, code_size );
+			table.clear();
+			next = END + 1;
+			code_size = MIN_CODE_SIZE as u32 + 1;
+		}
+		prefix = k as u16;
+	}
+	bits.write( prefix, code_size );
+	bits.write( END, code_size );
+	bits.finish()
+}
+
+#[derive(Default)]
+struct BitWriter {
+	out : Vec<u8>,
+	acc : u32,
+	len : u32,
+}
+
+impl BitWriter {
+	fn write(&mut self, code:u16, size:u32) {
+		self.acc |= (code as u32) << self.len;
+		self.len += size;
+		while self.len >= 8 {
+			self.out.push( self.acc as u8 );
+			self.acc >>= 8;
+			self.len -= 8;
+		}
+	}
+
+	fn finish(mut self) -> Vec<u8> {
+		if self.len > 0 {
+			self.out.push( self.acc as u8 );
+		}
+		self.out
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/// Width, height, palette and per frame palette indices of a GIF written by `encode`
+	fn decode(gif:&[u8]) -> (u16, u16, Vec<u8>, Vec<Vec<u8>>) {
+		assert_eq!( &gif[..6], b"GIF89a" );
+		let size = |at:usize| u16::from_le_bytes( [gif[at], gif[at+1]] );
+		let (width, height) = (size(6), size(8));
+		let palette = gif[13..13 + 256*3].to_vec();
+		let mut at = 13 + 256*3;
+		let mut frames = vec![];
+		loop {
+			match gif[at] {
+				0x21 => {
+					at += 2;
+					while gif[at] != 0 {
+						at += gif[at] as usize + 1;
+					}
+					at += 1;
+				}
+				0x2C => {
+					assert_eq!( (size(at+5), size(at+7)), (width, height) );
+					assert_eq!( gif[at+10], MIN_CODE_SIZE );
+					at += 11;
+					let mut data = vec![];
+					while gif[at] != 0 {
+						data.extend( &gif[at+1..at+1 + gif[at] as usize] );
+						at += gif[at] as usize + 1;
+					}
+					at += 1;
+					frames.push( unlzw(&data) );
+				}
+				0x3B => return (width, height, palette, frames),
+				other => panic!("unexpected block {other:#x}"),
+			}
+		}
+	}
+
+	fn unlzw(data:&[u8]) -> Vec<u8> {
+		let mut bits = data.iter().flat_map( |&byte| (0..8).map( move |bit| (byte >> bit) & 1 ) );
+		let mut read = |size:u32| (0..size).map( |bit| (bits.next().unwrap() as u16) << bit ).sum::<u16>();
+		let reset = || (0..CLEAR).map( |i| vec![i as u8] ).chain( [vec![], vec![]] ).collect::<Vec<_>>();
+		let (mut table, mut code_size, mut previous) = (reset(), MIN_CODE_SIZE as u32 + 1, None::<u16>);
+		let mut out = vec![];
+		loop {
+			let code = read(code_size);
+			match code {
+				CLEAR => {
+					(table, code_size, previous) = (reset(), MIN_CODE_SIZE as u32 + 1, None);
+					continue
+				}
+				END => return out,
+				_ => {}
+			}
+			let entry = match (table.get(code as usize), previous) {
+				(Some(entry), None) => entry.clone(),
+				(Some(entry), Some(previous)) => {
+					let mut new = table[previous as usize].clone();
+					new.push( entry[0] );
+					table.push(new);
+					table[code as usize].clone()
+				}
+				(None, Some(previous)) => {
+					assert_eq!( code as usize, table.len() );
+					let mut new = table[previous as usize].clone();
+					new.push( new[0] );
+					table.push( new.clone() );
+					new
+				}
+				(None, None) => panic!("code {code} before any entry"),
+			};
+			if table.len() == 1 << code_size && code_size < 12 {
+				code_size += 1;
+			}
+			out.extend( &entry );
+			previous = Some(code);
+		}
+	}
+
+	fn noise(width:u32, height:u32, seed:u32) -> RgbImage {
+		let mut state = seed;
+		let pixels = (0..width*height*3).map( |_| {
+			state = state.wrapping_mul(1664525).wrapping_add(1013904223);
+			(state >> 24) as u8
+		} ).collect();
+		RgbImage::from_raw( width, height, pixels ).unwrap()
+	}
+
+	#[test]
+	fn round_trip() {
+		let frames = [noise(160, 120, 1), RgbImage::solid(160, 120, [40, 200, 90]), noise(160, 120, 2)];
+		let gif = encode( &frames.iter().collect::<Vec<_>>(), 8 ).unwrap();
+		let (width, height, gif_palette, decoded) = decode(&gif);
+		assert_eq!( (width, height), (160, 120) );
+		assert_eq!( gif_palette, palette() );
+		assert_eq!( decoded.len(), frames.len() );
+		for (frame, decoded) in frames.iter().zip(&decoded) {
+			assert_eq!( decoded, &indices(frame) );
+		}
+	}
+
+	#[test]
+	fn noise_fills_the_code_table() {
+		// 19200 mostly unrepeated pixels take far more than 4096 codes, so the table starts over
+		let data = indices( &noise(160, 120, 3) );
+		assert!( lzw(&data).len() * 8 > (MAX_CODES as usize) * 12 );
+		assert_eq!( unlzw( &lzw(&data) ), data );
+	}
+
+	#[test]
+	fn single_pixel() {
+		let frame = RgbImage::solid( 1, 1, [255, 0, 0] );
+		let (width, height, _, decoded) = decode( &encode( &[&frame], 10 ).unwrap() );
+		assert_eq!( (width, height), (1, 1) );
+		assert_eq!( decoded, vec![indices(&frame)] );
+		assert_eq!( unlzw( &lzw(&[]) ), Vec::<u8>::new() );
+	}
+
+	#[test]
+	fn frames_of_another_size_fail() {
+		let (first, second) = (RgbImage::new(8, 8), RgbImage::new(8, 16));
+		assert!( encode( &[&first, &second], 10 ).is_err() );
+		assert_eq!( encode( &[], 10 ).unwrap(), Vec::<u8>::new() );
+	}
+}
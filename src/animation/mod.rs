@@ -0,0 +1,163 @@
+//! Latent walks.
+//!
+//! A walk goes through keyframes of a prompt and a seed. Frames between two keyframes blend the
+//! text embeddings of the prompts ([`PromptBlend`]) and the noise of the seeds ([`Variation`]),
+//! both by slerp. Every frame is a normal job, [`Animations`] collects them by job id and writes
+//! the numbered frames and the animation once the last one is done.
+
+pub mod apng;
+pub mod gif;
+
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::generation::{GeneratedImage, GenerationRequest, PromptBlend, RgbImage, Variation};
+use crate::queue::JobId;
+
+/// Most frames a walk may have
+pub const MAX_FRAMES:usize = 1000;
+
+#[derive(Debug, Default, PartialEq, Clone, Copy)]
+pub enum AnimationFormat {
+	#[default]
+	Gif,
+	Apng,
+}
+
+impl AnimationFormat {
+	pub const ALL:[AnimationFormat;2] = [AnimationFormat::Gif, AnimationFormat::Apng];
+
+	fn file_name(&self) -> &'static str {
+		match self {
+			Self::Gif => "animation.gif",
+			Self::Apng => "animation.png",
+		}
+	}
+}
+
+impl fmt::Display for AnimationFormat {
+	fn fmt(&self, f:&mut fmt::Formatter<'_>) -> fmt::Result {
+		f.write_str( match self {
+			Self::Gif => "GIF",
+			Self::Apng => "APNG",
+		} )
+	}
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Keyframe {
+	pub prompt : String,
+	pub seed : u64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Walk {
+	pub keyframes : Vec<Keyframe>,
+
+	/// Frames from one keyframe up to the next one
+	pub frames_between : usize,
+	pub fps : u16,
+	pub format : AnimationFormat,
+}
+
+impl Walk {
+	/// Request of every frame, the last keyframe included
+	pub fn frames(&self, template:&GenerationRequest) -> Result<Vec<GenerationRequest>, String> {
+		if self.keyframes.len() < 2 {
+			return Err( "A walk needs at least two keyframes".to_string() )
+		}
+		let between = self.frames_between.max(1);
+		let count = (self.keyframes.len() - 1) * between + 1;
+		if count > MAX_FRAMES {
+			return Err( format!("{count} frames, at most {MAX_FRAMES} are allowed") )
+		}
+
+		let mut frames = vec![];
+		for pair in self.keyframes.windows(2) {
+			let (from, to) = (&pair[0], &pair[1]);
+			for n in 0..between {
+				let t = n as f64 / between as f64;
+				frames.push( frame( template, from, to, t ) );
+			}
+		}
+		let last = self.keyframes.last().unwrap();
+		frames.push( frame( template, last, last, 0. ) );
+		Ok(frames)
+	}
+}
+
+/// Frame `t` of the way from `from` to `to`
+fn frame(template:&GenerationRequest, from:&Keyframe, to:&Keyframe, t:f64) -> GenerationRequest {
+	let moving = t > 0.;
+	GenerationRequest {
+		prompt : from.prompt.clone(),
+		prompt_blend : (moving && to.prompt != from.prompt).then( || PromptBlend { prompt : to.prompt.clone(), weight : t } ),
+		variation : (moving && to.seed != from.seed).then_some( Variation { seed : to.seed, strength : t } ),
+		..template.with_seed(from.seed)
+	}
+}
+
+/// A walk waiting for its frames
+#[derive(Debug, Clone)]
+struct Animation {
+	walk : Walk,
+	jobs : Vec<JobId>,
+
+	/// `None` until the job is done, `Some(None)` when it failed
+	frames : Vec<Option<Option<RgbImage>>>,
+}
+
+/// Walks whose frames are in the queue. Provided as a context by `app`, fed by the queue listener
+#[derive(Clone, Default)]
+pub struct Animations {
+	animations : Arc<Mutex<Vec<Animation>>>,
+}
+
+impl Animations {
+	/// `jobs` are the ids of the requests of [`Walk::frames`], in the same order
+	pub fn add(&self, walk:Walk, jobs:&[JobId]) {
+		let frames = vec![None; jobs.len()];
+		self.animations.lock().unwrap().push( Animation { walk, jobs : jobs.to_vec(), frames } );
+	}
+
+	/// Record the first image of job `id`, `None` when it failed or was cancelled.
+	/// Writes the frames and the animation into a new directory of `dir` when this was the
+	/// last frame of a walk, and returns that directory
+	pub fn finished(&self, id:JobId, image:Option<&GeneratedImage>, dir:&Path) -> Option<std::io::Result<PathBuf>> {
+		let mut animations = self.animations.lock().unwrap();
+		let index = animations.iter().position( |a| a.jobs.contains(&id) )?;
+		let animation = &mut animations[index];
+		for (job, frame) in animation.jobs.iter().zip( &mut animation.frames ) {
+			if *job == id {
+				*frame = Some( image.map( |i| i.image.clone() ) );
+			}
+		}
+		if animation.frames.iter().any( Option::is_none ) {
+			return None
+		}
+		let animation = animations.remove(index);
+		drop(animations);
+		let frames = animation.frames.iter().flatten().flatten().collect::<Vec<_>>();
+		Some( save( dir, &frames, &animation.walk ) )
+	}
+}
+
+/// Write `frames` as `frame-0001.png` and on, and the animation, into a new `walk-<unix millis>`
+/// directory of `dir`. Failed frames are left out
+fn save(dir:&Path, frames:&[&RgbImage], walk:&Walk) -> std::io::Result<PathBuf> {
+	let millis = SystemTime::now().duration_since(UNIX_EPOCH).map_or( 0, |d| d.as_millis() );
+	let dir = dir.join( format!("walk-{millis}") );
+	std::fs::create_dir_all(&dir)?;
+	let invalid = |e:png::EncodingError| std::io::Error::new( std::io::ErrorKind::InvalidData, e );
+	for (n, frame) in frames.iter().enumerate() {
+		std::fs::write( dir.join( format!("frame-{:04}.png", n + 1) ), frame.to_png().map_err(invalid)? )?;
+	}
+	let animation = match walk.format {
+		AnimationFormat::Gif => gif::encode( frames, walk.fps )?,
+		AnimationFormat::Apng => apng::encode( frames, walk.fps ).map_err(invalid)?,
+	};
+	std::fs::write( dir.join( walk.format.file_name() ), animation )?;
+	Ok(dir)
+}
@@ -0,0 +1,468 @@
+/// Outpainting canvas.
+///
+/// A [`Canvas`] is an image that grows one outpaint at a time. An outpaint adds a strip on one
+/// side, pre-fills it from the image and inpaints a window made of the strip and some of the
+/// image next to it, so the job stays the same size however large the canvas gets along that
+/// side. Canvases save as PNG with their history in a text chunk and open again from it.
+
+use std::fmt;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+
+use crate::generation::{GeneratedImage, GenerationMode, GenerationRequest, Mask, RgbImage};
+use crate::metadata;
+use crate::queue::JobId;
+
+/// Keyword of the history chunk of saved canvases
+pub const CANVAS_KEY:&str = "sd-freya-canvas";
+
+#[derive(Debug, Default, PartialEq, Clone, Copy, Serialize, Deserialize)]
+pub enum Side {
+	Left,
+	#[default]
+	Right,
+	Up,
+	Down,
+}
+
+impl Side {
+	pub const ALL:[Side;4] = [Side::Left, Side::Right, Side::Up, Side::Down];
+
+	fn is_horizontal(&self) -> bool {
+		matches!( self, Self::Left | Self::Right )
+	}
+}
+
+impl fmt::Display for Side {
+	fn fmt(&self, f:&mut fmt::Formatter<'_>) -> fmt::Result {
+		f.write_str( match self {
+			Self::Left => "Left",
+			Self::Right => "Right",
+			Self::Up => "Up",
+			Self::Down => "Down",
+		} )
+	}
+}
+
+/// What the new area holds before it is inpainted
+#[derive(Debug, Default, PartialEq, Clone, Copy, Serialize, Deserialize)]
+pub enum Fill {
+	/// The edge pixels repeated outwards
+	#[default]
+	Stretch,
+
+	/// The image reflected at its edge
+	Mirror,
+
+	/// Random colors, for a denoising strength of 1
+	Noise,
+}
+
+impl Fill {
+	pub const ALL:[Fill;3] = [Fill::Stretch, Fill::Mirror, Fill::Noise];
+}
+
+impl fmt::Display for Fill {
+	fn fmt(&self, f:&mut fmt::Formatter<'_>) -> fmt::Result {
+		f.write_str( match self {
+			Self::Stretch => "Edge stretch",
+			Self::Mirror => "Mirror",
+			Self::Noise => "Noise",
+		} )
+	}
+}
+
+/// Settings of one outpaint
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Extend {
+	pub side : Side,
+
+	/// Pixels added. Rounded up so the canvas stays a multiple of 8
+	pub pixels : u32,
+	pub fill : Fill,
+
+	/// Pixels of the image inpainted along with the new strip, for the model to see
+	pub context : u32,
+
+	/// Pixels of the image the new strip fades into
+	pub overlap : u32,
+}
+
+/// One outpaint of the canvas history
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Step {
+	pub side : Side,
+	pub pixels : u32,
+	pub fill : Fill,
+	pub context : u32,
+	pub overlap : u32,
+	pub strength : f64,
+	pub prompt : String,
+	pub seed : u64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Canvas {
+	pub image : RgbImage,
+	pub steps : Vec<Step>,
+}
+
+impl Canvas {
+	pub fn new(image:RgbImage) -> Self {
+		Self { image, steps : vec![] }
+	}
+
+	/// Read a canvas saved by [`Canvas::save`]. Other PNGs open as a canvas without history
+	pub fn open(path:&Path) -> Result<Self, String> {
+		let bytes = std::fs::read(path).map_err( |e| format!("Can't read '{}' : {e}", path.display()) )?;
+		let image = RgbImage::from_png(&bytes).map_err( |e| format!("Can't decode '{}' : {e}", path.display()) )?;
+		let chunks = metadata::read_text_chunks(&bytes).unwrap_or_default();
+		let steps = match chunks.iter().find( |(k, _)| k == CANVAS_KEY ) {
+			Some((_, json)) => serde_json::from_str(json).map_err( |e| format!("Invalid canvas history in '{}' : {e}", path.display()) )?,
+			None => vec![],
+		};
+		Ok( Self { image, steps } )
+	}
+
+	pub fn save(&self, path:&Path) -> Result<(), String> {
+		let json = serde_json::to_string(&self.steps).unwrap_or_default();
+		let mut out = Vec::new();
+		self.image.write_png_with_text( &mut out, &[(CANVAS_KEY, &json)] ).map_err( |e| e.to_string() )?;
+		std::fs::write( path, out ).map_err( |e| format!("Can't write '{}' : {e}", path.display()) )
+	}
+
+	/// Inpainting request of `extend` and the canvas it finishes. `template` gives the prompt, seed
+	/// and sampling settings
+	pub fn outpaint(&self, extend:&Extend, template:&GenerationRequest, strength:f64) -> (GenerationRequest, Pending) {
+		let (width, height) = (self.image.width, self.image.height);
+		let seed = template.seed();
+		let (len, across) = if extend.side.is_horizontal() { (width, height) } else { (height, width) };
+		let new_len = round8( len + extend.pixels.max(1) );
+		let added = new_len - len;
+		// Uneven sizes get padded at the right or bottom, inpainted along with the strip
+		let new_across = round8(across);
+		let (new_width, new_height) = if extend.side.is_horizontal() { (new_len, new_across) } else { (new_across, new_len) };
+
+		let old = match extend.side {
+			Side::Left => Rect { x : added, y : 0, width, height },
+			Side::Up => Rect { x : 0, y : added, width, height },
+			Side::Right | Side::Down => Rect { x : 0, y : 0, width, height },
+		};
+		let image = prefill( &self.image, new_width, new_height, &old, extend.fill, seed );
+
+		let window_len = round8( added + extend.context.min(len) ).min(new_len);
+		let window = match extend.side {
+			Side::Left => Rect { x : 0, y : 0, width : window_len, height : new_height },
+			Side::Up => Rect { x : 0, y : 0, width : new_width, height : window_len },
+			Side::Right => Rect { x : new_width - window_len, y : 0, width : window_len, height : new_height },
+			Side::Down => Rect { x : 0, y : new_height - window_len, width : new_width, height : window_len },
+		};
+		let source = image.crop( window.x, window.y, window.width, window.height );
+		let mask = mask( &window, &old, new_width, new_height, extend.overlap );
+
+		let request = GenerationRequest {
+			mode : GenerationMode::Inpaint { source : Arc::new(source), mask : Arc::new(mask), strength },
+			width : window.width,
+			height : window.height,
+			tileable : false,
+			hires : None,
+			..template.with_seed(seed)
+		};
+		let mut steps = self.steps.clone();
+		steps.push( Step {
+			side : extend.side,
+			pixels : added,
+			fill : extend.fill,
+			context : extend.context,
+			overlap : extend.overlap,
+			strength,
+			prompt : template.prompt.clone(),
+			seed,
+		} );
+		(request, Pending { image, window, steps })
+	}
+}
+
+fn round8(v:u32) -> u32 {
+	(v + 7) / 8 * 8
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Rect {
+	x : u32,
+	y : u32,
+	width : u32,
+	height : u32,
+}
+
+impl Rect {
+	fn contains(&self, x:u32, y:u32) -> bool {
+		(self.x..self.x + self.width).contains(&x) && (self.y..self.y + self.height).contains(&y)
+	}
+}
+
+/// `image` placed at `old` on a `width` x `height` canvas, the rest filled with `fill`
+fn prefill(image:&RgbImage, width:u32, height:u32, old:&Rect, fill:Fill, seed:u64) -> RgbImage {
+	let mut rng = StdRng::seed_from_u64(seed);
+	let mut out = RgbImage::new(width, height);
+	out.paste( image, old.x, old.y );
+	for y in 0..height {
+		for x in 0..width {
+			if old.contains(x, y) {
+				continue
+			}
+			let (rx, ry) = (x as i64 - old.x as i64, y as i64 - old.y as i64);
+			let color = match fill {
+				Fill::Stretch => image.get( rx.clamp( 0, old.width as i64 - 1 ) as u32, ry.clamp( 0, old.height as i64 - 1 ) as u32 ),
+				Fill::Mirror => image.get( reflect( rx, old.width ), reflect( ry, old.height ) ),
+				Fill::Noise => rng.gen(),
+			};
+			out.put( x, y, color );
+		}
+	}
+	out
+}
+
+/// Index `i` of a row of `len` repeated back and forth
+fn reflect(i:i64, len:u32) -> u32 {
+	let len = len as i64;
+	let m = i.rem_euclid( 2 * len );
+	(if m < len { m } else { 2 * len - 1 - m }) as u32
+}
+
+/// Mask of `window` : the new area is repainted, the old image fades in over `overlap` pixels
+/// from the edges that face the new area
+fn mask(window:&Rect, old:&Rect, width:u32, height:u32, overlap:u32) -> Mask {
+	let mut mask = Mask::new( window.width, window.height );
+	for y in 0..window.height {
+		for x in 0..window.width {
+			let (cx, cy) = (window.x + x, window.y + y);
+			let value = if !old.contains(cx, cy) {
+				255
+			} else {
+				let distance = [
+					(old.x > 0).then( || cx - old.x ),
+					(old.x + old.width < width).then( || old.x + old.width - 1 - cx ),
+					(old.y > 0).then( || cy - old.y ),
+					(old.y + old.height < height).then( || old.y + old.height - 1 - cy ),
+				].into_iter().flatten().min().unwrap_or(u32::MAX);
+				match overlap {
+					0 => 0,
+					_ => (255. * (1. - (distance as f64 + 0.5) / overlap as f64)).clamp( 0., 255. ).round() as u8,
+				}
+			};
+			mask.data[ (y*window.width + x) as usize ] = value;
+		}
+	}
+	mask
+}
+
+/// Extended canvas waiting for its inpainted window
+#[derive(Debug, Clone)]
+pub struct Pending {
+	/// The canvas with the pre-filled new area
+	image : RgbImage,
+	window : Rect,
+	steps : Vec<Step>,
+}
+
+impl Pending {
+	/// The canvas with the window replaced by `output`, the image of the request
+	pub fn finish(self, output:&RgbImage) -> Canvas {
+		let mut image = self.image;
+		image.paste( output, self.window.x, self.window.y );
+		Canvas { image, steps : self.steps }
+	}
+}
+
+#[derive(Default)]
+struct State {
+	canvas : Option<Canvas>,
+
+	/// PNG of `canvas` for display
+	png : Arc<Vec<u8>>,
+	pending : Option<(JobId, Pending)>,
+}
+
+/// The open canvas and its outpaint in the queue, if any. Provided as a context by `app`,
+/// fed by the queue listener
+#[derive(Clone, Default)]
+pub struct OpenCanvas {
+	state : Arc<Mutex<State>>,
+}
+
+impl OpenCanvas {
+	pub fn get(&self) -> Option<Canvas> {
+		self.state.lock().unwrap().canvas.clone()
+	}
+
+	pub fn png(&self) -> Arc<Vec<u8>> {
+		self.state.lock().unwrap().png.clone()
+	}
+
+	/// Replace the open canvas. An outpaint still in the queue won't be applied to it
+	pub fn set(&self, canvas:Canvas) {
+		let mut state = self.state.lock().unwrap();
+		state.png = Arc::new( canvas.image.to_png().unwrap_or_default() );
+		state.canvas = Some(canvas);
+		state.pending = None;
+	}
+
+	pub fn is_pending(&self) -> bool {
+		self.state.lock().unwrap().pending.is_some()
+	}
+
+	/// Wait for job `id` to finish `pending`
+	pub fn start(&self, id:JobId, pending:Pending) {
+		self.state.lock().unwrap().pending = Some( (id, pending) );
+	}
+
+	/// Apply the first image of job `id` when it is the pending outpaint, `None` when it failed
+	pub fn finished(&self, id:JobId, image:Option<&GeneratedImage>) {
+		let mut state = self.state.lock().unwrap();
+		if state.pending.as_ref().map( |(job, _)| *job ) != Some(id) {
+			return
+		}
+		let (_, pending) = state.pending.take().unwrap();
+		if let Some(image) = image {
+			let canvas = pending.finish( &image.image );
+			state.png = Arc::new( canvas.image.to_png().unwrap_or_default() );
+			state.canvas = Some(canvas);
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/// Every pixel different, so misplaced pixels show
+	fn gradient(width:u32, height:u32) -> RgbImage {
+		let mut image = RgbImage::new(width, height);
+		for y in 0..height {
+			for x in 0..width {
+				image.put( x, y, [x as u8, y as u8, (x + y) as u8] );
+			}
+		}
+		image
+	}
+
+	fn template() -> GenerationRequest {
+		GenerationRequest { prompt : "a beach".into(), seeds : vec![7], ..GenerationRequest::default() }
+	}
+
+	fn extend(side:Side, fill:Fill) -> Extend {
+		Extend { side, pixels : 30, fill, context : 20, overlap : 8 }
+	}
+
+	#[test]
+	fn sides() {
+		// 100x60 grows to 104 across, 130 rounds up to 136 along
+		let canvas = Canvas::new( gradient(100, 60) );
+		let cases = [
+			(Side::Left, (136, 64), Rect { x : 36, y : 0, width : 100, height : 60 }, Rect { x : 0, y : 0, width : 56, height : 64 }),
+			(Side::Right, (136, 64), Rect { x : 0, y : 0, width : 100, height : 60 }, Rect { x : 80, y : 0, width : 56, height : 64 }),
+			(Side::Up, (104, 96), Rect { x : 0, y : 36, width : 100, height : 60 }, Rect { x : 0, y : 0, width : 104, height : 56 }),
+			(Side::Down, (104, 96), Rect { x : 0, y : 0, width : 100, height : 60 }, Rect { x : 0, y : 40, width : 104, height : 56 }),
+		];
+		for (side, (width, height), old, window) in cases {
+			let (request, pending) = canvas.outpaint( &extend(side, Fill::Stretch), &template(), 0.9 );
+			assert_eq!( (pending.image.width, pending.image.height), (width, height), "{side}" );
+			assert_eq!( pending.window, window, "{side}" );
+			assert_eq!( (request.width, request.height), (window.width, window.height), "{side}" );
+			assert_eq!( pending.image.crop( old.x, old.y, old.width, old.height ), canvas.image, "{side}" );
+			let GenerationMode::Inpaint { source, mask, strength } = &request.mode else { panic!("{side} isn't an inpaint") };
+			assert_eq!( **source, pending.image.crop( window.x, window.y, window.width, window.height ) );
+			assert_eq!( (mask.width, mask.height), (window.width, window.height) );
+			assert_eq!( *strength, 0.9 );
+			assert_eq!( request.seeds, vec![7] );
+			assert_eq!( pending.steps, vec![Step {
+				side,
+				pixels : 36,
+				fill : Fill::Stretch,
+				context : 20,
+				overlap : 8,
+				strength : 0.9,
+				prompt : "a beach".into(),
+				seed : 7,
+			}] );
+		}
+	}
+
+	#[test]
+	fn mask_fades_into_the_image() {
+		// 64x64 extended right by 32 : the window is x 32..96, the old image ends at 64
+		let canvas = Canvas::new( gradient(64, 64) );
+		let extend = Extend { side : Side::Right, pixels : 32, fill : Fill::Stretch, context : 32, overlap : 16 };
+		let (request, pending) = canvas.outpaint( &extend, &template(), 1. );
+		assert_eq!( pending.window, Rect { x : 32, y : 0, width : 64, height : 64 } );
+		let GenerationMode::Inpaint { mask, .. } = &request.mode else { panic!() };
+		for y in [0, 31, 63] {
+			for x in 32..64 {
+				assert_eq!( mask.get(x, y), 255 );
+			}
+			assert_eq!( mask.get(31, y), 247 );
+			assert_eq!( mask.get(16, y), 8 );
+			for x in 0..16 {
+				assert_eq!( mask.get(x, y), 0 );
+			}
+			for x in 16..32 {
+				assert!( mask.get(x, y) > mask.get(x - 1, y) );
+			}
+		}
+
+		let hard = Extend { overlap : 0, ..extend };
+		let (request, _) = canvas.outpaint( &hard, &template(), 1. );
+		let GenerationMode::Inpaint { mask, .. } = &request.mode else { panic!() };
+		assert!( (0..64).all( |y| (0..32).all( |x| mask.get(x, y) == 0 ) && (32..64).all( |x| mask.get(x, y) == 255 ) ) );
+	}
+
+	#[test]
+	fn reflect_edges() {
+		assert_eq!( (-6..10).map( |i| reflect(i, 4) ).collect::<Vec<_>>(), [2, 3, 3, 2, 1, 0, 0, 1, 2, 3, 3, 2, 1, 0, 0, 1] );
+		assert_eq!( (-3..3).map( |i| reflect(i, 1) ).collect::<Vec<_>>(), [0; 6] );
+	}
+
+	#[test]
+	fn fills() {
+		let image = gradient(16, 8);
+		let old = Rect { x : 0, y : 0, width : 16, height : 8 };
+		let stretched = prefill( &image, 24, 8, &old, Fill::Stretch, 0 );
+		let mirrored = prefill( &image, 24, 8, &old, Fill::Mirror, 0 );
+		for y in 0..8 {
+			for k in 0..8 {
+				assert_eq!( stretched.get(16 + k, y), image.get(15, y) );
+				assert_eq!( mirrored.get(16 + k, y), image.get(15 - k, y) );
+			}
+		}
+		assert_eq!( prefill( &image, 24, 8, &old, Fill::Noise, 3 ), prefill( &image, 24, 8, &old, Fill::Noise, 3 ) );
+		assert_ne!( prefill( &image, 24, 8, &old, Fill::Noise, 3 ), prefill( &image, 24, 8, &old, Fill::Noise, 4 ) );
+	}
+
+	#[test]
+	fn finish_pastes_the_window() {
+		let canvas = Canvas::new( gradient(64, 40) );
+		let (request, pending) = canvas.outpaint( &extend(Side::Left, Fill::Mirror), &template(), 1. );
+		let output = RgbImage::solid( request.width, request.height, [1, 2, 3] );
+		let finished = pending.clone().finish(&output);
+		assert_eq!( finished.steps, pending.steps );
+		assert_eq!( finished.image.crop( 0, 0, request.width, request.height ), output );
+		// Left of 64 + 30 rounds to 96, the window to 56
+		assert_eq!( finished.image.crop( 56, 0, 40, 40 ), canvas.image.crop( 24, 0, 40, 40 ) );
+	}
+
+	#[test]
+	fn save_and_open() {
+		let (_, pending) = Canvas::new( gradient(32, 32) ).outpaint( &extend(Side::Up, Fill::Noise), &template(), 0.75 );
+		let canvas = pending.finish( &RgbImage::solid(32, 56, [9, 9, 9]) );
+		let path = std::env::temp_dir().join( format!("sd-freya-canvas-{}.png", std::process::id()) );
+		canvas.save(&path).unwrap();
+		let opened = Canvas::open(&path);
+		std::fs::remove_file(&path).unwrap();
+		assert_eq!( opened.unwrap(), canvas );
+	}
+}
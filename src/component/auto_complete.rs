@@ -47,6 +47,9 @@ where
     let onclick = move |_: MouseEvent| {
         if state.read().is_focused() {
             *state.write() = AutoCompleteState::Selected( cx.props.item_idx );
+            if let Some(caller) = &cx.props.onclick {
+                caller.call( &cx.props.value );
+            }
         }
     };
 
@@ -79,8 +82,9 @@ pub struct AutoCompleteProps<'a, T: 'static> {
     #[props(optional)]
     onchange : Option< EventHandler<'a, String> >,
 
+    /// Called with the index of the item picked with `Enter`
     #[props(optional)]
-    onselected : Option< EventHandler<'a, String> >,
+    onselected : Option< EventHandler<'a, i32> >,
 
     children: Element<'a>,
 }
@@ -127,8 +131,6 @@ where
     let state = use_shared_state::<AutoCompleteState>(cx).unwrap();
     let opened = use_shared_state::<bool>(cx).unwrap();
 
-    println!("is_opened : {}" , *opened.read() && cx.props.children.is_some() );
-
     let color = theme.dropdown.font_theme.color;
 
     /// What's meaning?
@@ -145,7 +147,7 @@ where
     };
 
     let onkeyup = move |e:KeyboardEvent| {
-        let is_opened = *opened.read() && cx.props.children.is_some();
+        let is_opened = *opened.read() && cx.props.item_count > 0;
         let idx = state.read().idx();
         match e.key {
             // Close when `Escape` key is pressed
@@ -155,9 +157,12 @@ where
             }
             // Open the dropdown items when the `Enter` key is pressed
             Key::Enter if is_opened => {
-                if cx.props.item_count > 0 {
+                if cx.props.item_count > 0 && idx >= 0 {
                     *opened.write() = false;
                     *state.write() = AutoCompleteState::Selected( idx );
+                    if let Some(caller) = &cx.props.onselected {
+                        caller.call( idx );
+                    }
                 }
             }
             Key::ArrowUp => {
@@ -182,8 +187,6 @@ where
         }
     };
 
-	let input_text = use_state(cx, String::new);
-
     render!(
         rect {
             // width: "auto",
@@ -191,9 +194,8 @@ where
             margin: "5",
             Input {
                 max_lines: "1",
-                value: input_text.get().clone(),
+                value: cx.props.value.to_string(),
                 onchange: |e:String| {
-                    input_text.set( e.clone() );
                     if let Some(caller) = &cx.props.onchange {
                         caller.call( e );
                     }
@@ -209,7 +211,7 @@ where
                 width: "130",
                 height: "auto",
                 shadow: "0 0 20 0 rgb(0, 0, 0, 100)",
-                if *opened.read() {
+                if *opened.read() && cx.props.item_count > 0 {
                     &cx.props.children
                 }
             }    
@@ -217,34 +219,53 @@ where
     )
 }
 
+/// Word being typed at the end of `text`
+pub fn last_word(text:&str) -> &str {
+    let start = text.rfind( |c:char| c.is_whitespace() || "(),[]{}|:".contains(c) ).map_or( 0, |i| i + 1 );
+    &text[start..]
+}
+
+/// [`SimpleWordComplete`] component properties.
+#[derive(Props)]
+pub struct SimpleWordCompleteProps<'a> {
+    /// Input value
+    value: String,
+
+    /// Words that complete the [`last_word`] of `value`
+    hints: Vec<String>,
+
+    /// Called with the edited text, and with the text completed by a picked hint
+    onchange: EventHandler<'a, String>,
+}
+
 /// `SimpleWordComplete` component.
 ///
+/// Controlled single line input with a popup of `hints`. Picking one, with a click or the arrow
+/// keys and `Enter`, replaces the word being typed.
 ///
 /// # Example
 /// ```no_run
 /// # use freya::prelude::*;
-/// use component::auto_complete;
+/// use component::auto_complete::{self, last_word};
 ///
 /// fn app(cx: Scope) -> Element {
-///     let hints = ["alice", "bob", "carol"]
-///     let input_value = use_state(cx, || "input here...".to_string());
+///     const WORDS:[&str;3] = ["alice", "bob", "carol"];
+///     let text = use_state(cx, String::new);
+///     let hints = WORDS.iter()
+///         .filter( |w| !last_word(text).is_empty() && w.starts_with( last_word(text) ) )
+///         .map( |w| w.to_string() )
+///         .collect();
 ///     render!(
 ///         auto_complete::SimpleWordComplete {
-///             get_word_hints : |last| {
-///                 const hints:[&'static str;79] = [
-///                     "Alice", "Bob", "Car","Dog","Elephant","Fish","Giraffe","Horse","Ice cream","Jaguar","Kangaroo","Lion","Monkey","Nectarine","Octopus","Penguin","Queen","Rabbit","Snake","Tiger","Umbrella","Vase","Whale","Xylophone","Yak","Zebra",
-///                     "Aardvark","Bison","Cheetah","Dolphin","Elephant","Falcon","Gorilla","Hippopotamus","Ibis","Jaguar","Kangaroo","Lion","Moose","Nightingale","Ostrich","Penguin","Quokka","Raccoon","Squirrel","Tiger","Umbrella","Vulture","Walrus","X-ray tetra","Yak","Zebra",
-///                     "Alligator","Bear","Cat","Dog","Elephant","Fox","Giraffe","Horse","Iguana","Jaguar","Kangaroo","Lion","Monkey","Nightingale","Octopus","Penguin","Quokka","Rabbit","Snake","Tiger","Umbrella","Vulture","Whale","X-ray fish","Yak","Zoo",
-///                     "Alpine"];
-///                 hints.iter().filter( |h| h.len() != last.len() && h.starts_with(last) )
-///                     .map( |e| e.to_string() ).collect::<Vec<String>>()
-///             }
+///             value : text.get().clone(),
+///             hints : hints,
+///             onchange : move |e| text.set(e),
 ///         }
 ///     )
 /// }
 /// ```
-#[inline_props]
-pub fn SimpleWordComplete(cx: Scope, get_word_hints:fn(&str) -> Vec<String>) -> Element {
+#[allow(non_snake_case)]
+pub fn SimpleWordComplete<'a>(cx: Scope<'a, SimpleWordCompleteProps<'a>>) -> Element<'a> {
     // state
     use_shared_state_provider(cx, AutoCompleteState::default);
 
@@ -253,49 +274,40 @@ pub fn SimpleWordComplete(cx: Scope, get_word_hints:fn(&str) -> Vec<String>) ->
 
     let opened = use_shared_state::<bool>(cx).unwrap();
     let state = use_shared_state::<AutoCompleteState>(cx).unwrap();
-    let input_value = use_state(cx, String::new);
-    let auto_hints:&UseState<Vec<String>> = use_state(cx, Vec::new);
-
-    let onchange = |e:String| {
-        if input_value.get() != &e {
-            let last = &e[ e.rfind( char::is_whitespace ).map( |e| e+1 ).unwrap_or( e.len() ) .. ];
-            
-            let new_hints = 
-            if last.len() > 0 {
-                get_word_hints(last)
-            } else {
-                vec![]
-            };
-
-            if auto_hints.get() != &new_hints {
-                *state.write_silent() = AutoCompleteState::None;
-                if new_hints.len() > 0 {
-                    *opened.write() = true;
-                } else {
-                    *opened.write() = false;
-                }
-                auto_hints.set( new_hints );
-            }
-
-            input_value.set(e.clone() );
+    let value = &cx.props.value;
+    let hints = &cx.props.hints;
+
+    let onchange = move |e:String| {
+        if value != &e {
+            *state.write_silent() = AutoCompleteState::None;
+            *opened.write() = true;
+            cx.props.onchange.call( e );
         }
     };
 
-    println!("Painting sw");
+    let pick = move |idx:i32| {
+        if let Some(hint) = usize::try_from(idx).ok().and_then( |i| hints.get(i) ) {
+            let kept = &value[..value.len() - last_word(value).len()];
+            cx.props.onchange.call( format!("{kept}{hint}") );
+        }
+        *state.write() = AutoCompleteState::None;
+        *opened.write() = false;
+    };
 
     render!(
         AutoComplete {
-            value : input_value.get().clone(),
-            item_count : auto_hints.get().len() as _,
+            value : value.clone(),
+            item_count : hints.len() as _,
             onchange : onchange,
-            for (i,h) in auto_hints.get().iter().enumerate() {
+            onselected : pick,
+            for (i,h) in hints.iter().enumerate() {
                 AutoCompleteItem {
                     value : h.to_owned(),
                     item_idx : i as _,
-                    onclick : |_| {},
+                    onclick : move |_| pick( i as _ ),
                     label { "{h}" }
                 }
-            }        
+            }
         }
     )
 }
@@ -0,0 +1,131 @@
+use std::sync::Arc;
+
+use freya::prelude::{*, pointer::MouseButton};
+
+use winit::window::CursorIcon;
+
+use crate::generation::Mask;
+
+/// Identifies the current status of the MaskPainter.
+#[derive(Debug, Default, PartialEq, Clone, Copy)]
+pub enum PaintStatus {
+	/// Default state.
+	#[default]
+	Idle,
+	/// Left button is down over the canvas
+	Painting,
+}
+
+/// [`MaskPainter`] component properties.
+#[derive(Props)]
+pub struct MaskPainterProps<'a> {
+	/// Encoded image shown under the mask
+	pub source_png : Arc<Vec<u8>>,
+
+	pub mask : Mask,
+
+	/// Called with the edited mask
+	pub onchange : EventHandler<'a, Mask>,
+
+	/// Displayed width. The height follows the mask aspect ratio
+	#[props(optional)]
+	pub view_width : Option<f32>,
+}
+
+/// Controlled mask painting canvas.
+///
+/// Left drag paints (or erases) with a round brush. The toolbar has brush size, eraser,
+/// clear, invert and feathering.
+///
+/// # Props
+/// See [`MaskPainterProps`].
+#[allow(non_snake_case)]
+pub fn MaskPainter<'a>(cx:Scope<'a, MaskPainterProps<'a>>) -> Element<'a> {
+	let status = use_state(cx, PaintStatus::default);
+	let brush_size = use_state(cx, || 24f32);
+	let feather = use_state(cx, || 4f32);
+	let eraser = use_state(cx, || false);
+
+	let mask = &cx.props.mask;
+	let view_w = cx.props.view_width.unwrap_or(256.);
+	let scale = mask.width as f32 / view_w;
+	let view_h = mask.height as f32 / scale;
+
+	let source_data = bytes_to_data(cx, &cx.props.source_png);
+	let overlay_data = bytes_to_data(cx, &mask.to_overlay_png().unwrap_or_default());
+
+	let paint = move |e:&MouseEvent| {
+		let p = e.get_element_coordinates();
+		let mut m = cx.props.mask.clone();
+		let value = if *eraser.get() { 0 } else { 255 };
+		// The brush size is in displayed pixels, like the pointer
+		m.paint( p.x as f32 * scale, p.y as f32 * scale, *brush_size.get() / 2. * scale, value );
+		cx.props.onchange.call(m);
+	};
+
+	let onmousedown = move |e:MouseEvent| {
+		if let Some(MouseButton::Left) = e.data.get_trigger_button() {
+			status.set( PaintStatus::Painting );
+			paint(&e);
+		}
+	};
+
+	let onmouseover = move |e:MouseEvent| {
+		if let PaintStatus::Painting = status.get() {
+			paint(&e);
+		}
+	};
+
+	let onmouseup = move |_:MouseEvent| {
+		status.set( PaintStatus::Idle );
+	};
+
+	let edit = move |f:fn(&mut Mask)| {
+		let mut m = cx.props.mask.clone();
+		f(&mut m);
+		cx.props.onchange.call(m);
+	};
+
+	let brush_label = *brush_size.get() as u32;
+	let feather_label = *feather.get() as u32;
+	let eraser_label = if *eraser.get() { "Eraser" } else { "Brush" };
+
+	render!( rect {
+		direction : "vertical",
+		rect {
+			direction : "horizontal",
+			label { margin : "6", "Size {brush_label}" }
+			Slider {
+				width : 80.0,
+				value : *brush_size.get() as f64,
+				onmoved : |p| brush_size.set( (p as f32).max(1.) ),
+			}
+			Button { onclick : move |_| eraser.set( !*eraser.get() ), label { "{eraser_label}" } }
+			Button { onclick : move |_| edit( Mask::clear ), label { "Clear" } }
+			Button { onclick : move |_| edit( Mask::invert ), label { "Invert" } }
+			label { margin : "6", "Feather {feather_label}" }
+			Slider {
+				width : 60.0,
+				value : *feather.get() as f64 * 5.,
+				onmoved : |p| feather.set( p as f32 / 5. ),
+			}
+			Button { onclick : move |_| cx.props.onchange.call( cx.props.mask.feathered( *feather.get() as u32 ) ), label { "Feather" } }
+		}
+		rect {
+			width : "{view_w}",
+			height : "{view_h}",
+			onmousedown : onmousedown,
+			onmouseover : onmouseover,
+			onclick : onmouseup,
+			onmouseleave : onmouseup,
+			CursorArea {
+				icon : CursorIcon::Crosshair,
+				image { image_data : source_data, width : "{view_w}", height : "{view_h}" }
+				rect {
+					offset_y : "-{view_h}",
+					image { image_data : overlay_data, width : "{view_w}", height : "{view_h}" }
+				}
+			}
+		}
+	} )
+}
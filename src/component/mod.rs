@@ -5,6 +5,7 @@ pub mod square_grid;
 pub mod detail;
 pub mod float_right;
 pub mod separator;
+pub mod mask_painter;
 
 pub mod prelude {
 	pub use super::split::{Split,SplitProps, SplitDirection};
@@ -13,4 +14,5 @@ pub mod prelude {
 	pub use super::detail::{Detail, DetailProps};
 	pub use super::float_right::{FloatRight, FloatRightProps};
 	pub use super::separator::{HR,VR};
+	pub use super::mask_painter::{MaskPainter, MaskPainterProps};
 }
\ No newline at end of file
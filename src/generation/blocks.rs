@@ -0,0 +1,74 @@
+/// Layers shared by the UNet and the VAE, with the layout and weight names of `diffusers`.
+
+use tch::{nn, nn::Module, Tensor};
+
+use super::seamless::{conv2d, Conv2d, Padding};
+
+/// Group norm settings of a model
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Norm {
+	pub groups : i64,
+	pub eps : f64,
+}
+
+impl Norm {
+	pub fn build(&self, vs:nn::Path, channels:i64) -> nn::GroupNorm {
+		nn::group_norm( vs, self.groups, channels, nn::GroupNormConfig { eps : self.eps, ..Default::default() } )
+	}
+}
+
+/// Two 3x3 convolutions with a skip connection, and the time embedding added in between
+#[derive(Debug)]
+pub(crate) struct Resnet {
+	norm1 : nn::GroupNorm,
+	conv1 : Conv2d,
+	time_emb_proj : Option<nn::Linear>,
+	norm2 : nn::GroupNorm,
+	conv2 : Conv2d,
+
+	/// 1x1 convolution of the skip connection when the channel count changes
+	conv_shortcut : Option<Conv2d>,
+	output_scale : f64,
+}
+
+impl Resnet {
+	pub fn new(vs:nn::Path, in_channels:i64, out_channels:i64, time_channels:Option<i64>, norm:Norm, mode:&Padding) -> Self {
+		Self {
+			norm1 : norm.build( &vs / "norm1", in_channels ),
+			conv1 : conv2d( &vs / "conv1", in_channels, out_channels, 3, 1, 1, mode ),
+			time_emb_proj : time_channels.map( |t| nn::linear( &vs / "time_emb_proj", t, out_channels, Default::default() ) ),
+			norm2 : norm.build( &vs / "norm2", out_channels ),
+			conv2 : conv2d( &vs / "conv2", out_channels, out_channels, 3, 1, 1, mode ),
+			conv_shortcut : (in_channels != out_channels).then( || conv2d( &vs / "conv_shortcut", in_channels, out_channels, 1, 1, 0, mode ) ),
+			output_scale : 1.,
+		}
+	}
+
+	/// Divide the output by `scale`
+	pub fn with_output_scale(self, scale:f64) -> Self {
+		Self { output_scale : scale, ..self }
+	}
+
+	pub fn forward(&self, xs:&Tensor, time:Option<&Tensor>) -> Tensor {
+		let h = xs.apply(&self.norm1).silu().apply(&self.conv1);
+		let h = match (&self.time_emb_proj, time) {
+			(Some(proj), Some(time)) => h + time.silu().apply(proj).unsqueeze(-1).unsqueeze(-1),
+			_ => h,
+		};
+		let h = h.apply(&self.norm2).silu().apply(&self.conv2);
+		let skip = match &self.conv_shortcut {
+			Some(conv) => xs.apply(conv),
+			None => xs.shallow_clone(),
+		};
+		(skip + h) / self.output_scale
+	}
+}
+
+/// Nearest neighbour upscaling to `size`, twice the size by default, then a 3x3 convolution
+pub(crate) fn upsample(xs:&Tensor, conv:&Conv2d, size:Option<(i64, i64)>) -> Tensor {
+	let (h, w) = size.unwrap_or_else( || {
+		let s = xs.size();
+		(s[2] * 2, s[3] * 2)
+	} );
+	conv.forward( &xs.upsample_nearest2d( &[h, w], None, None ) )
+}
@@ -0,0 +1,209 @@
+/// Single file checkpoints in the original Stable Diffusion layout.
+///
+/// The pipeline is built with the `diffusers` weight names, so each of its variables is looked up
+/// under the name the checkpoint uses for it, the same mapping as the `diffusers` conversion
+/// script. Only safetensors checkpoints can be read, `.ckpt` files are pickles.
+
+use std::collections::HashMap;
+use std::path::Path;
+
+use anyhow::Context;
+use tch::{nn, Tensor};
+
+const UNET_PREFIX:&str = "model.diffusion_model.";
+const VAE_PREFIX:&str = "first_stage_model.";
+
+/// SD 1.x text encoder, already in the `transformers` layout
+const CLIP_PREFIX:&str = "cond_stage_model.transformer.";
+
+/// SD 2.x OpenCLIP text encoder
+const OPEN_CLIP_PREFIX:&str = "cond_stage_model.model.";
+
+/// Resnets per UNet down block, the same for SD 1.5 and 2.1. Up blocks have one more
+const UNET_LAYERS:usize = 2;
+
+/// Resolutions of the VAE, numbered the other way round in the decoder of checkpoints
+const VAE_BLOCKS:usize = 4;
+
+pub(crate) struct Checkpoint {
+	tensors : HashMap<String, Tensor>,
+}
+
+impl Checkpoint {
+	pub fn read(path:&Path) -> anyhow::Result<Self> {
+		let tensors = Tensor::read_safetensors(path).with_context( || format!("can't read checkpoint {path:?}") )?;
+		Ok( Self { tensors : tensors.into_iter().collect() } )
+	}
+
+	fn get(&self, prefix:&str, name:&str) -> Option<Tensor> {
+		self.tensors.get( &format!("{prefix}{name}") ).map( Tensor::shallow_clone )
+	}
+
+	pub fn load_unet(&self, vs:&nn::VarStore) -> anyhow::Result<()> {
+		copy_into( vs, |name| unet_names(name).iter().find_map( |n| self.get( UNET_PREFIX, n ) ) )
+	}
+
+	pub fn load_vae(&self, vs:&nn::VarStore) -> anyhow::Result<()> {
+		copy_into( vs, |name| self.get( VAE_PREFIX, &vae_name(name) ) )
+	}
+
+	pub fn load_clip(&self, vs:&nn::VarStore) -> anyhow::Result<()> {
+		match self.tensors.keys().any( |k| k.starts_with(OPEN_CLIP_PREFIX) ) {
+			true => copy_into( vs, |name| self.open_clip(name) ),
+			false => copy_into( vs, |name| self.get( CLIP_PREFIX, name ) ),
+		}
+	}
+
+	/// OpenCLIP tensor of text encoder variable `name`. Query, key and value are one tensor there
+	fn open_clip(&self, name:&str) -> Option<Tensor> {
+		let get = |name:&str| self.get( OPEN_CLIP_PREFIX, name );
+		let name = name.strip_prefix("text_model.")?;
+		match name {
+			"embeddings.token_embedding.weight" => return get("token_embedding.weight"),
+			"embeddings.position_embedding.weight" => return get("positional_embedding"),
+			_ => {}
+		}
+		if let Some(param) = name.strip_prefix("final_layer_norm.") {
+			return get( &format!("ln_final.{param}") )
+		}
+		let (layer, rest) = name.strip_prefix("encoder.layers.")?.split_once('.')?;
+		let (module, param) = rest.rsplit_once('.')?;
+		let block = format!("transformer.resblocks.{layer}");
+		if let Some(i) = ["self_attn.q_proj", "self_attn.k_proj", "self_attn.v_proj"].iter().position( |&m| m == module ) {
+			return get( &format!("{block}.attn.in_proj_{param}") ).map( |t| t.chunk( 3, 0 ).swap_remove(i) )
+		}
+		let module = match module {
+			"layer_norm1" => "ln_1",
+			"layer_norm2" => "ln_2",
+			"mlp.fc1" => "mlp.c_fc",
+			"mlp.fc2" => "mlp.c_proj",
+			"self_attn.out_proj" => "attn.out_proj",
+			_ => return None,
+		};
+		get( &format!("{block}.{module}.{param}") )
+	}
+}
+
+/// Copy the tensor `get` finds for each variable of `vs` into it
+fn copy_into(vs:&nn::VarStore, get:impl Fn(&str) -> Option<Tensor>) -> anyhow::Result<()> {
+	let _guard = tch::no_grad_guard();
+	for (name, mut var) in vs.variables() {
+		let source = get(&name).with_context( || format!("no weights for {name} in the checkpoint") )?;
+		// VAE attention projections are 1x1 convolutions in checkpoints and linear layers here
+		var.f_copy_( &source.reshape( &var.size() ).to_kind( var.kind() ) )?;
+	}
+	Ok(())
+}
+
+/// Parts of a dotted name from `from` on, joined again
+fn join(parts:&[&str], from:usize) -> String {
+	parts[from..].join(".")
+}
+
+fn index(s:&str) -> usize {
+	s.parse().unwrap_or_default()
+}
+
+/// Checkpoint name of a resnet variable of the UNet
+fn unet_resnet(rest:&str) -> String {
+	let (module, param) = rest.split_once('.').unwrap_or( (rest, "") );
+	let module = match module {
+		"norm1" => "in_layers.0",
+		"conv1" => "in_layers.2",
+		"time_emb_proj" => "emb_layers.1",
+		"norm2" => "out_layers.0",
+		"conv2" => "out_layers.3",
+		"conv_shortcut" => "skip_connection",
+		m => m,
+	};
+	format!("{module}.{param}")
+}
+
+/// Checkpoint names UNet variable `name` may have. The upsampler follows the attention in blocks
+/// that have one, so it has two candidates
+fn unet_names(name:&str) -> Vec<String> {
+	let p = name.split('.').collect::<Vec<_>>();
+	let down = |b:&str, l:usize| 1 + index(b) * (UNET_LAYERS + 1) + l;
+	let up = |b:&str, l:usize| index(b) * (UNET_LAYERS + 1) + l;
+	let name = match p.as_slice() {
+		["time_embedding", "linear_1", ..] => format!("time_embed.0.{}", join(&p, 2)),
+		["time_embedding", "linear_2", ..] => format!("time_embed.2.{}", join(&p, 2)),
+		["conv_in", ..] => format!("input_blocks.0.0.{}", join(&p, 1)),
+		["conv_norm_out", ..] => format!("out.0.{}", join(&p, 1)),
+		["conv_out", ..] => format!("out.2.{}", join(&p, 1)),
+		["down_blocks", b, "resnets", l, ..] => format!("input_blocks.{}.0.{}", down( b, index(l) ), unet_resnet( &join(&p, 4) )),
+		["down_blocks", b, "attentions", l, ..] => format!("input_blocks.{}.1.{}", down( b, index(l) ), join(&p, 4)),
+		["down_blocks", b, "downsamplers", "0", "conv", ..] => format!("input_blocks.{}.0.op.{}", down( b, UNET_LAYERS ), join(&p, 5)),
+		["mid_block", "resnets", "0", ..] => format!("middle_block.0.{}", unet_resnet( &join(&p, 3) )),
+		["mid_block", "attentions", "0", ..] => format!("middle_block.1.{}", join(&p, 3)),
+		["mid_block", "resnets", "1", ..] => format!("middle_block.2.{}", unet_resnet( &join(&p, 3) )),
+		["up_blocks", b, "resnets", l, ..] => format!("output_blocks.{}.0.{}", up( b, index(l) ), unet_resnet( &join(&p, 4) )),
+		["up_blocks", b, "attentions", l, ..] => format!("output_blocks.{}.1.{}", up( b, index(l) ), join(&p, 4)),
+		["up_blocks", b, "upsamplers", "0", "conv", ..] => {
+			let i = up( b, UNET_LAYERS );
+			return vec![ format!("output_blocks.{i}.1.conv.{}", join(&p, 5)), format!("output_blocks.{i}.2.conv.{}", join(&p, 5)) ]
+		}
+		_ => return vec![],
+	};
+	vec![name]
+}
+
+/// Checkpoint name of VAE variable `name`
+fn vae_name(name:&str) -> String {
+	let p = name.split('.').collect::<Vec<_>>();
+	let resnet = |rest:String| rest.replacen( "conv_shortcut", "nin_shortcut", 1 );
+	let reversed = |b:&str| VAE_BLOCKS - 1 - index(b);
+	match p.as_slice() {
+		[coder, "conv_norm_out", ..] => format!("{coder}.norm_out.{}", join(&p, 2)),
+		[coder, "mid_block", "resnets", l, ..] => format!("{coder}.mid.block_{}.{}", index(l) + 1, resnet( join(&p, 4) )),
+		[coder, "mid_block", "attentions", "0", module, ..] => {
+			let module = match *module {
+				"group_norm" => "norm",
+				"query" => "q",
+				"key" => "k",
+				"value" => "v",
+				"proj_attn" => "proj_out",
+				m => m,
+			};
+			format!("{coder}.mid.attn_1.{module}.{}", join(&p, 5))
+		}
+		["encoder", "down_blocks", b, "resnets", l, ..] => format!("encoder.down.{b}.block.{l}.{}", resnet( join(&p, 5) )),
+		["encoder", "down_blocks", b, "downsamplers", "0", "conv", ..] => format!("encoder.down.{b}.downsample.conv.{}", join(&p, 6)),
+		["decoder", "up_blocks", b, "resnets", l, ..] => format!("decoder.up.{}.block.{l}.{}", reversed(b), resnet( join(&p, 5) )),
+		["decoder", "up_blocks", b, "upsamplers", "0", "conv", ..] => format!("decoder.up.{}.upsample.conv.{}", reversed(b), join(&p, 6)),
+		_ => name.to_string(),
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn unet() {
+		assert_eq!( unet_names("conv_in.weight"), ["input_blocks.0.0.weight"] );
+		assert_eq!( unet_names("time_embedding.linear_2.bias"), ["time_embed.2.bias"] );
+		assert_eq!( unet_names("down_blocks.0.resnets.1.conv1.weight"), ["input_blocks.2.0.in_layers.2.weight"] );
+		assert_eq!( unet_names("down_blocks.1.attentions.0.proj_in.weight"), ["input_blocks.4.1.proj_in.weight"] );
+		assert_eq!( unet_names("down_blocks.2.downsamplers.0.conv.bias"), ["input_blocks.9.0.op.bias"] );
+		assert_eq!( unet_names("down_blocks.3.resnets.0.conv_shortcut.weight"), ["input_blocks.10.0.skip_connection.weight"] );
+		assert_eq!( unet_names("mid_block.resnets.1.time_emb_proj.weight"), ["middle_block.2.emb_layers.1.weight"] );
+		assert_eq!( unet_names("up_blocks.1.resnets.2.norm2.weight"), ["output_blocks.5.0.out_layers.0.weight"] );
+		assert_eq!( unet_names("up_blocks.3.attentions.2.transformer_blocks.0.attn1.to_q.weight"), ["output_blocks.11.1.transformer_blocks.0.attn1.to_q.weight"] );
+		assert_eq!( unet_names("up_blocks.0.upsamplers.0.conv.weight"), ["output_blocks.2.1.conv.weight", "output_blocks.2.2.conv.weight"] );
+		assert_eq!( unet_names("conv_norm_out.bias"), ["out.0.bias"] );
+	}
+
+	#[test]
+	fn vae() {
+		assert_eq!( vae_name("encoder.conv_in.weight"), "encoder.conv_in.weight" );
+		assert_eq!( vae_name("encoder.down_blocks.1.resnets.0.conv_shortcut.weight"), "encoder.down.1.block.0.nin_shortcut.weight" );
+		assert_eq!( vae_name("encoder.down_blocks.2.downsamplers.0.conv.weight"), "encoder.down.2.downsample.conv.weight" );
+		assert_eq!( vae_name("decoder.up_blocks.0.resnets.2.norm1.bias"), "decoder.up.3.block.2.norm1.bias" );
+		assert_eq!( vae_name("decoder.up_blocks.2.upsamplers.0.conv.weight"), "decoder.up.1.upsample.conv.weight" );
+		assert_eq!( vae_name("decoder.mid_block.resnets.1.conv2.weight"), "decoder.mid.block_2.conv2.weight" );
+		assert_eq!( vae_name("encoder.mid_block.attentions.0.proj_attn.bias"), "encoder.mid.attn_1.proj_out.bias" );
+		assert_eq!( vae_name("decoder.conv_norm_out.weight"), "decoder.norm_out.weight" );
+		assert_eq!( vae_name("post_quant_conv.weight"), "post_quant_conv.weight" );
+	}
+}
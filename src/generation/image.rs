@@ -0,0 +1,100 @@
+use std::io::Write;
+
+/// 8 bit RGB image, row major and tightly packed
+#[derive(Debug, Clone, PartialEq)]
+pub struct RgbImage {
+	pub width : u32,
+	pub height : u32,
+	pub pixels : Vec<u8>,
+}
+
+impl RgbImage {
+	pub fn new(width:u32, height:u32) -> Self {
+		Self { width, height, pixels : vec![0; (width*height*3) as usize] }
+	}
+
+	pub fn solid(width:u32, height:u32, color:[u8;3]) -> Self {
+		let pixels = color.iter().copied().cycle().take( (width*height*3) as usize ).collect();
+		Self { width, height, pixels }
+	}
+
+	pub fn from_raw(width:u32, height:u32, pixels:Vec<u8>) -> Option<Self> {
+		( pixels.len() == (width*height*3) as usize ).then_some( Self { width, height, pixels } )
+	}
+
+	pub fn get(&self, x:u32, y:u32) -> [u8;3] {
+		let i = ((y*self.width + x)*3) as usize;
+		[self.pixels[i], self.pixels[i+1], self.pixels[i+2]]
+	}
+
+	pub fn put(&mut self, x:u32, y:u32, color:[u8;3]) {
+		let i = ((y*self.width + x)*3) as usize;
+		self.pixels[i..i+3].copy_from_slice( &color );
+	}
+
+	/// `width` x `height` part with its top left corner at `x`, `y`. Must be inside the image
+	pub fn crop(&self, x:u32, y:u32, width:u32, height:u32) -> Self {
+		let mut pixels = Vec::with_capacity( (width*height*3) as usize );
+		for row in y..y+height {
+			let i = ((row*self.width + x)*3) as usize;
+			pixels.extend_from_slice( &self.pixels[i..i + (width*3) as usize] );
+		}
+		Self { width, height, pixels }
+	}
+
+	/// Copy `image` over this one with its top left corner at `x`, `y`. Clipped to this image
+	pub fn paste(&mut self, image:&RgbImage, x:u32, y:u32) {
+		let width = image.width.min( self.width.saturating_sub(x) );
+		for row in 0..image.height.min( self.height.saturating_sub(y) ) {
+			let from = (row*image.width*3) as usize;
+			let to = (((y + row)*self.width + x)*3) as usize;
+			self.pixels[to..to + (width*3) as usize].copy_from_slice( &image.pixels[from..from + (width*3) as usize] );
+		}
+	}
+
+	/// Encode as PNG
+	pub fn to_png(&self) -> Result<Vec<u8>, png::EncodingError> {
+		let mut out = Vec::new();
+		self.write_png(&mut out)?;
+		Ok(out)
+	}
+
+	pub fn write_png<W:Write>(&self, w:W) -> Result<(), png::EncodingError> {
+		self.write_png_with_text( w, &[] )
+	}
+
+	/// Encode as PNG with `(keyword, text)` chunks. Latin-1 text goes to tEXt, anything else to iTXt
+	pub fn write_png_with_text<W:Write>(&self, w:W, text:&[(&str, &str)]) -> Result<(), png::EncodingError> {
+		let mut encoder = png::Encoder::new(w, self.width, self.height);
+		encoder.set_color( png::ColorType::Rgb );
+		encoder.set_depth( png::BitDepth::Eight );
+		for (keyword, text) in text {
+			if text.chars().all( |c| (c as u32) < 256 ) {
+				encoder.add_text_chunk( keyword.to_string(), text.to_string() )?;
+			} else {
+				encoder.add_itxt_chunk( keyword.to_string(), text.to_string() )?;
+			}
+		}
+		let mut writer = encoder.write_header()?;
+		writer.write_image_data( &self.pixels )
+	}
+
+	/// Decode a PNG. Alpha is dropped and grayscale is expanded to RGB
+	pub fn from_png(bytes:&[u8]) -> Result<Self, png::DecodingError> {
+		let mut decoder = png::Decoder::new(bytes);
+		decoder.set_transformations( png::Transformations::normalize_to_color8() );
+		let mut reader = decoder.read_info()?;
+		let mut buf = vec![0; reader.output_buffer_size()];
+		let info = reader.next_frame(&mut buf)?;
+		buf.truncate( info.buffer_size() );
+
+		let pixels = match info.color_type {
+			png::ColorType::Rgb => buf,
+			png::ColorType::Rgba => buf.chunks_exact(4).flat_map( |p| [p[0], p[1], p[2]] ).collect(),
+			png::ColorType::Grayscale => buf.iter().flat_map( |&g| [g, g, g] ).collect(),
+			png::ColorType::GrayscaleAlpha => buf.chunks_exact(2).flat_map( |p| [p[0], p[0], p[0]] ).collect(),
+			png::ColorType::Indexed => unreachable!("expanded by normalize_to_color8"),
+		};
+		Ok( Self { width : info.width, height : info.height, pixels } )
+	}
+}
@@ -0,0 +1,201 @@
+/// LoRA merging.
+///
+/// The low rank deltas `up · down · alpha / rank` of each LoRA are added to the UNet and text
+/// encoder weights in place, scaled by the tag weight. Touched weights are backed up the first
+/// time, so switching to another set of LoRAs restores them before merging the new ones. A job
+/// with the same LoRAs as the previous one reuses the merged weights as they are.
+///
+/// Both the kohya layout (`lora_unet_down_blocks_0_..._to_q.lora_down.weight`) and the diffusers
+/// / PEFT layout (`unet.down_blocks.0....to_q.lora_A.weight`) are understood.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use tch::{nn, Tensor};
+
+use crate::models::file_hash;
+
+/// Suffixes of the down, up and alpha tensors of a module, per layout
+const LAYOUTS:[(&str, &str);3] = [
+	(".lora_down.weight", ".lora_up.weight"),
+	(".lora_A.weight", ".lora_B.weight"),
+	(".lora.down.weight", ".lora.up.weight"),
+];
+const ALPHA:&str = ".alpha";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Part {
+	Unet,
+	Text,
+}
+
+/// A LoRA file merged at some strength
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Applied {
+	pub name : String,
+	pub path : PathBuf,
+	pub weight : f64,
+	pub hash : String,
+}
+
+/// `dir/name.safetensors`, or a file of that name in a sub directory
+pub(crate) fn find(dir:&Path, name:&str) -> anyhow::Result<PathBuf> {
+	fn walk(dir:&Path, file:&str) -> Option<PathBuf> {
+		let direct = dir.join(file);
+		if direct.is_file() {
+			return Some(direct)
+		}
+		std::fs::read_dir(dir).ok()?.flatten()
+			.map( |e| e.path() )
+			.filter( |p| p.is_dir() )
+			.find_map( |p| walk(&p, file) )
+	}
+	walk( dir, &format!("{name}.safetensors") ).with_context( || format!("LoRA '{name}' not found in {dir:?}") )
+}
+
+/// Merged LoRAs of a pipeline and the weights they replaced
+#[derive(Default)]
+pub(crate) struct LoraState {
+	applied : Vec<Applied>,
+	backups : HashMap<(Part, String), Tensor>,
+}
+
+impl LoraState {
+	pub fn applied(&self) -> &[Applied] {
+		&self.applied
+	}
+
+	/// Make `wanted`, as `(name, path, weight)`, the merged LoRAs
+	pub fn apply(&mut self, wanted:&[(String, PathBuf, f64)], unet:&nn::VarStore, text:&nn::VarStore) -> anyhow::Result<()> {
+		let same = wanted.len() == self.applied.len()
+			&& wanted.iter().zip(&self.applied).all( |((_, path, weight), a)| *path == a.path && *weight == a.weight );
+		if same {
+			return Ok(())
+		}
+		self.restore(unet, text);
+
+		let mut targets = targets(unet, Part::Unet, "lora_unet_", "unet.");
+		targets.extend( targets(text, Part::Text, "lora_te_", "text_encoder.") );
+		for (name, path, weight) in wanted {
+			// Hashed first, so no error can leave a merged LoRA that isn't listed as applied
+			let merged = file_hash(path).and_then( |hash| {
+				self.merge( path, *weight, &targets, unet, text )?;
+				Ok(hash)
+			} );
+			match merged {
+				Ok(hash) => self.applied.push( Applied { name : name.clone(), path : path.clone(), weight : *weight, hash } ),
+				Err(e) => {
+					self.restore(unet, text);
+					return Err( e.context( format!("can't merge LoRA '{name}'") ) )
+				}
+			}
+		}
+		Ok(())
+	}
+
+	/// Put the backed up weights back
+	pub fn restore(&mut self, unet:&nn::VarStore, text:&nn::VarStore) {
+		let (unet, text) = (unet.variables(), text.variables());
+		for ((part, name), original) in self.backups.drain() {
+			let vars = match part { Part::Unet => &unet, Part::Text => &text };
+			if let Some(mut var) = vars.get(&name).map( Tensor::shallow_clone ) {
+				var.copy_(&original);
+			}
+		}
+		self.applied.clear();
+	}
+
+	fn merge(&mut self, path:&Path, weight:f64, targets:&HashMap<String, (Part, String)>, unet:&nn::VarStore, text:&nn::VarStore) -> anyhow::Result<()> {
+		let tensors = Tensor::read_safetensors(path)?.into_iter().collect::<HashMap<_, _>>();
+		let (unet, text) = (unet.variables(), text.variables());
+		let mut merged = 0;
+		for (key, down) in &tensors {
+			let Some((module, up_suffix)) = LAYOUTS.iter().find_map( |(d, u)| key.strip_suffix(d).map( |m| (m, u) ) ) else { continue };
+			let up = tensors.get( &format!("{module}{up_suffix}") ).with_context( || format!("no up weight for {module}") )?;
+			let Some((part, name)) = targets.get(module) else { continue };
+			let vars = match part { Part::Unet => &unet, Part::Text => &text };
+			let mut var = vars.get(name).map( Tensor::shallow_clone ).with_context( || format!("no weight {name}") )?;
+
+			let rank = down.size()[0];
+			let alpha = tensors.get( &format!("{module}{ALPHA}") ).map_or( rank as f64, |a| a.double_value(&[]) );
+			let delta = up.flatten(1, -1).to_kind( var.kind() ).matmul( &down.flatten(1, -1).to_kind( var.kind() ) );
+			anyhow::ensure!( delta.numel() == var.numel(), "shape of {module} doesn't match {name}" );
+			let delta = delta.reshape( &var.size() ).to_device( var.device() ) * (weight * alpha / rank as f64);
+
+			self.backups.entry( (*part, name.clone()) ).or_insert_with( || var.copy() );
+			var.f_add_(&delta)?;
+			merged += 1;
+		}
+		anyhow::ensure!( merged > 0, "no layer of the file matches this model" );
+		Ok(())
+	}
+}
+
+/// LoRA module keys to the weight they modify, in both the kohya and the dotted layout
+fn targets(vs:&nn::VarStore, part:Part, kohya_prefix:&str, dotted_prefix:&str) -> HashMap<String, (Part, String)> {
+	let mut out = HashMap::new();
+	for name in vs.variables().into_keys() {
+		let Some(module) = name.strip_suffix(".weight") else { continue };
+		out.insert( format!("{kohya_prefix}{}", module.replace('.', "_")), (part, name.clone()) );
+		out.insert( format!("{dotted_prefix}{module}"), (part, name.clone()) );
+	}
+	out
+}
+
+#[cfg(test)]
+mod tests {
+	use tch::{Device, Kind};
+
+	use super::*;
+
+	#[test]
+	fn targets_in_both_layouts() {
+		let vs = nn::VarStore::new(Device::Cpu);
+		let _ = nn::linear( vs.root() / "down_blocks" / 0 / "attentions" / 1 / "transformer_blocks" / 0 / "attn1" / "to_q", 4, 4, Default::default() );
+		let targets = targets( &vs, Part::Unet, "lora_unet_", "unet." );
+		let name = "down_blocks.0.attentions.1.transformer_blocks.0.attn1.to_q.weight".to_string();
+		assert_eq!( targets.get("lora_unet_down_blocks_0_attentions_1_transformer_blocks_0_attn1_to_q"), Some( &(Part::Unet, name.clone()) ) );
+		assert_eq!( targets.get("unet.down_blocks.0.attentions.1.transformer_blocks.0.attn1.to_q"), Some( &(Part::Unet, name) ) );
+		// Biases aren't LoRA targets
+		assert_eq!( targets.len(), 2 );
+
+		let vs = nn::VarStore::new(Device::Cpu);
+		let _ = nn::linear( vs.root() / "text_model" / "encoder" / "layers" / 11 / "self_attn" / "q_proj", 4, 4, Default::default() );
+		let targets = targets( &vs, Part::Text, "lora_te_", "text_encoder." );
+		let name = "text_model.encoder.layers.11.self_attn.q_proj.weight".to_string();
+		assert_eq!( targets.get("lora_te_text_model_encoder_layers_11_self_attn_q_proj"), Some( &(Part::Text, name.clone()) ) );
+		assert_eq!( targets.get("text_encoder.text_model.encoder.layers.11.self_attn.q_proj"), Some( &(Part::Text, name) ) );
+	}
+
+	#[test]
+	fn merge_and_restore() {
+		let _guard = tch::no_grad_guard();
+		let unet = nn::VarStore::new(Device::Cpu);
+		let text = nn::VarStore::new(Device::Cpu);
+		let _ = nn::linear( unet.root() / "mid_block" / "attentions" / 0 / "proj_in", 4, 3, Default::default() );
+		let weight = || unet.variables()["mid_block.attentions.0.proj_in.weight"].copy();
+		let original = weight();
+
+		let (down, up) = (Tensor::randn( &[2, 4], (Kind::Float, Device::Cpu) ), Tensor::randn( &[3, 2], (Kind::Float, Device::Cpu) ));
+		let path = std::env::temp_dir().join( format!("sd-freya-lora-{}.safetensors", std::process::id()) );
+		Tensor::write_safetensors( &[
+			("lora_unet_mid_block_attentions_0_proj_in.lora_down.weight", &down),
+			("lora_unet_mid_block_attentions_0_proj_in.lora_up.weight", &up),
+			("lora_unet_mid_block_attentions_0_proj_in.alpha", &Tensor::from(1f32)),
+		], &path ).unwrap();
+
+		let mut state = LoraState::default();
+		let applied = state.apply( &[("test".into(), path.clone(), 0.5)], &unet, &text );
+		std::fs::remove_file(&path).unwrap();
+		applied.unwrap();
+		assert_eq!( state.applied().len(), 1 );
+		// weight · alpha / rank = 0.5 · 1 / 2
+		let expected = &original + up.matmul(&down) * 0.25;
+		assert!( (weight() - expected).abs().max().double_value(&[]) < 1e-6 );
+
+		state.restore( &unet, &text );
+		assert!( state.applied().is_empty() );
+		assert_eq!( weight(), original );
+	}
+}
@@ -0,0 +1,103 @@
+use super::RgbImage;
+
+/// 8 bit single channel mask. 255 means "repaint this pixel"
+#[derive(Debug, Clone, PartialEq)]
+pub struct Mask {
+	pub width : u32,
+	pub height : u32,
+	pub data : Vec<u8>,
+}
+
+impl Mask {
+	pub fn new(width:u32, height:u32) -> Self {
+		Self { width, height, data : vec![0; (width*height) as usize] }
+	}
+
+	pub fn get(&self, x:u32, y:u32) -> u8 {
+		self.data[ (y*self.width + x) as usize ]
+	}
+
+	/// Nearest neighbour sample as if the mask was `width` x `height`, in `[0, 1]`
+	pub fn sample(&self, x:u32, y:u32, width:u32, height:u32) -> f64 {
+		self.get( x * self.width / width, y * self.height / height ) as f64 / 255.
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.data.iter().all( |&v| v == 0 )
+	}
+
+	/// Fill a circle with `value`
+	pub fn paint(&mut self, cx:f32, cy:f32, radius:f32, value:u8) {
+		let (x0, x1) = ( (cx - radius).floor().max(0.) as u32, ((cx + radius).ceil() as u32).min(self.width) );
+		let (y0, y1) = ( (cy - radius).floor().max(0.) as u32, ((cy + radius).ceil() as u32).min(self.height) );
+		for y in y0..y1 {
+			for x in x0..x1 {
+				let (dx, dy) = (x as f32 + 0.5 - cx, y as f32 + 0.5 - cy);
+				if dx*dx + dy*dy <= radius*radius {
+					self.data[ (y*self.width + x) as usize ] = value;
+				}
+			}
+		}
+	}
+
+	pub fn clear(&mut self) {
+		self.data.fill(0);
+	}
+
+	pub fn invert(&mut self) {
+		self.data.iter_mut().for_each( |v| *v = 255 - *v );
+	}
+
+	/// Soften the edges with a box blur of `radius` pixels, applied twice per axis
+	pub fn feathered(&self, radius:u32) -> Self {
+		if radius == 0 {
+			return self.clone()
+		}
+		let (w, h) = (self.width as usize, self.height as usize);
+		let r = radius as isize;
+		let blur = |src:&[u8], len:usize, stride:usize, count:usize, step:usize| {
+			let mut out = vec![0u8; src.len()];
+			for line in 0..count {
+				let base = line * step;
+				for i in 0..len as isize {
+					let (from, to) = ( (i - r).max(0), (i + r).min(len as isize - 1) );
+					let sum:u32 = (from..=to).map( |j| src[ base + j as usize * stride ] as u32 ).sum();
+					out[ base + i as usize * stride ] = ( sum / (to - from + 1) as u32 ) as u8;
+				}
+			}
+			out
+		};
+		let mut data = self.data.clone();
+		for _ in 0..2 {
+			data = blur( &data, w, 1, h, w );
+			data = blur( &data, h, w, w, 1 );
+		}
+		Self { width : self.width, height : self.height, data }
+	}
+
+	/// `over` where the mask is set, `base` elsewhere. `base` is sampled nearest neighbour to fit `over`
+	pub fn composite(&self, base:&RgbImage, over:&RgbImage) -> RgbImage {
+		let mut out = over.clone();
+		for y in 0..over.height {
+			for x in 0..over.width {
+				let k = self.sample( x, y, over.width, over.height );
+				let b = base.get( x * base.width / over.width, y * base.height / over.height );
+				let o = over.get(x, y);
+				let mix = |i:usize| ( b[i] as f64 + (o[i] as f64 - b[i] as f64) * k ).round() as u8;
+				out.put( x, y, [mix(0), mix(1), mix(2)] );
+			}
+		}
+		out
+	}
+
+	/// Red RGBA overlay for display on top of the source image
+	pub fn to_overlay_png(&self) -> Result<Vec<u8>, png::EncodingError> {
+		let pixels = self.data.iter().flat_map( |&v| [255, 64, 64, v / 2] ).collect::<Vec<u8>>();
+		let mut out = Vec::new();
+		let mut encoder = png::Encoder::new( &mut out, self.width, self.height );
+		encoder.set_color( png::ColorType::Rgba );
+		encoder.set_depth( png::BitDepth::Eight );
+		encoder.write_header()?.write_image_data( &pixels )?;
+		Ok(out)
+	}
+}
@@ -0,0 +1,127 @@
+/// Deterministic backend that doesn't need model weights.
+/// Used for UI work and tests on machines without a checkpoint.
+
+use std::ops::ControlFlow;
+
+use super::upscale::{self, Upscaler, Upscalers};
+use super::{report, GenerationBackend, GenerationMode, GenerationRequest, GeneratedImage, Mask, Progress, RgbImage, SplitMix};
+
+/// What the [`MockBackend`] paints
+#[derive(Debug, Default, PartialEq, Clone, Copy)]
+pub enum MockPattern {
+	/// One color derived from the seed and prompt
+	#[default]
+	Solid,
+
+	/// Per pixel noise derived from the seed and prompt
+	Noise,
+}
+
+#[derive(Debug, Default)]
+pub struct MockBackend {
+	pub pattern : MockPattern,
+
+	/// Looked up by name for the hi-res pass
+	pub upscalers : Upscalers,
+}
+
+impl MockBackend {
+	pub fn new(pattern:MockPattern) -> Self {
+		Self { pattern, upscalers : Upscalers::default() }
+	}
+
+	pub fn with_upscalers(self, upscalers:Upscalers) -> Self {
+		Self { upscalers, ..self }
+	}
+}
+
+/// FNV-1a, so the same prompt always gives the same color
+fn hash_str(s:&str) -> u64 {
+	s.bytes().fold( 0xcbf29ce484222325u64, |h, b| (h ^ b as u64).wrapping_mul(0x100000001b3) )
+}
+
+pub fn render(pattern:MockPattern, request:&GenerationRequest, seed:u64) -> RgbImage {
+	let mut rng = SplitMix::new( seed ^ hash_str(&request.prompt) );
+	match pattern {
+		MockPattern::Solid => {
+			let c = rng.next_u64().to_le_bytes();
+			RgbImage::solid( request.width, request.height, [c[0], c[1], c[2]] )
+		}
+		MockPattern::Noise => {
+			let mut image = RgbImage::new( request.width, request.height );
+			for chunk in image.pixels.chunks_mut(8) {
+				let bytes = rng.next_u64().to_le_bytes();
+				chunk.copy_from_slice( &bytes[..chunk.len()] );
+			}
+			image
+		}
+	}
+}
+
+/// Linear blend of `source` towards `target`, scaled per pixel by `mask`.
+/// `source` and `mask` are sampled nearest neighbour to fit `target`
+fn blend(source:&RgbImage, target:&RgbImage, mask:Option<&Mask>, strength:f64) -> RgbImage {
+	let mut out = target.clone();
+	for y in 0..target.height {
+		for x in 0..target.width {
+			let s = source.get( x * source.width / target.width, y * source.height / target.height );
+			let t = target.get(x, y);
+			let k = strength * mask.map_or( 1., |m| m.sample(x, y, target.width, target.height) );
+			let mix = |i:usize| ( s[i] as f64 + (t[i] as f64 - s[i] as f64) * k ).round() as u8;
+			out.put( x, y, [mix(0), mix(1), mix(2)] );
+		}
+	}
+	out
+}
+
+impl GenerationBackend for MockBackend {
+	fn name(&self) -> &str {
+		"mock"
+	}
+
+	fn generate(&mut self, request:&GenerationRequest, progress:&mut dyn FnMut(Progress) -> ControlFlow<()>) -> anyhow::Result<Vec<GeneratedImage>> {
+		// One model call per step, whatever the sampler
+		let per_image = request.steps_per_image( |steps| steps );
+		let total = per_image * request.seeds.len();
+		let mut images = Vec::with_capacity( request.seeds.len() );
+		for (n, &seed) in request.seeds.iter().enumerate() {
+			for step in 1 ..= per_image {
+				report( progress, Progress::Step { step : n*per_image + step, total } )?;
+			}
+			report( progress, Progress::Decoding )?;
+			// Variations and prompt blends blend towards the image of the other seed or prompt
+			let rendered = match &request.variation {
+				Some(v) => blend( &render(self.pattern, request, seed), &render(self.pattern, request, v.seed), None, v.strength.clamp(0., 1.) ),
+				None => render(self.pattern, request, seed),
+			};
+			let rendered = match &request.prompt_blend {
+				Some(b) => {
+					let other = GenerationRequest { prompt : b.prompt.clone(), prompt_blend : None, ..request.clone() };
+					blend( &rendered, &render(self.pattern, &other, seed), None, b.weight.clamp(0., 1.) )
+				}
+				None => rendered,
+			};
+			let image = match &request.mode {
+				GenerationMode::Txt2Img => rendered,
+				GenerationMode::Img2Img { source, strength } => blend( source, &rendered, None, strength.clamp(0., 1.) ),
+				GenerationMode::Inpaint { source, mask, strength } => blend( source, &rendered, Some(mask), strength.clamp(0., 1.) ),
+			};
+			// Latent upscaling resizes the pixels with Lanczos here, there are no latents to resize
+			let image = match request.hires() {
+				Some(hires) => {
+					let (width, height) = hires.target_size( request.width, request.height );
+					let upscaler = match hires.is_latent() {
+						true => self.upscalers.find( upscale::LANCZOS.name() )?,
+						false => self.upscalers.find( &hires.upscaler )?,
+					};
+					let upscaled = upscaler.upscale( &image, width, height )?;
+					let target = render( self.pattern, &GenerationRequest { width, height, ..request.clone() }, seed );
+					blend( &upscaled, &target, None, hires.strength.clamp(0., 1.) )
+				}
+				None => image,
+			};
+			images.push( GeneratedImage { image, seed, info : vec![] } );
+		}
+		Ok(images)
+	}
+}
@@ -0,0 +1,91 @@
+//! Image generation backends.
+//!
+//! A [`GenerationBackend`] takes a [`GenerationRequest`], reports [`Progress`] while it works
+//! and returns the finished images. Returning [`ControlFlow::Break`] from the progress callback
+//! cancels the request at the next denoising step.
+
+use std::fmt;
+use std::ops::ControlFlow;
+
+mod request;
+mod blocks;
+mod checkpoint;
+mod image;
+mod lora;
+mod mask;
+mod model;
+mod resample;
+mod sampler;
+mod schedulers;
+mod seamless;
+mod seed;
+mod textual_inversion;
+mod tiled_vae;
+mod unet;
+mod vae;
+pub mod mock;
+pub mod sd;
+pub mod upscale;
+
+pub use request::{GenerationRequest, GenerationMode, HiresFix, PromptBlend, Variation, img2img_start_step, LATENT_UPSCALER};
+pub use image::RgbImage;
+pub use mask::Mask;
+pub use model::{ModelSet, SdVersion};
+pub use sampler::Sampler;
+pub use seed::{SeedMode, SplitMix, plan_batches, random_seed, variations};
+
+/// Progress reported by a backend while a request is running
+#[derive(Debug, Clone, PartialEq)]
+pub enum Progress {
+	/// Model weights are being loaded
+	Loading,
+
+	/// Denoising step `step` (1 based) of `total` finished.
+	/// Counted over the whole job, so a batch of 2 at 20 steps reports 40 steps
+	Step { step : usize, total : usize },
+
+	/// Latents are being decoded to pixels
+	Decoding,
+}
+
+/// One finished output image
+#[derive(Debug, Clone, PartialEq)]
+pub struct GeneratedImage {
+	pub image : RgbImage,
+
+	/// Seed the image was sampled with
+	pub seed : u64,
+
+	/// Backend specific `(key, value)` metadata, like the embeddings the prompt used
+	pub info : Vec<(String, String)>,
+}
+
+/// Error returned by a backend when the progress callback asked it to stop
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Cancelled;
+
+impl fmt::Display for Cancelled {
+	fn fmt(&self, f:&mut fmt::Formatter<'_>) -> fmt::Result {
+		f.write_str("cancelled")
+	}
+}
+
+impl std::error::Error for Cancelled {}
+
+/// Forward `progress` and bail out with [`Cancelled`] on `Break`
+pub fn report(progress:&mut dyn FnMut(Progress) -> ControlFlow<()>, p:Progress) -> anyhow::Result<()> {
+	match progress(p) {
+		ControlFlow::Continue(()) => Ok(()),
+		ControlFlow::Break(()) => Err( Cancelled.into() ),
+	}
+}
+
+/// Something that turns a [`GenerationRequest`] into images.
+///
+/// `generate` blocks until the request is finished, so it must never be called from the render thread.
+pub trait GenerationBackend : Send {
+	/// Display name of the backend
+	fn name(&self) -> &str;
+
+	fn generate(&mut self, request:&GenerationRequest, progress:&mut dyn FnMut(Progress) -> ControlFlow<()>) -> anyhow::Result<Vec<GeneratedImage>>;
+}
@@ -0,0 +1,44 @@
+use std::fmt;
+use std::path::PathBuf;
+
+#[derive(Debug, Default, PartialEq, Eq, Hash, Clone, Copy, serde::Serialize, serde::Deserialize)]
+pub enum SdVersion {
+	#[default]
+	V1_5,
+	V2_1,
+}
+
+impl fmt::Display for SdVersion {
+	fn fmt(&self, f:&mut fmt::Formatter<'_>) -> fmt::Result {
+		f.write_str( match self {
+			Self::V1_5 => "SD 1.5",
+			Self::V2_1 => "SD 2.1",
+		} )
+	}
+}
+
+/// Weights that make up one pipeline. A single file checkpoint has its path for all three parts
+#[derive(Debug, Clone, PartialEq)]
+pub struct ModelSet {
+	/// Display name, usually the UNet file stem
+	pub name : String,
+	pub version : SdVersion,
+	pub unet : PathBuf,
+	pub vae : PathBuf,
+	pub clip : PathBuf,
+
+	/// Short hash of the UNet file, if known
+	pub hash : Option<String>,
+}
+
+impl ModelSet {
+	/// Set of a single file checkpoint in the original Stable Diffusion layout
+	pub fn checkpoint(name:String, version:SdVersion, path:PathBuf, hash:Option<String>) -> Self {
+		Self { name, version, unet : path.clone(), vae : path.clone(), clip : path, hash }
+	}
+
+	/// Whether the parts come from one checkpoint file
+	pub fn is_checkpoint(&self) -> bool {
+		self.unet == self.vae && self.unet == self.clip
+	}
+}
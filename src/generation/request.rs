@@ -0,0 +1,262 @@
+use std::sync::Arc;
+
+use super::{Mask, ModelSet, RgbImage, Sampler};
+
+/// What the backend starts sampling from
+#[derive(Debug, Clone, PartialEq, Default)]
+pub enum GenerationMode {
+	/// Pure noise
+	#[default]
+	Txt2Img,
+
+	/// Partly noised latent of `source`
+	Img2Img {
+		source : Arc<RgbImage>,
+
+		/// 0 keeps the source, 1 ignores it
+		strength : f64,
+	},
+
+	/// Like img2img, but only the masked part of `source` is repainted
+	Inpaint {
+		source : Arc<RgbImage>,
+		mask : Arc<Mask>,
+		strength : f64,
+	},
+}
+
+/// Hi-res upscaler that resizes the latents instead of decoding them. Fast, but needs a higher
+/// strength to clean up
+pub const LATENT_UPSCALER:&str = "Latent";
+
+/// Second txt2img pass : the first pass is enlarged by `scale`, then run through img2img
+#[derive(Debug, Clone, PartialEq)]
+pub struct HiresFix {
+	pub scale : f64,
+
+	/// [`LATENT_UPSCALER`] or the name of one of the [`Upscalers`](super::upscale::Upscalers),
+	/// which decode, resize the pixels and encode again
+	pub upscaler : String,
+
+	/// Steps of the second pass, 0 uses the steps of the first pass
+	pub steps : usize,
+
+	/// Denoising strength of the second pass
+	pub strength : f64,
+}
+
+impl HiresFix {
+	pub fn is_latent(&self) -> bool {
+		self.upscaler.eq_ignore_ascii_case(LATENT_UPSCALER)
+	}
+
+	/// Size of the second pass, rounded to multiples of 8
+	pub fn target_size(&self, width:u32, height:u32) -> (u32, u32) {
+		let scale = |v:u32| ((v as f64 * self.scale / 8.).round() as u32).max(1) * 8;
+		(scale(width), scale(height))
+	}
+
+	pub fn steps_or(&self, first_pass:usize) -> usize {
+		if self.steps == 0 { first_pass } else { self.steps }
+	}
+}
+
+impl Default for HiresFix {
+	fn default() -> Self {
+		Self { scale : 2., upscaler : LATENT_UPSCALER.to_string(), steps : 0, strength : 0.55 }
+	}
+}
+
+/// Second seed whose noise is mixed into the noise of every image of a request
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Variation {
+	pub seed : u64,
+
+	/// 0 keeps the noise of the main seed, 1 uses the noise of `seed` only
+	pub strength : f64,
+}
+
+/// Second prompt the text conditioning is interpolated towards
+#[derive(Debug, Clone, PartialEq)]
+pub struct PromptBlend {
+	pub prompt : String,
+
+	/// 0 is the main prompt only, 1 this prompt only
+	pub weight : f64,
+}
+
+/// Parameters of a single generation
+#[derive(Debug, Clone, PartialEq)]
+pub struct GenerationRequest {
+	pub mode : GenerationMode,
+
+	/// Weights to use. `None` keeps the backend default
+	pub model : Option<ModelSet>,
+
+	pub prompt : String,
+	pub negative_prompt : String,
+
+	/// Slerp of the prompt's embeddings towards another prompt, for prompt interpolation
+	pub prompt_blend : Option<PromptBlend>,
+
+	/// Encode prompts past the CLIP window in extra chunks. Off truncates them
+	pub long_prompts : bool,
+
+	/// Output width in pixels. Must be a multiple of 8
+	pub width : u32,
+
+	/// Output height in pixels. Must be a multiple of 8
+	pub height : u32,
+
+	/// Make the output wrap around at its edges, for textures
+	pub tileable : bool,
+
+	/// Denoising steps
+	pub steps : usize,
+
+	pub sampler : Sampler,
+
+	/// Classifier free guidance scale
+	pub cfg_scale : f64,
+
+	/// One seed per image of the job. Images are sampled one after the other
+	pub seeds : Vec<u64>,
+
+	/// Small changes to the images of `seeds`
+	pub variation : Option<Variation>,
+
+	/// Second pass at a higher resolution. Only used by txt2img
+	pub hires : Option<HiresFix>,
+}
+
+impl Default for GenerationRequest {
+	fn default() -> Self {
+		Self {
+			mode : GenerationMode::default(),
+			model : None,
+			prompt : String::new(),
+			negative_prompt : String::new(),
+			prompt_blend : None,
+			long_prompts : true,
+			tileable : false,
+			width : 512,
+			height : 512,
+			steps : 20,
+			sampler : Sampler::default(),
+			cfg_scale : 7.5,
+			seeds : vec![0],
+			variation : None,
+			hires : None,
+		}
+	}
+}
+
+impl GenerationRequest {
+	/// Seed of the first image
+	pub fn seed(&self) -> u64 {
+		self.seeds.first().copied().unwrap_or_default()
+	}
+
+	/// Copy of this request that makes the single image of `seed`
+	pub fn with_seed(&self, seed:u64) -> Self {
+		Self { seeds : vec![seed], ..self.clone() }
+	}
+
+	/// Hi-res pass, when it applies to this request
+	pub fn hires(&self) -> Option<&HiresFix> {
+		self.hires.as_ref().filter( |_| matches!( self.mode, GenerationMode::Txt2Img ) )
+	}
+
+	/// Sampler timesteps run per image, the hi-res pass included. `timesteps` gives the length of
+	/// the sampler's timestep list for a number of steps, which is more than the steps for samplers
+	/// that call the model several times per step
+	pub fn steps_per_image(&self, timesteps:impl Fn(usize) -> usize) -> usize {
+		let hires = self.hires().map_or( 0, |h| {
+			let count = timesteps( h.steps_or(self.steps) );
+			count - img2img_start_step( count, h.strength )
+		} );
+		let count = timesteps(self.steps);
+		count - self.start_step(count) + hires
+	}
+
+	/// Index of the first of `timesteps` sampler timesteps that actually runs. Timesteps before it are skipped.
+	pub fn start_step(&self, timesteps:usize) -> usize {
+		match &self.mode {
+			GenerationMode::Txt2Img => 0,
+			GenerationMode::Img2Img { strength, .. }
+			| GenerationMode::Inpaint { strength, .. } => img2img_start_step( timesteps, *strength ),
+		}
+	}
+}
+
+/// Same mapping as the diffusers img2img pipeline : `strength` of the steps run, counted from the end
+pub fn img2img_start_step(steps:usize, strength:f64) -> usize {
+	let init = (steps as f64 * strength.clamp(0., 1.)) as usize;
+	steps - init.min(steps)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn img2img(steps:usize, strength:f64) -> GenerationRequest {
+		let source = Arc::new( RgbImage::new(8, 8) );
+		GenerationRequest { mode : GenerationMode::Img2Img { source, strength }, steps, ..Default::default() }
+	}
+
+	#[test]
+	fn strength_zero_skips_every_step() {
+		assert_eq!( img2img_start_step(20, 0.), 20 );
+		assert_eq!( img2img(20, 0.).start_step(20), 20 );
+		assert_eq!( img2img(20, 0.).steps_per_image( |s| s ), 0 );
+	}
+
+	#[test]
+	fn strength_one_runs_every_step() {
+		assert_eq!( img2img_start_step(20, 1.), 0 );
+		assert_eq!( img2img(20, 1.).steps_per_image( |s| s ), 20 );
+	}
+
+	#[test]
+	fn fractional_strength_runs_the_last_steps() {
+		assert_eq!( img2img_start_step(20, 0.75), 5 );
+		assert_eq!( img2img_start_step(10, 0.55), 5 );
+		assert_eq!( img2img(20, 0.75).steps_per_image( |s| s ), 15 );
+	}
+
+	#[test]
+	fn zero_steps() {
+		assert_eq!( img2img_start_step(0, 0.5), 0 );
+		assert_eq!( img2img(0, 0.5).steps_per_image( |s| s ), 0 );
+	}
+
+	#[test]
+	fn strength_out_of_range_is_clamped() {
+		assert_eq!( img2img_start_step(20, 1.5), 0 );
+		assert_eq!( img2img_start_step(20, -1.), 20 );
+	}
+
+	#[test]
+	fn txt2img_starts_at_zero() {
+		let r = GenerationRequest { steps : 20, ..Default::default() };
+		assert_eq!( r.start_step(20), 0 );
+		assert_eq!( r.steps_per_image( |s| s ), 20 );
+	}
+
+	#[test]
+	fn counts_follow_the_sampler_timesteps() {
+		// Like Heun : two model calls per step but the first
+		let heun = |steps:usize| (2 * steps).saturating_sub(1);
+		assert_eq!( img2img(10, 0.5).start_step( heun(10) ), 10 );
+		assert_eq!( img2img(10, 0.5).steps_per_image(heun), 9 );
+
+		let hires = GenerationRequest {
+			steps : 10,
+			hires : Some( HiresFix { steps : 4, strength : 0.5, ..Default::default() } ),
+			..Default::default()
+		};
+		assert_eq!( hires.steps_per_image( |s| s ), 10 + 2 );
+		// 7 hires timesteps, the last 3 of them run
+		assert_eq!( hires.steps_per_image(heun), 19 + 3 );
+	}
+}
@@ -0,0 +1,171 @@
+/// Separable image resampling.
+///
+/// Rows are resized first, then columns. When shrinking, the kernel is stretched by the scale
+/// factor so every source pixel contributes. The output is worked on in tiles, each reading only
+/// the source pixels under its kernels, so large resizes don't hold the whole image as floats.
+
+use std::f64::consts::PI;
+
+use super::RgbImage;
+
+/// Resampling filter, `support` is the kernel radius in source pixels at scale 1
+pub struct Filter {
+	pub support : f64,
+	pub kernel : fn(f64) -> f64,
+}
+
+fn sinc(x:f64) -> f64 {
+	if x == 0. { 1. } else { (PI * x).sin() / (PI * x) }
+}
+
+pub const LANCZOS3:Filter = Filter {
+	support : 3.,
+	kernel : |x| if x.abs() < 3. { sinc(x) * sinc(x / 3.) } else { 0. },
+};
+
+/// Smooth cubic, Keys' kernel with `a = -0.5`
+pub const BICUBIC:Filter = Filter {
+	support : 2.,
+	kernel : |x| {
+		let x = x.abs();
+		if x < 1. {
+			1.5*x*x*x - 2.5*x*x + 1.
+		} else if x < 2. {
+			-0.5*x*x*x + 2.5*x*x - 4.*x + 2.
+		} else {
+			0.
+		}
+	},
+};
+
+/// Box of one source pixel. Picks the nearest pixel when enlarging, averages when shrinking
+pub const NEAREST:Filter = Filter {
+	support : 0.5,
+	kernel : |x| if (-0.5..0.5).contains(&x) { 1. } else { 0. },
+};
+
+/// Output pixels per tile side. Only one tile of intermediate values is held at a time
+const TILE:usize = 512;
+
+/// `image` resized to `width` x `height` with `filter`
+pub fn resample(image:&RgbImage, width:u32, height:u32, filter:&Filter) -> RgbImage {
+	if (image.width, image.height) == (width, height) {
+		return image.clone()
+	}
+	let xs = weights( image.width, width, filter );
+	let ys = weights( image.height, height, filter );
+	let mut out = RgbImage::new(width, height);
+	for y in (0..ys.len()).step_by(TILE) {
+		for x in (0..xs.len()).step_by(TILE) {
+			let tile = resample_tile( image, &xs[x..(x + TILE).min(xs.len())], &ys[y..(y + TILE).min(ys.len())] );
+			out.paste( &tile, x as u32, y as u32 );
+		}
+	}
+	out
+}
+
+/// Weights of every destination index along an axis : first source index and weights
+fn weights(src:u32, dst:u32, filter:&Filter) -> Vec<(usize, Vec<f64>)> {
+	let scale = src as f64 / dst as f64;
+	let stretch = scale.max(1.);
+	let support = filter.support * stretch;
+	(0..dst).map( |d| {
+		let center = (d as f64 + 0.5) * scale;
+		let first = ((center - support).floor().max(0.)) as usize;
+		let last = ((center + support).ceil() as usize).min( src as usize );
+		let mut w = (first..last)
+			.map( |s| (filter.kernel)( (s as f64 + 0.5 - center) / stretch ) )
+			.collect::<Vec<_>>();
+		let sum:f64 = w.iter().sum();
+		if sum != 0. {
+			w.iter_mut().for_each( |v| *v /= sum );
+		}
+		(first, w)
+	} ).collect()
+}
+
+/// Output pixels of the `xs` columns and `ys` rows. Rows are resized first, then columns
+fn resample_tile(image:&RgbImage, xs:&[(usize, Vec<f64>)], ys:&[(usize, Vec<f64>)]) -> RgbImage {
+	// Source rows the tile reads
+	let top = ys.iter().map( |(first, _)| *first ).min().unwrap_or(0);
+	let bottom = ys.iter().map( |(first, w)| first + w.len() ).max().unwrap_or(top);
+	let width = xs.len();
+
+	let mut rows = vec![0.; (bottom - top) * width * 3];
+	for y in top..bottom {
+		for (x, (first, w)) in xs.iter().enumerate() {
+			let mut acc = [0f64;3];
+			for (k, weight) in w.iter().enumerate() {
+				let i = (y * image.width as usize + first + k) * 3;
+				acc.iter_mut().zip( &image.pixels[i..i+3] ).for_each( |(a, &v)| *a += v as f64 * weight );
+			}
+			let i = ((y - top) * width + x) * 3;
+			rows[i..i+3].copy_from_slice(&acc);
+		}
+	}
+
+	let mut out = RgbImage::new( width as u32, ys.len() as u32 );
+	for (y, (first, w)) in ys.iter().enumerate() {
+		for x in 0..width {
+			let mut acc = [0f64;3];
+			for (k, weight) in w.iter().enumerate() {
+				let i = ((first + k - top) * width + x) * 3;
+				acc.iter_mut().zip( &rows[i..i+3] ).for_each( |(a, v)| *a += v * weight );
+			}
+			out.put( x as u32, y as u32, acc.map( |v| v.round().clamp(0., 255.) as u8 ) );
+		}
+	}
+	out
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn gradient(width:u32, height:u32) -> RgbImage {
+		let mut image = RgbImage::new(width, height);
+		for y in 0..height {
+			for x in 0..width {
+				image.put( x, y, [(x * 7) as u8, (y * 13) as u8, (x * y) as u8] );
+			}
+		}
+		image
+	}
+
+	#[test]
+	fn identity_at_scale_1() {
+		let image = gradient(37, 21);
+		for filter in [&LANCZOS3, &BICUBIC, &NEAREST] {
+			assert_eq!( resample( &image, 37, 21, filter ), image );
+			// The kernels are 1 at 0 and 0 at every other whole pixel
+			for (d, (first, w)) in weights( 37, 37, filter ).iter().enumerate() {
+				for (k, weight) in w.iter().enumerate() {
+					let expected = if first + k == d { 1. } else { 0. };
+					assert!( (weight - expected).abs() < 1e-12, "{d} {k} {weight}" );
+				}
+			}
+		}
+	}
+
+	#[test]
+	fn nearest_doubles_pixels() {
+		let image = gradient(300, 9);
+		let out = resample( &image, 600, 18, &NEAREST );
+		for y in 0..18 {
+			for x in 0..600 {
+				assert_eq!( out.get(x, y), image.get(x / 2, y / 2) );
+			}
+		}
+	}
+
+	#[test]
+	fn constant_stays_constant() {
+		let image = RgbImage::solid( 300, 40, [10, 128, 250] );
+		// 700 wide spans two tiles
+		for (width, height) in [(700, 93), (123, 17), (300, 1)] {
+			for filter in [&LANCZOS3, &BICUBIC] {
+				assert_eq!( resample( &image, width, height, filter ), RgbImage::solid( width, height, [10, 128, 250] ) );
+			}
+		}
+	}
+}
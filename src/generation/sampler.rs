@@ -0,0 +1,55 @@
+use std::fmt;
+use std::str::FromStr;
+
+/// Noise scheduler used by the denoising loop.
+///
+/// Display names follow Automatic1111 so they can be written to and read from image metadata.
+#[derive(Debug, Default, PartialEq, Eq, Hash, Clone, Copy)]
+pub enum Sampler {
+	#[default]
+	Ddim,
+	Ddpm,
+	Euler,
+	EulerAncestral,
+	DpmSolverMultistep,
+	Pndm,
+	Heun,
+	Lms,
+}
+
+impl Sampler {
+	pub const ALL:[Sampler;8] = [
+		Sampler::Ddim, Sampler::Ddpm, Sampler::Euler, Sampler::EulerAncestral,
+		Sampler::DpmSolverMultistep, Sampler::Pndm, Sampler::Heun, Sampler::Lms,
+	];
+
+	pub fn name(&self) -> &'static str {
+		match self {
+			Self::Ddim => "DDIM",
+			Self::Ddpm => "DDPM",
+			Self::Euler => "Euler",
+			Self::EulerAncestral => "Euler a",
+			Self::DpmSolverMultistep => "DPM++ 2M",
+			Self::Pndm => "PLMS",
+			Self::Heun => "Heun",
+			Self::Lms => "LMS",
+		}
+	}
+}
+
+impl fmt::Display for Sampler {
+	fn fmt(&self, f:&mut fmt::Formatter<'_>) -> fmt::Result {
+		f.write_str( self.name() )
+	}
+}
+
+impl FromStr for Sampler {
+	type Err = String;
+
+	fn from_str(s:&str) -> Result<Self, Self::Err> {
+		let s = s.trim();
+		Self::ALL.iter().copied()
+			.find( |v| v.name().eq_ignore_ascii_case(s) )
+			.ok_or_else( || format!("Unknown sampler '{s}'") )
+	}
+}
@@ -0,0 +1,101 @@
+/// [`Sampler`] to `diffusers` scheduler mapping.
+///
+/// The `diffusers` schedulers don't share a trait and disagree on the timestep type
+/// (`usize` for DDIM like schedulers, `f64` for the k-diffusion ones), so they are wrapped in [`Schedule`].
+
+use diffusers::schedulers::{
+	ddim::{DDIMScheduler, DDIMSchedulerConfig},
+	ddpm::{DDPMScheduler, DDPMSchedulerConfig},
+	dpmsolver_multistep::{DPMSolverMultistepScheduler, DPMSolverMultistepSchedulerConfig},
+	euler_ancestral_discrete::{EulerAncestralDiscreteScheduler, EulerAncestralDiscreteSchedulerConfig},
+	euler_discrete::{EulerDiscreteScheduler, EulerDiscreteSchedulerConfig},
+	heun_discrete::{HeunDiscreteScheduler, HeunDiscreteSchedulerConfig},
+	lms_discrete::{LMSDiscreteScheduler, LMSDiscreteSchedulerConfig},
+	pndm::{PNDMScheduler, PNDMSchedulerConfig},
+	PredictionType,
+};
+use tch::Tensor;
+
+use super::Sampler;
+
+pub(crate) trait Schedule {
+	fn timesteps(&self) -> Vec<f64>;
+	fn init_noise_sigma(&self) -> f64;
+	fn scale_model_input(&self, sample:Tensor, timestep:f64) -> Tensor;
+	fn step(&mut self, model_output:&Tensor, timestep:f64, sample:&Tensor) -> Tensor;
+	fn add_noise(&self, original:&Tensor, noise:Tensor, timestep:f64) -> Tensor;
+}
+
+macro_rules! impl_schedule {
+	($scheduler:ty, $timestep:ty) => {
+		impl Schedule for $scheduler {
+			fn timesteps(&self) -> Vec<f64> {
+				<$scheduler>::timesteps(self).iter().map( |&t| t as f64 ).collect()
+			}
+			fn init_noise_sigma(&self) -> f64 {
+				<$scheduler>::init_noise_sigma(self)
+			}
+			fn scale_model_input(&self, sample:Tensor, timestep:f64) -> Tensor {
+				<$scheduler>::scale_model_input(self, sample, timestep as $timestep)
+			}
+			fn step(&mut self, model_output:&Tensor, timestep:f64, sample:&Tensor) -> Tensor {
+				<$scheduler>::step(self, model_output, timestep as $timestep, sample)
+			}
+			fn add_noise(&self, original:&Tensor, noise:Tensor, timestep:f64) -> Tensor {
+				<$scheduler>::add_noise(self, original, noise, timestep as $timestep)
+			}
+		}
+	};
+}
+
+impl_schedule!(DDIMScheduler, usize);
+impl_schedule!(DDPMScheduler, usize);
+impl_schedule!(DPMSolverMultistepScheduler, usize);
+impl_schedule!(PNDMScheduler, usize);
+impl_schedule!(EulerDiscreteScheduler, f64);
+impl_schedule!(EulerAncestralDiscreteScheduler, f64);
+impl_schedule!(HeunDiscreteScheduler, f64);
+impl_schedule!(LMSDiscreteScheduler, f64);
+
+/// Timesteps of `sampler` over `steps` steps, one UNet call each. Heun and PNDM call the UNet
+/// more often than once per step, so this is what progress and img2img strength are counted in
+pub(crate) fn timestep_count(sampler:Sampler, steps:usize, base:&DDIMSchedulerConfig) -> usize {
+	build( sampler, steps, base ).timesteps().len()
+}
+
+/// Build the scheduler of `sampler`. `base` is the DDIM config of the SD version, its betas and
+/// prediction type are carried over to the other schedulers.
+pub(crate) fn build(sampler:Sampler, steps:usize, base:&DDIMSchedulerConfig) -> Box<dyn Schedule> {
+	let (beta_start, beta_end, beta_schedule) = (base.beta_start, base.beta_end, base.beta_schedule);
+	let prediction_type:PredictionType = base.prediction_type;
+	let train_timesteps = base.train_timesteps;
+
+	match sampler {
+		Sampler::Ddim => Box::new( DDIMScheduler::new( steps, *base ) ),
+		Sampler::Ddpm => Box::new( DDPMScheduler::new( steps, DDPMSchedulerConfig {
+			beta_start, beta_end, beta_schedule, prediction_type, train_timesteps,
+			..Default::default()
+		} ) ),
+		Sampler::Euler => Box::new( EulerDiscreteScheduler::new( steps, EulerDiscreteSchedulerConfig {
+			beta_start, beta_end, beta_schedule, prediction_type, train_timesteps,
+		} ) ),
+		Sampler::EulerAncestral => Box::new( EulerAncestralDiscreteScheduler::new( steps, EulerAncestralDiscreteSchedulerConfig {
+			beta_start, beta_end, beta_schedule, prediction_type, train_timesteps,
+		} ) ),
+		Sampler::DpmSolverMultistep => Box::new( DPMSolverMultistepScheduler::new( steps, DPMSolverMultistepSchedulerConfig {
+			beta_start, beta_end, beta_schedule, prediction_type, train_timesteps,
+			..Default::default()
+		} ) ),
+		Sampler::Pndm => Box::new( PNDMScheduler::new( steps, PNDMSchedulerConfig {
+			beta_start, beta_end, beta_schedule, prediction_type, train_timesteps,
+			..Default::default()
+		} ) ),
+		Sampler::Heun => Box::new( HeunDiscreteScheduler::new( steps, HeunDiscreteSchedulerConfig {
+			beta_start, beta_end, beta_schedule, prediction_type, train_timesteps,
+		} ) ),
+		Sampler::Lms => Box::new( LMSDiscreteScheduler::new( steps, LMSDiscreteSchedulerConfig {
+			beta_start, beta_end, beta_schedule, prediction_type, train_timesteps,
+			..Default::default()
+		} ) ),
+	}
+}
@@ -0,0 +1,612 @@
+/// Stable Diffusion on CPU through the `diffusers` crate.
+/// Weights are loaded on the first request and kept until a request picks another [`ModelSet`].
+
+use std::collections::BTreeSet;
+use std::ops::ControlFlow;
+use std::path::PathBuf;
+
+use anyhow::Context;
+use diffusers::pipelines::stable_diffusion::StableDiffusionConfig;
+use diffusers::transformers::clip::ClipTextTransformer;
+use tch::{nn, nn::Module, Device, Kind, Tensor};
+
+use crate::models::file_hash;
+use crate::prompt::chunks::{self, Chunk};
+use crate::prompt::clip::{AddedWord, ClipTokenizer, VOCAB_FILE};
+use crate::prompt::embeddings;
+use crate::prompt::schedule::{self, Scheduled};
+use crate::prompt::tags;
+
+use super::checkpoint::Checkpoint;
+use super::lora::{self, LoraState};
+use super::schedulers::{self, Schedule};
+use super::seamless::Padding;
+use super::textual_inversion::{self, Embedding};
+use super::tiled_vae::{self, Tiling};
+use super::unet::UNet;
+use super::upscale::Upscalers;
+use super::vae::Vae;
+use super::{img2img_start_step, report, GenerationBackend, GenerationMode, GenerationRequest, GeneratedImage, Mask, ModelSet, Progress, RgbImage, SdVersion};
+
+/// Scaling factor of the SD latent space
+pub(crate) const VAE_SCALE:f64 = 0.18215;
+
+/// Default of [`DiffusersConfig::tiled_vae_pixels`]. A full 1024x1024 decode needs several GB
+pub const TILED_VAE_PIXELS:u32 = 768 * 768;
+
+/// Where to find the weights of each part of the pipeline
+#[derive(Debug, Clone, PartialEq)]
+pub struct DiffusersConfig {
+	/// CLIP BPE vocabulary
+	pub vocab : PathBuf,
+
+	/// Used when a request doesn't pick a model
+	pub model : ModelSet,
+
+	/// Textual inversion embeddings, see [`crate::prompt::embeddings`]
+	pub embeddings : PathBuf,
+
+	/// LoRA files for `<lora:name:weight>` tags
+	pub loras : PathBuf,
+
+	/// Attention slice size. Lower memory use at the cost of speed
+	pub sliced_attention_size : Option<i64>,
+
+	/// Images with more pixels than this go through the VAE in tiles, `None` never tiles
+	pub tiled_vae_pixels : Option<u32>,
+}
+
+impl DiffusersConfig {
+	/// Default file names of the `diffusers` examples inside `dir`
+	pub fn from_dir(dir:impl Into<PathBuf>, version:SdVersion) -> Self {
+		let dir = dir.into();
+		let suffix = match version { SdVersion::V1_5 => "", SdVersion::V2_1 => "_v2.1" };
+		Self {
+			vocab : dir.join(VOCAB_FILE),
+			embeddings : dir.join("embeddings"),
+			loras : dir.join("loras"),
+			model : ModelSet {
+				name : format!("unet{suffix}"),
+				version,
+				unet : dir.join( format!("unet{suffix}.safetensors") ),
+				vae : dir.join( format!("vae{suffix}.safetensors") ),
+				clip : dir.join( format!("clip{suffix}.safetensors") ),
+				hash : None,
+			},
+			sliced_attention_size : None,
+			tiled_vae_pixels : Some(TILED_VAE_PIXELS),
+		}
+	}
+
+	fn sd_config(&self, version:SdVersion, width:u32, height:u32) -> StableDiffusionConfig {
+		let (w, h) = (Some(width as i64), Some(height as i64));
+		match version {
+			SdVersion::V1_5 => StableDiffusionConfig::v1_5( self.sliced_attention_size, h, w ),
+			SdVersion::V2_1 => StableDiffusionConfig::v2_1( self.sliced_attention_size, h, w ),
+		}
+	}
+}
+
+/// Name of the CLIP token embedding table
+const TOKEN_TABLE:&str = "text_model.embeddings.token_embedding.weight";
+
+/// Loaded models
+struct Pipeline {
+	model : ModelSet,
+
+	/// Short hash of `model`, hashed on load when the set doesn't know it
+	model_hash : String,
+	tokenizer : ClipTokenizer,
+	text_model : ClipTextTransformer,
+
+	/// CLIP token embedding table, shared with `text_model`.
+	/// Textual inversion vectors are appended to it as extra token ids
+	token_table : Tensor,
+
+	/// `token_table` as loaded, without embeddings
+	base_table : Tensor,
+
+	embeddings : Vec<Embedding>,
+	embeddings_signature : textual_inversion::Signature,
+
+	/// Names of the embeddings that failed to load, and why
+	embedding_errors : Vec<(String, String)>,
+	vae : Vae,
+	tiled_vae_pixels : Option<u32>,
+
+	/// Padding of the UNet and VAE convolutions, circular for tileable requests
+	padding : Padding,
+	unet : UNet,
+
+	/// Weights of `text_model` and `unet`, for LoRA merging
+	text_vs : nn::VarStore,
+	unet_vs : nn::VarStore,
+	loras : LoraState,
+}
+
+impl Pipeline {
+	fn load(config:&DiffusersConfig, model:&ModelSet, device:Device) -> anyhow::Result<Self> {
+		let sd_config = config.sd_config(model.version, 512, 512);
+		// Read once for all three parts
+		let checkpoint = match model.is_checkpoint() {
+			true => Some( Checkpoint::read(&model.unet)? ),
+			false => None,
+		};
+		let load = |vs:&mut nn::VarStore, path:&PathBuf, part:fn(&Checkpoint, &nn::VarStore) -> anyhow::Result<()>| -> anyhow::Result<()> {
+			match &checkpoint {
+				Some(checkpoint) => part( checkpoint, vs ),
+				None => Ok( vs.load(path)? ),
+			}
+		};
+
+		// Built by hand instead of `build_clip_transformer` and `build_unet` to keep the weights reachable
+		let mut text_vs = nn::VarStore::new(device);
+		let text_model = ClipTextTransformer::new( text_vs.root(), &sd_config.clip );
+		load( &mut text_vs, &model.clip, Checkpoint::load_clip )?;
+		text_vs.freeze();
+		let token_table = text_vs.variables().remove(TOKEN_TABLE).context("no token embedding table in CLIP weights")?;
+
+		let mut unet_vs = nn::VarStore::new(device);
+		let padding = Padding::default();
+		let unet = UNet::new( unet_vs.root(), 4, 4, sd_config.unet.clone(), &padding );
+		load( &mut unet_vs, &model.unet, Checkpoint::load_unet )?;
+		unet_vs.freeze();
+
+		let mut vae_vs = nn::VarStore::new(device);
+		let vae = Vae::new( vae_vs.root(), &sd_config.autoencoder, &padding );
+		load( &mut vae_vs, &model.vae, Checkpoint::load_vae )?;
+		vae_vs.freeze();
+
+		Ok( Self {
+			model : model.clone(),
+			model_hash : match &model.hash {
+				Some(hash) => hash.clone(),
+				None => file_hash(&model.unet)?,
+			},
+			tokenizer : ClipTokenizer::load( &config.vocab, model.version )?,
+			text_model,
+			base_table : token_table.copy(),
+			token_table,
+			embeddings : vec![],
+			embeddings_signature : vec![],
+			embedding_errors : vec![],
+			vae,
+			tiled_vae_pixels : config.tiled_vae_pixels,
+			padding,
+			unet,
+			text_vs,
+			unet_vs,
+			loras : LoraState::default(),
+		} )
+	}
+
+	/// Load the embeddings of `dir` when it changed since the last call, and append the vectors that
+	/// fit this text encoder to the token table. Files that fail to load are skipped, see
+	/// [`Pipeline::check_embeddings`]
+	fn sync_embeddings(&mut self, dir:&std::path::Path) {
+		let files = embeddings::list(dir);
+		let signature = textual_inversion::signature(&files);
+		if signature == self.embeddings_signature {
+			return
+		}
+		let dim = self.base_table.size()[1];
+		self.embeddings.clear();
+		self.embedding_errors.clear();
+		for f in &files {
+			match Embedding::load(f) {
+				Ok(e) if e.dim() == dim => self.embeddings.push(e),
+				Ok(e) => self.embedding_errors.push( (e.name, format!("for another model, {} wide instead of {dim}", e.dim())) ),
+				Err(err) => self.embedding_errors.push( (f.name.clone(), format!("{err:#}")) ),
+			}
+		}
+		self.embeddings_signature = signature;
+
+		let mut next = self.base_table.size()[0];
+		let mut added = vec![];
+		let mut tables = vec![ self.base_table.shallow_clone() ];
+		for e in &self.embeddings {
+			added.push( AddedWord { word : e.name.clone(), first : next, count : e.count() } );
+			next += e.count() as i64;
+			tables.push( e.vectors.to_device( self.base_table.device() ) );
+		}
+		self.token_table.set_data( &Tensor::cat( &tables, 0 ) );
+		self.tokenizer.set_added(added);
+	}
+
+	/// Fail when a prompt of `request` names an embedding that couldn't be loaded, instead of
+	/// tokenizing the name as plain text
+	fn check_embeddings(&self, request:&GenerationRequest) -> anyhow::Result<()> {
+		let prompts = [&request.prompt, &request.negative_prompt].into_iter().chain( request.prompt_blend.as_ref().map( |b| &b.prompt ) );
+		for prompt in prompts {
+			if let Some((name, error)) = self.embedding_errors.iter().find( |(name, _)| !embeddings::find_word(prompt, name).is_empty() ) {
+				anyhow::bail!( "can't load embedding '{name}' : {error}" )
+			}
+		}
+		Ok(())
+	}
+
+	/// Names and hashes of the embeddings used by `chunks`, as A1111's `TI hashes` value
+	fn used_embeddings<'c>(&self, chunks:impl IntoIterator<Item = &'c Chunk>) -> Option<String> {
+		let used = chunks.into_iter()
+			.flat_map( |c| &c.tokens )
+			.filter_map( |t| self.tokenizer.added_word(t.id) )
+			.collect::<BTreeSet<_>>();
+		let used = self.embeddings.iter()
+			.filter( |e| used.contains( e.name.as_str() ) )
+			.map( |e| format!("{}: {}", e.name, e.hash) )
+			.collect::<Vec<_>>();
+		( !used.is_empty() ).then( || used.join(", ") )
+	}
+
+	/// Encoded prompts of `request` for a run of `steps` steps. LoRA tags are left out
+	fn conditioning(&self, steps:usize, request:&GenerationRequest, device:Device) -> anyhow::Result<Conditioning> {
+		let split = |prompt:&str| -> anyhow::Result<Vec<Chunk>> {
+			let split = chunks::split( prompt, &self.tokenizer )?;
+			Ok( if request.long_prompts { split } else { vec![ chunks::truncate(split) ] } )
+		};
+		// Prompt editing gives a prompt per range of steps, each encoded once up front
+		let schedule = |prompt:&str| schedule::schedule( &tags::extract_loras(prompt).0, steps );
+		let cond_schedule = schedule( &request.prompt );
+		let uncond_schedule = schedule( &request.negative_prompt );
+		let blend_schedule = request.prompt_blend.as_ref().map( |b| schedule(&b.prompt) ).unwrap_or_default();
+		let split_all = |s:&[Scheduled]| s.iter().map( |s| split(&s.prompt) ).collect::<anyhow::Result<Vec<_>>>();
+		let mut cond = split_all(&cond_schedule)?;
+		let mut uncond = split_all(&uncond_schedule)?;
+		let mut blend = split_all(&blend_schedule)?;
+		chunks::pad_to_match( cond.iter_mut().chain( uncond.iter_mut() ).chain( blend.iter_mut() ) );
+		let encode_all = |chunks:&[Vec<Chunk>]| -> Vec<Tensor> { chunks.iter().map( |c| self.encode_chunks(c, device) ).collect() };
+		Ok( Conditioning {
+			embeddings_used : self.used_embeddings( cond.iter().chain(&uncond).chain(&blend).flatten() ),
+			cond : encode_all(&cond),
+			uncond : encode_all(&uncond),
+			blend : encode_all(&blend),
+			cond_schedule,
+			uncond_schedule,
+			blend_schedule,
+			blend_weight : request.prompt_blend.as_ref().map_or( 0., |b| b.weight ),
+			cfg_scale : request.cfg_scale,
+			steps,
+		} )
+	}
+
+	/// Run `scheduler` on `latents` from step `start` to the end
+	fn denoise(
+		&self,
+		scheduler:&mut dyn Schedule,
+		mut latents:Tensor,
+		start:usize,
+		conditioning:&Conditioning,
+		inpaint:Option<Inpaint<'_>>,
+		steps:&mut StepCounter<'_>,
+	) -> anyhow::Result<Tensor> {
+		let timesteps = scheduler.timesteps();
+		for (i, &timestep) in timesteps.iter().enumerate().skip(start) {
+			let input = Tensor::cat( &[&latents, &latents], 0 );
+			let input = scheduler.scale_model_input( input, timestep );
+			// Samplers like Heun have more timesteps than steps, the prompt schedule counts steps
+			let step = i * conditioning.steps / timesteps.len();
+			let noise_pred = self.unet.forward( &input, timestep, &conditioning.at(step) ).chunk(2, 0);
+			let (uncond_pred, cond_pred) = (&noise_pred[0], &noise_pred[1]);
+			let noise_pred = uncond_pred + (cond_pred - uncond_pred) * conditioning.cfg_scale;
+			latents = scheduler.step( &noise_pred, timestep, &latents );
+			if let Some(Inpaint { init, mask, noise }) = &inpaint {
+				// Keep the unmasked area on the noise level of the source for the next step
+				let known = match timesteps.get(i+1) {
+					Some(&next) => scheduler.add_noise( init, noise.shallow_clone(), next ),
+					None => init.shallow_clone(),
+				};
+				latents = &latents * *mask + known * (mask.neg() + 1.);
+			}
+			steps.step()?;
+		}
+		Ok(latents)
+	}
+
+	/// Whether a `width` x `height` image goes through the VAE in tiles. Never for tileable
+	/// requests, circular padding would wrap each tile on itself
+	fn tiled_vae(&self, width:i64, height:i64) -> bool {
+		!self.padding.is_circular() && self.tiled_vae_pixels.map_or( false, |max| width * height > max as i64 )
+	}
+
+	fn decode(&self, latents:&Tensor) -> anyhow::Result<RgbImage> {
+		let latents = latents / VAE_SCALE;
+		let size = latents.size();
+		let image = match self.tiled_vae( size[3] * 8, size[2] * 8 ) {
+			true => tiled_vae::decode( &latents, Tiling::default(), |t| self.vae.decode(t) ),
+			false => self.vae.decode(&latents),
+		};
+		tensor_to_image( &vae_output_to_u8(&image).squeeze_dim(0) )
+	}
+
+	/// Latents of a `[1, 3, h, w]` image tensor
+	fn encode(&self, image:&Tensor) -> Tensor {
+		let size = image.size();
+		let latents = match self.tiled_vae( size[3], size[2] ) {
+			true => tiled_vae::encode( image, Tiling::default(), |t| self.vae.encode(t) ),
+			false => self.vae.encode(image),
+		};
+		latents * VAE_SCALE
+	}
+
+	/// Text embeddings of the chunks, concatenated along the token axis
+	fn encode_chunks(&self, chunks:&[Chunk], device:Device) -> Tensor {
+		let embeddings = chunks.iter().map( |chunk| {
+			let (tokens, weights) = self.tokenizer.chunk_input(chunk);
+			let tokens = Tensor::of_slice(&tokens).view((1, -1)).to(device);
+			apply_weights( self.text_model.forward(&tokens), &weights, device )
+		} ).collect::<Vec<_>>();
+		Tensor::cat( &embeddings, 1 )
+	}
+}
+
+/// Encoded prompts of one sampling run, per range of steps
+struct Conditioning {
+	cond : Vec<Tensor>,
+	uncond : Vec<Tensor>,
+	cond_schedule : Vec<Scheduled>,
+	uncond_schedule : Vec<Scheduled>,
+
+	/// Embeddings of [`GenerationRequest::prompt_blend`], empty without one
+	blend : Vec<Tensor>,
+	blend_schedule : Vec<Scheduled>,
+	blend_weight : f64,
+	cfg_scale : f64,
+
+	/// Steps the schedules were made for
+	steps : usize,
+
+	/// `TI hashes` metadata value
+	embeddings_used : Option<String>,
+}
+
+impl Conditioning {
+	/// Unconditional and conditional embeddings of step `step`, stacked for one UNet call
+	fn at(&self, step:usize) -> Tensor {
+		let cond = &self.cond[ schedule::index_at(&self.cond_schedule, step) ];
+		let cond = match self.blend.is_empty() {
+			true => cond.shallow_clone(),
+			false => slerp( self.blend_weight, cond, &self.blend[ schedule::index_at(&self.blend_schedule, step) ] ),
+		};
+		Tensor::cat( &[ &self.uncond[ schedule::index_at(&self.uncond_schedule, step) ], &cond ], 0 )
+	}
+}
+
+/// Steps run so far over every pass of every image of a request
+struct StepCounter<'p> {
+	done : usize,
+	total : usize,
+	progress : &'p mut dyn FnMut(Progress) -> ControlFlow<()>,
+}
+
+impl StepCounter<'_> {
+	fn step(&mut self) -> anyhow::Result<()> {
+		self.done += 1;
+		report( self.progress, Progress::Step { step : self.done, total : self.total } )
+	}
+}
+
+/// Source latents and mask of an inpainting run
+struct Inpaint<'t> {
+	init : &'t Tensor,
+	mask : &'t Tensor,
+
+	/// Noise the run started from, to noise the source the same way
+	noise : &'t Tensor,
+}
+
+/// Spherical interpolation from `a` at 0 to `b` at 1. Keeps the norm of noise, where a linear
+/// mix of two noises would be too smooth. Linear when `a` and `b` are almost parallel
+pub(crate) fn slerp(t:f64, a:&Tensor, b:&Tensor) -> Tensor {
+	let norm = |t:&Tensor| (t * t).sum(Kind::Float).sqrt().double_value(&[]);
+	let dot = (a * b).sum(Kind::Float).double_value(&[]) / (norm(a) * norm(b));
+	if dot.abs() > 0.9995 {
+		return a * (1. - t) + b * t
+	}
+	let omega = dot.acos();
+	a * (((1. - t) * omega).sin() / omega.sin()) + b * ((t * omega).sin() / omega.sin())
+}
+
+/// Scale each token embedding of `[1, n, dim]` by its weight, then restore the overall mean
+/// so emphasis changes the balance between tokens and not the strength of the whole prompt
+fn apply_weights(embeddings:Tensor, weights:&[f32], device:Device) -> Tensor {
+	if weights.iter().all( |&w| w == 1. ) {
+		return embeddings
+	}
+	let original_mean = embeddings.mean(Kind::Float);
+	let weights = Tensor::of_slice(weights).view((1, -1, 1)).to(device);
+	let weighted = embeddings * weights;
+	let mean = weighted.mean(Kind::Float);
+	weighted * (original_mean / mean)
+}
+
+pub struct DiffusersBackend {
+	config : DiffusersConfig,
+	device : Device,
+	pipeline : Option<Pipeline>,
+
+	/// Looked up by name for the hi-res pass
+	upscalers : Upscalers,
+}
+
+impl DiffusersBackend {
+	pub fn new(config:DiffusersConfig) -> Self {
+		Self { config, device : Device::Cpu, pipeline : None, upscalers : Upscalers::default() }
+	}
+
+	pub fn with_upscalers(self, upscalers:Upscalers) -> Self {
+		Self { upscalers, ..self }
+	}
+
+	pub fn config(&self) -> &DiffusersConfig {
+		&self.config
+	}
+}
+
+/// `[3, h, w]` uint8 tensor to image
+pub(crate) fn tensor_to_image(t:&Tensor) -> anyhow::Result<RgbImage> {
+	let (_, h, w) = t.size3()?;
+	let pixels = Vec::<u8>::try_from( t.permute(&[1, 2, 0]).contiguous().flatten(0, -1) )?;
+	RgbImage::from_raw( w as u32, h as u32, pixels ).context("unexpected tensor size")
+}
+
+/// Image to `[1, 3, h, w]` float tensor in `[-1, 1]`, resized to `width` x `height`
+pub(crate) fn image_to_tensor(image:&RgbImage, width:u32, height:u32, device:Device) -> Tensor {
+	let t = Tensor::of_slice( &image.pixels )
+		.view( (image.height as i64, image.width as i64, 3) )
+		.permute( &[2, 0, 1] )
+		.unsqueeze(0)
+		.to_kind( Kind::Float );
+	let t = if (image.width, image.height) != (width, height) {
+		t.upsample_bilinear2d( &[height as i64, width as i64], false, None, None )
+	} else {
+		t
+	};
+	(t / 255. * 2. - 1.).to(device)
+}
+
+/// Mask to `[1, 1, height, width]` float tensor in `[0, 1]`
+pub(crate) fn mask_to_tensor(mask:&Mask, width:i64, height:i64, device:Device) -> Tensor {
+	let t = Tensor::of_slice( &mask.data )
+		.view( (1, 1, mask.height as i64, mask.width as i64) )
+		.to_kind( Kind::Float ) / 255.;
+	t.adaptive_avg_pool2d( &[height, width] ).to(device)
+}
+
+/// Decoded VAE output in `[-1, 1]` to `[3, h, w]` uint8
+pub(crate) fn vae_output_to_u8(t:&Tensor) -> Tensor {
+	let t = (t / 2 + 0.5).clamp(0., 1.).to_device(Device::Cpu);
+	(t * 255.).to_kind(Kind::Uint8)
+}
+
+impl GenerationBackend for DiffusersBackend {
+	fn name(&self) -> &str {
+		"diffusers"
+	}
+
+	fn generate(&mut self, request:&GenerationRequest, progress:&mut dyn FnMut(Progress) -> ControlFlow<()>) -> anyhow::Result<Vec<GeneratedImage>> {
+		let _guard = tch::no_grad_guard();
+		let device = self.device;
+		let model = request.model.as_ref().unwrap_or( &self.config.model );
+		if self.pipeline.as_ref().map( |p| &p.model ) != Some(model) {
+			report( progress, Progress::Loading )?;
+			// Free the old weights before loading the new ones
+			self.pipeline = None;
+			self.pipeline = Some( Pipeline::load(&self.config, model, device)? );
+		}
+		// LoRA tags are applied here and never reach the tokenizer
+		let (_, lora_tags) = tags::extract_loras( &request.prompt );
+		let wanted = lora_tags.iter()
+			.map( |t| Ok( (t.name.clone(), lora::find( &self.config.loras, &t.name )?, t.weight) ) )
+			.collect::<anyhow::Result<Vec<_>>>()?;
+
+		let pipeline = self.pipeline.as_mut().unwrap();
+		pipeline.sync_embeddings( &self.config.embeddings );
+		pipeline.check_embeddings(request)?;
+		pipeline.loras.apply( &wanted, &pipeline.unet_vs, &pipeline.text_vs )?;
+		pipeline.padding.set_circular( request.tileable );
+		let pipeline = self.pipeline.as_ref().unwrap();
+		let sd_config = self.config.sd_config( model.version, request.width, request.height );
+
+		let mut info = vec![];
+		// The request only names its model when it picked one
+		if request.model.is_none() {
+			info.push( ("Model hash".to_string(), pipeline.model_hash.clone()) );
+			info.push( ("Model".to_string(), model.name.clone()) );
+		}
+		let conditioning = pipeline.conditioning( request.steps, request, device )?;
+		if let Some(used) = &conditioning.embeddings_used {
+			info.push( ("TI hashes".to_string(), used.clone()) );
+		}
+		if !pipeline.loras.applied().is_empty() {
+			let hashes = pipeline.loras.applied().iter().map( |l| format!("{}: {}", l.name, l.hash) ).collect::<Vec<_>>();
+			info.push( ("Lora hashes".to_string(), hashes.join(", ")) );
+		}
+		// Prompt editing is scheduled over the steps of each pass, so the hi-res pass encodes again
+		let hires = match request.hires() {
+			Some(h) => Some( (h, pipeline.conditioning( h.steps_or(request.steps), request, device )?) ),
+			None => None,
+		};
+		// Looked up before the first pass, so an unknown name fails early
+		let hires_upscaler = match request.hires() {
+			Some(h) if !h.is_latent() => Some( self.upscalers.find(&h.upscaler)? ),
+			_ => None,
+		};
+
+		let latent_size = [1, 4, sd_config.height / 8, sd_config.width / 8];
+		let timestep_count = |steps| schedulers::timestep_count( request.sampler, steps, &sd_config.scheduler );
+		let start = request.start_step( timestep_count(request.steps) );
+		let source = match &request.mode {
+			GenerationMode::Txt2Img => None,
+			GenerationMode::Img2Img { source, .. } | GenerationMode::Inpaint { source, .. } => {
+				Some( image_to_tensor( source, request.width, request.height, device ) )
+			}
+		};
+		let latent_mask = match &request.mode {
+			GenerationMode::Inpaint { mask, .. } => Some( mask_to_tensor( mask, latent_size[3], latent_size[2], device ) ),
+			_ => None,
+		};
+
+		let mut steps = StepCounter { done : 0, total : request.steps_per_image(timestep_count) * request.seeds.len(), progress };
+		let mut images = Vec::with_capacity( request.seeds.len() );
+		for &seed in &request.seeds {
+			// Schedulers keep per run state, so every image gets a fresh one
+			let mut scheduler = schedulers::build( request.sampler, request.steps, &sd_config.scheduler );
+			let timesteps = scheduler.timesteps();
+
+			tch::manual_seed( seed as i64 );
+			let noise = Tensor::randn( &latent_size, (Kind::Float, device) );
+			// The VAE samples its posterior, so the source is encoded once seeded, after the noise
+			// to keep that the same as in txt2img
+			let init = source.as_ref().map( |source| pipeline.encode(source) );
+			let noise = match &request.variation {
+				Some(variation) => {
+					tch::manual_seed( variation.seed as i64 );
+					let other = Tensor::randn( &latent_size, (Kind::Float, device) );
+					slerp( variation.strength, &noise, &other )
+				}
+				None => noise,
+			};
+			let latents = match (&init, timesteps.get(start)) {
+				(None, _) => &noise * scheduler.init_noise_sigma(),
+				(Some(init), Some(&t)) => scheduler.add_noise( init, noise.shallow_clone(), t ),
+				(Some(init), None) => init.shallow_clone(),
+			};
+			let inpaint = match (&init, &latent_mask) {
+				(Some(init), Some(mask)) => Some( Inpaint { init, mask, noise : &noise } ),
+				_ => None,
+			};
+			let mut latents = pipeline.denoise( &mut *scheduler, latents, start, &conditioning, inpaint, &mut steps )?;
+
+			if let Some((hires, conditioning)) = &hires {
+				let (width, height) = hires.target_size( request.width, request.height );
+				let upscaled = match &hires_upscaler {
+					None => latents.upsample_bilinear2d( &[height as i64 / 8, width as i64 / 8], false, None, None ),
+					Some(upscaler) => {
+						let image = upscaler.upscale( &pipeline.decode(&latents)?, width, height )?;
+						pipeline.encode( &image_to_tensor( &image, width, height, device ) )
+					}
+				};
+				let steps = hires.steps_or(request.steps);
+				let hires_config = self.config.sd_config( model.version, width, height );
+				let mut scheduler = schedulers::build( request.sampler, steps, &hires_config.scheduler );
+				let timesteps = scheduler.timesteps();
+				let start = img2img_start_step( timesteps.len(), hires.strength );
+				let noise = Tensor::randn( &upscaled.size(), (Kind::Float, device) );
+				let latents_start = match timesteps.get(start) {
+					Some(&t) => scheduler.add_noise( &upscaled, noise, t ),
+					None => upscaled,
+				};
+				latents = pipeline.denoise( &mut *scheduler, latents_start, start, conditioning, None, &mut steps )?;
+			}
+
+			report( steps.progress, Progress::Decoding )?;
+			let image = pipeline.decode(&latents)?;
+			let image = match &request.mode {
+				GenerationMode::Inpaint { source, mask, .. } => mask.composite( source, &image ),
+				_ => image,
+			};
+			images.push( GeneratedImage { image, seed, info : info.clone() } );
+		}
+		Ok(images)
+	}
+}
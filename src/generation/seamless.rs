@@ -0,0 +1,63 @@
+/// Seamless tiling.
+///
+/// The UNet and VAE are built from [`Conv2d`]s that share one [`Padding`] switch. With it on,
+/// every convolution pads its input with the opposite edges instead of zeros, like torch's
+/// `padding_mode="circular"`, so features flow across the borders and the image wraps around.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use tch::{nn, Tensor};
+
+/// Padding of every [`Conv2d`] of a model, switched per request
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Padding(Arc<AtomicBool>);
+
+impl Padding {
+	pub fn set_circular(&self, circular:bool) {
+		self.0.store( circular, Ordering::Relaxed );
+	}
+
+	pub fn is_circular(&self) -> bool {
+		self.0.load(Ordering::Relaxed)
+	}
+}
+
+/// `[n, c, h, w]` with rows and columns of the opposite edges added `[top, bottom, left, right]`
+pub(crate) fn pad_circular(t:&Tensor, [top, bottom, left, right]:[i64;4]) -> Tensor {
+	let wrap = |t:Tensor, dim:i64, before:i64, after:i64| {
+		let len = t.size()[dim as usize];
+		Tensor::cat( &[t.narrow( dim, len - before, before ), t.shallow_clone(), t.narrow( dim, 0, after )], dim )
+	};
+	wrap( wrap( t.shallow_clone(), 2, top, bottom ), 3, left, right )
+}
+
+/// Square 2D convolution with the weight names of `nn::conv2d`
+#[derive(Debug)]
+pub(crate) struct Conv2d {
+	ws : Tensor,
+	bs : Tensor,
+	stride : i64,
+	padding : i64,
+	mode : Padding,
+}
+
+pub(crate) fn conv2d(vs:nn::Path, in_channels:i64, out_channels:i64, kernel:i64, stride:i64, padding:i64, mode:&Padding) -> Conv2d {
+	Conv2d {
+		ws : vs.zeros( "weight", &[out_channels, in_channels, kernel, kernel] ),
+		bs : vs.zeros( "bias", &[out_channels] ),
+		stride,
+		padding,
+		mode : mode.clone(),
+	}
+}
+
+impl nn::Module for Conv2d {
+	fn forward(&self, xs:&Tensor) -> Tensor {
+		let (xs, padding) = match self.mode.is_circular() {
+			true => (pad_circular( xs, [self.padding; 4] ), 0),
+			false => (xs.shallow_clone(), self.padding),
+		};
+		xs.conv2d( &self.ws, Some(&self.bs), &[self.stride; 2], &[padding; 2], &[1, 1], 1 )
+	}
+}
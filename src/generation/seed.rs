@@ -0,0 +1,175 @@
+use std::fmt;
+
+use super::{GenerationRequest, Variation};
+
+/// Most variations one click may queue
+pub const MAX_VARIATIONS:usize = 100;
+
+/// How the per image seeds of a batch run are picked
+#[derive(Debug, Default, PartialEq, Clone, Copy)]
+pub enum SeedMode {
+	/// Every image uses the given seed
+	Fixed,
+
+	/// Every image gets a new random seed
+	#[default]
+	Random,
+
+	/// Image `n` of the run uses `seed + n`
+	Increment,
+
+	/// Every image uses the seed of the last finished image
+	ReuseLast,
+}
+
+impl SeedMode {
+	pub const ALL:[SeedMode;4] = [SeedMode::Fixed, SeedMode::Random, SeedMode::Increment, SeedMode::ReuseLast];
+
+	/// Whether the seed typed by the user is used
+	pub fn uses_seed(&self) -> bool {
+		matches!( self, Self::Fixed | Self::Increment )
+	}
+}
+
+impl fmt::Display for SeedMode {
+	fn fmt(&self, f:&mut fmt::Formatter<'_>) -> fmt::Result {
+		f.write_str( match self {
+			Self::Fixed => "Fixed",
+			Self::Random => "Random",
+			Self::Increment => "Increment",
+			Self::ReuseLast => "Reuse last",
+		} )
+	}
+}
+
+/// Random seed in the 32 bit range other SD frontends use
+pub fn random_seed() -> u64 {
+	rand::random::<u32>() as u64
+}
+
+/// splitmix64. Small and stable across platforms and crate versions, so a seed always makes
+/// the same picks
+pub struct SplitMix(u64);
+
+impl SplitMix {
+	pub fn new(seed:u64) -> Self {
+		Self(seed)
+	}
+
+	pub fn next_u64(&mut self) -> u64 {
+		self.0 = self.0.wrapping_add(0x9E3779B97F4A7C15);
+		let mut z = self.0;
+		z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+		z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+		z ^ (z >> 31)
+	}
+}
+
+/// Split a run into `batch_count` jobs of `batch_size` images, each with its exact seeds.
+///
+/// `template.seeds` is ignored. `seed` is the typed seed and `last_seed` the seed of the last
+/// finished image. [`SeedMode::ReuseLast`] uses `seed` when there is no finished image yet.
+pub fn plan_batches(
+	template:&GenerationRequest,
+	mode:SeedMode,
+	seed:u64,
+	last_seed:Option<u64>,
+	batch_count:usize,
+	batch_size:usize,
+	mut random:impl FnMut() -> u64,
+) -> Vec<GenerationRequest> {
+	let mut n = 0u64;
+	let mut next = move || {
+		let s = match mode {
+			SeedMode::Fixed => seed,
+			SeedMode::Random => random(),
+			SeedMode::Increment => seed.wrapping_add(n),
+			SeedMode::ReuseLast => last_seed.unwrap_or(seed),
+		};
+		n += 1;
+		s
+	};
+
+	(0..batch_count.max(1)).map( |_| GenerationRequest {
+		seeds : (0..batch_size.max(1)).map( |_| next() ).collect(),
+		..template.clone()
+	} ).collect()
+}
+
+/// `count` single image jobs of `template`'s first seed mixed with `variation_seed`,
+/// at strengths `step`, `2*step` and so on, up to 1
+pub fn variations(template:&GenerationRequest, variation_seed:u64, count:usize, step:f64) -> Result<Vec<GenerationRequest>, String> {
+	if !(1..=MAX_VARIATIONS).contains(&count) {
+		return Err( format!("{count} variations, between 1 and {MAX_VARIATIONS} are allowed") )
+	}
+	if !(step > 0. && step <= 1.) {
+		return Err( "variation step must be between 0 and 1".to_string() )
+	}
+	Ok( (1..=count).map( |n| GenerationRequest {
+		variation : Some( Variation { seed : variation_seed, strength : (step * n as f64).min(1.) } ),
+		..template.with_seed( template.seed() )
+	} ).collect() )
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/// Seeds of each job planned with `mode`, drawing 100, 101... as random seeds
+	fn plan(mode:SeedMode, last_seed:Option<u64>, batch_count:usize, batch_size:usize) -> Vec<Vec<u64>> {
+		let mut random = 100..;
+		let template = GenerationRequest { seeds : vec![7], ..Default::default() };
+		plan_batches( &template, mode, 42, last_seed, batch_count, batch_size, move || random.next().unwrap() )
+			.into_iter()
+			.map( |job| job.seeds )
+			.collect()
+	}
+
+	#[test]
+	fn fixed() {
+		assert_eq!( plan( SeedMode::Fixed, Some(5), 2, 2 ), [[42, 42], [42, 42]] );
+	}
+
+	#[test]
+	fn random() {
+		assert_eq!( plan( SeedMode::Random, Some(5), 2, 2 ), [[100, 101], [102, 103]] );
+	}
+
+	#[test]
+	fn increment_continues_across_batches() {
+		assert_eq!( plan( SeedMode::Increment, None, 3, 2 ), [[42, 43], [44, 45], [46, 47]] );
+		assert_eq!( plan( SeedMode::Increment, None, 1, 1 ), [[42]] );
+	}
+
+	#[test]
+	fn reuse_last() {
+		assert_eq!( plan( SeedMode::ReuseLast, Some(5), 2, 1 ), [[5], [5]] );
+		assert_eq!( plan( SeedMode::ReuseLast, None, 1, 2 ), [[42, 42]] );
+	}
+
+	#[test]
+	fn empty_counts_make_one_image() {
+		assert_eq!( plan( SeedMode::Fixed, None, 0, 0 ), [[42]] );
+	}
+
+	#[test]
+	fn variation_strengths_stop_at_one() {
+		let template = GenerationRequest { seeds : vec![7, 8], ..Default::default() };
+		let jobs = variations( &template, 99, 4, 0.3 ).unwrap();
+		let strengths = jobs.iter().map( |j| j.variation.unwrap().strength ).collect::<Vec<_>>();
+		assert_eq!( strengths.len(), 4 );
+		assert!( (strengths[2] - 0.9).abs() < 1e-9 );
+		assert_eq!( strengths[3], 1. );
+		assert!( jobs.iter().all( |j| j.seeds == [7] && j.variation.unwrap().seed == 99 ) );
+	}
+
+	#[test]
+	fn variation_counts_and_steps_are_checked() {
+		let template = GenerationRequest::default();
+		assert!( variations( &template, 1, 0, 0.1 ).is_err() );
+		assert!( variations( &template, 1, MAX_VARIATIONS + 1, 0.1 ).is_err() );
+		assert!( variations( &template, 1, 4, 0. ).is_err() );
+		assert!( variations( &template, 1, 4, f64::NAN ).is_err() );
+		assert_eq!( variations( &template, 1, MAX_VARIATIONS, 0.01 ).unwrap().len(), MAX_VARIATIONS );
+	}
+}
@@ -0,0 +1,90 @@
+/// Textual inversion vectors.
+///
+/// Files are read with `tch`. Safetensors files work in every layout we know of : `emb_params`
+/// (A1111 and kohya), `string_to_param.*` and single tensor diffusers files. `.pt` and `.bin`
+/// files only load when they are TorchScript archives. The pickles `torch.save` writes, which is
+/// what most A1111 `.pt` embeddings are, are rejected and have to be converted to safetensors.
+
+use std::path::PathBuf;
+use std::time::SystemTime;
+
+use anyhow::Context;
+use tch::{Kind, Tensor};
+
+use crate::models::file_hash;
+use crate::prompt::embeddings::EmbeddingFile;
+
+pub(crate) struct Embedding {
+	pub name : String,
+
+	/// Short file hash, for the metadata
+	pub hash : String,
+
+	/// `[vectors, dim]`
+	pub vectors : Tensor,
+}
+
+impl Embedding {
+	pub fn load(file:&EmbeddingFile) -> anyhow::Result<Self> {
+		let is_safetensors = file.path.extension().map_or( false, |e| e == "safetensors" );
+		let tensors = match is_safetensors {
+			true => Tensor::read_safetensors(&file.path)?,
+			false => {
+				if is_pickle( &std::fs::read(&file.path)? ) {
+					anyhow::bail!("pickled PyTorch file, only safetensors and TorchScript embeddings load, convert it to .safetensors")
+				}
+				Tensor::load_multi(&file.path).context("not a TorchScript archive, convert it to .safetensors")?
+			}
+		};
+		let vectors = tensors.iter()
+			.find( |(k, _)| k == "emb_params" || k.starts_with("string_to_param") || k == "*" )
+			.or( match tensors.as_slice() { [single] => Some(single), _ => None } )
+			.map( |(_, t)| t.to_kind(Kind::Float) )
+			.context("no embedding vectors in file")?;
+		let vectors = match vectors.dim() {
+			1 => vectors.unsqueeze(0),
+			2 => vectors,
+			_ => anyhow::bail!( "unexpected embedding shape {:?}", vectors.size() ),
+		};
+		Ok( Self { name : file.name.clone(), hash : file_hash(&file.path)?, vectors } )
+	}
+
+	pub fn dim(&self) -> i64 {
+		self.vectors.size()[1]
+	}
+
+	pub fn count(&self) -> usize {
+		self.vectors.size()[0] as usize
+	}
+}
+
+/// Whether `bytes` are a `torch.save` pickle rather than a TorchScript archive. Old pickles start
+/// with the pickle protocol opcode, new ones are zip files like TorchScript but without its
+/// `constants.pkl`
+fn is_pickle(bytes:&[u8]) -> bool {
+	let has_constants = bytes.windows( b"constants.pkl".len() ).any( |w| w == b"constants.pkl" );
+	bytes.first() == Some(&0x80) || (bytes.starts_with(b"PK\x03\x04") && !has_constants)
+}
+
+/// Files and modification times, to tell when the embeddings directory changed
+pub(crate) type Signature = Vec<(PathBuf, Option<SystemTime>)>;
+
+pub(crate) fn signature(files:&[EmbeddingFile]) -> Signature {
+	files.iter()
+		.map( |f| (f.path.clone(), std::fs::metadata(&f.path).and_then( |m| m.modified() ).ok()) )
+		.collect()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn pickles() {
+		// Legacy `torch.save`, protocol 2
+		assert!( is_pickle( b"\x80\x02\x8a\x0alz" ) );
+		assert!( is_pickle( b"PK\x03\x04\x00\x00archive/data.pklPK\x01\x02archive/data.pkl" ) );
+		assert!( !is_pickle( b"PK\x03\x04\x00\x00archive/data.pklarchive/constants.pklarchive/code/" ) );
+		assert!( !is_pickle( b"" ) );
+	}
+}
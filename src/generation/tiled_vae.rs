@@ -0,0 +1,227 @@
+/// Tiled VAE decoding and encoding.
+///
+/// The input is cut into overlapping tiles that go through the VAE one at a time, so memory use
+/// depends on the tile size instead of the image size. Tiles are blended back with linear ramps
+/// across the overlaps. The VAE normalizes per tile, so the result is close to a full pass but
+/// not identical.
+
+use tch::{Kind, Tensor};
+
+/// Latent pixels per output pixel side
+const LATENT_FACTOR:i64 = 8;
+
+/// Tile layout, in latent pixels
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct Tiling {
+	pub tile : i64,
+	pub overlap : i64,
+}
+
+impl Default for Tiling {
+	fn default() -> Self {
+		Self { tile : 64, overlap : 16 }
+	}
+}
+
+/// Decode `[1, 4, h, w]` latents tile by tile with `decode`, which maps `[1, 4, th, tw]`
+/// latents to `[1, 3, th*8, tw*8]` pixels
+pub(crate) fn decode(latents:&Tensor, tiling:Tiling, decode:impl FnMut(&Tensor) -> Tensor) -> Tensor {
+	run( latents, tiling, 1, LATENT_FACTOR, decode )
+}
+
+/// Encode `[1, 3, h, w]` pixels tile by tile with `encode`, which maps `[1, 3, th*8, tw*8]`
+/// pixels to `[1, 4, th, tw]` latents
+pub(crate) fn encode(image:&Tensor, tiling:Tiling, encode:impl FnMut(&Tensor) -> Tensor) -> Tensor {
+	run( image, tiling, LATENT_FACTOR, 1, encode )
+}
+
+/// Tiles of `tiling` are `input_unit` input pixels and `output_unit` output pixels per latent pixel
+fn run(input:&Tensor, tiling:Tiling, input_unit:i64, output_unit:i64, mut f:impl FnMut(&Tensor) -> Tensor) -> Tensor {
+	let size = input.size();
+	let (h, w) = (size[2] / input_unit, size[3] / input_unit);
+	let rows = spans( h, tiling.tile, tiling.overlap );
+	let cols = spans( w, tiling.tile, tiling.overlap );
+
+	let mut out = None::<Tensor>;
+	let mut weights = None::<Tensor>;
+	for &(y, th) in &rows {
+		for &(x, tw) in &cols {
+			let tile = input
+				.narrow( 2, y * input_unit, th * input_unit )
+				.narrow( 3, x * input_unit, tw * input_unit );
+			let decoded = f(&tile);
+			let (channels, kind, device) = (decoded.size()[1], decoded.kind(), decoded.device());
+			let out = out.get_or_insert_with( || Tensor::zeros( &[1, channels, h * output_unit, w * output_unit], (kind, device) ) );
+			let weights = weights.get_or_insert_with( || Tensor::zeros( &[1, 1, h * output_unit, w * output_unit], (kind, device) ) );
+
+			// Ramps only on sides that have a neighbour, image borders keep full weight
+			let overlap = tiling.overlap * output_unit;
+			let ramp_y = ramp( th * output_unit, overlap, y > 0, y + th < h ).view( (1, 1, -1, 1) );
+			let ramp_x = ramp( tw * output_unit, overlap, x > 0, x + tw < w ).view( (1, 1, 1, -1) );
+			let mask = (ramp_y * ramp_x).to_kind(kind).to_device(device);
+
+			let (oy, ox) = (y * output_unit, x * output_unit);
+			let mut region = out.narrow( 2, oy, th * output_unit ).narrow( 3, ox, tw * output_unit );
+			region.add_( &(&decoded * &mask) );
+			let mut region = weights.narrow( 2, oy, th * output_unit ).narrow( 3, ox, tw * output_unit );
+			region.add_(&mask);
+		}
+	}
+	match (out, weights) {
+		(Some(out), Some(weights)) => out / weights,
+		_ => f(input),
+	}
+}
+
+/// `(start, len)` of tiles covering `0..len`. The last tile is moved back to end on `len`,
+/// overlapping its neighbour more than `overlap`
+fn spans(len:i64, tile:i64, overlap:i64) -> Vec<(i64, i64)> {
+	if len <= tile {
+		return vec![ (0, len) ]
+	}
+	let stride = (tile - overlap).max(1);
+	let mut out = vec![];
+	let mut start = 0;
+	while start + tile < len {
+		out.push( (start, tile) );
+		start += stride;
+	}
+	out.push( (len - tile, tile) );
+	out
+}
+
+/// Weights along one side of a tile : rising over the first `overlap` pixels when `rise`,
+/// falling over the last `overlap` pixels when `fall`. Never zero, so every pixel has a weight
+fn ramp(len:i64, overlap:i64, rise:bool, fall:bool) -> Tensor {
+	let overlap = overlap.min(len).max(1) as f64;
+	let weights = (0..len).map( |i| {
+		let from_start = if rise { ((i as f64 + 0.5) / overlap).min(1.) } else { 1. };
+		let from_end = if fall { (((len - i) as f64 - 0.5) / overlap).min(1.) } else { 1. };
+		(from_start * from_end) as f32
+	} ).collect::<Vec<_>>();
+	Tensor::of_slice(&weights).to_kind(Kind::Float)
+}
+
+#[cfg(test)]
+mod tests {
+	use diffusers::models::vae::AutoEncoderKLConfig;
+	use tch::{nn, Device};
+
+	use super::*;
+	use crate::generation::seamless::Padding;
+	use crate::generation::vae::Vae;
+
+	/// Largest difference allowed between tiled and untiled results
+	const TOLERANCE:f64 = 1e-4;
+
+	fn max_difference(a:&Tensor, b:&Tensor) -> f64 {
+		(a - b).abs().max().double_value(&[])
+	}
+
+	#[test]
+	fn spans_cover_every_pixel() {
+		for len in 1..200 {
+			for (tile, overlap) in [(64, 16), (32, 8), (16, 0), (8, 8)] {
+				let spans = spans( len, tile, overlap );
+				assert_eq!( spans[0].0, 0 );
+				let (start, size) = *spans.last().unwrap();
+				assert_eq!( start + size, len );
+				for pair in spans.windows(2) {
+					let ((a, a_len), (b, _)) = (pair[0], pair[1]);
+					assert!( b > a && b <= a + a_len, "gap between {:?} and {:?}", pair[0], pair[1] );
+				}
+				assert!( spans.iter().all( |&(start, size)| start >= 0 && size == tile.min(len) ) );
+			}
+		}
+	}
+
+	#[test]
+	fn ramps_never_zero() {
+		let ramp = ramp( 64, 16, true, true );
+		assert!( ramp.min().double_value(&[]) > 0. );
+		assert_eq!( ramp.double_value(&[32]), 1. );
+	}
+
+	fn upsample(t:&Tensor) -> Tensor {
+		let size = t.size();
+		t.upsample_nearest2d( &[size[2] * LATENT_FACTOR, size[3] * LATENT_FACTOR], None, None )
+	}
+
+	#[test]
+	fn tiled_decode_matches_untiled() {
+		let latents = Tensor::randn( &[1, 4, 40, 72], (Kind::Float, tch::Device::Cpu) );
+		let full = upsample(&latents);
+		let tiled = decode( &latents, Tiling { tile : 16, overlap : 4 }, upsample );
+		assert_eq!( tiled.size(), full.size() );
+		assert!( max_difference( &tiled, &full ) < TOLERANCE );
+	}
+
+	#[test]
+	fn tiled_encode_matches_untiled() {
+		let image = Tensor::randn( &[1, 3, 200, 136], (Kind::Float, tch::Device::Cpu) );
+		let pool = |t:&Tensor| t.avg_pool2d( &[LATENT_FACTOR, LATENT_FACTOR], &[LATENT_FACTOR, LATENT_FACTOR], &[0, 0], false, true, None );
+		let full = pool(&image);
+		let tiled = encode( &image, Tiling { tile : 8, overlap : 2 }, pool );
+		assert_eq!( tiled.size(), full.size() );
+		assert!( max_difference( &tiled, &full ) < TOLERANCE );
+	}
+
+	#[test]
+	fn small_input_is_one_tile() {
+		let latents = Tensor::randn( &[1, 4, 8, 8], (Kind::Float, tch::Device::Cpu) );
+		let mut calls = 0;
+		let out = decode( &latents, Tiling::default(), |t| { calls += 1; upsample(t) } );
+		assert_eq!( calls, 1 );
+		assert!( max_difference( &out, &upsample(&latents) ) < TOLERANCE );
+	}
+
+	/// Autoencoder with SD's 8x factor but few channels, and random convolution weights
+	fn small_vae() -> (nn::VarStore, Vae) {
+		tch::manual_seed(0);
+		let vs = nn::VarStore::new(Device::Cpu);
+		let config = AutoEncoderKLConfig { block_out_channels : vec![16, 16, 32, 32], layers_per_block : 1, latent_channels : 4, norm_num_groups : 8 };
+		let vae = Vae::new( vs.root(), &config, &Padding::default() );
+		// Convolutions start at zero, scaled like torch's default initialisation
+		for (_, mut var) in vs.variables() {
+			if var.dim() == 4 {
+				let fan_in = (var.numel() / var.size()[0] as usize) as f64;
+				var.copy_( &(var.randn_like() / fan_in.sqrt()) );
+			}
+		}
+		(vs, vae)
+	}
+
+	/// Group norms see only their tile and the tile borders cut the receptive field, so tiled
+	/// results differ from a full pass. Overlap and blending keep the mean difference under this
+	/// fraction of the spread of the full result
+	const VAE_TOLERANCE:f64 = 0.1;
+
+	fn relative_difference(tiled:&Tensor, full:&Tensor) -> f64 {
+		(tiled - full).abs().mean(Kind::Float).double_value(&[]) / full.std(true).double_value(&[])
+	}
+
+	#[test]
+	fn tiled_vae_decode_is_close_to_full() {
+		let _guard = tch::no_grad_guard();
+		let (_vs, vae) = small_vae();
+		let latents = Tensor::randn( &[1, 4, 32, 40], (Kind::Float, Device::Cpu) );
+		let full = vae.decode(&latents);
+		let tiled = decode( &latents, Tiling { tile : 24, overlap : 8 }, |t| vae.decode(t) );
+		assert_eq!( tiled.size(), full.size() );
+		let difference = relative_difference( &tiled, &full );
+		assert!( difference < VAE_TOLERANCE, "{difference}" );
+	}
+
+	#[test]
+	fn tiled_vae_encode_is_close_to_full() {
+		let _guard = tch::no_grad_guard();
+		let (_vs, vae) = small_vae();
+		let image = Tensor::rand( &[1, 3, 256, 320], (Kind::Float, Device::Cpu) ) * 2. - 1.;
+		// The mean, the sampled noise differs per tile anyway
+		let full = vae.posterior(&image).0;
+		let tiled = encode( &image, Tiling { tile : 24, overlap : 8 }, |t| vae.posterior(t).0 );
+		assert_eq!( tiled.size(), full.size() );
+		let difference = relative_difference( &tiled, &full );
+		assert!( difference < VAE_TOLERANCE, "{difference}" );
+	}
+}
@@ -0,0 +1,294 @@
+/// Conditional UNet of Stable Diffusion.
+///
+/// Same layers and weight names as `diffusers`' `UNet2DConditionModel`, which keeps its convolutions
+/// private. Built here on [`Conv2d`] so tileable requests can switch them to circular padding.
+/// The cross attention layers have no padding and come from `diffusers` as they are.
+
+use diffusers::models::attention::{SpatialTransformer, SpatialTransformerConfig};
+use diffusers::models::unet_2d::UNet2DConditionModelConfig;
+use tch::{nn, nn::Module, Kind, Tensor};
+
+use super::blocks::{upsample, Norm, Resnet};
+use super::seamless::{conv2d, Conv2d, Padding};
+
+/// Resnets with cross attention after each when `attentions` isn't empty, then a downsampler
+struct DownBlock {
+	resnets : Vec<Resnet>,
+	attentions : Vec<SpatialTransformer>,
+	downsampler : Option<Conv2d>,
+}
+
+impl DownBlock {
+	/// Output and the states the up blocks skip to
+	fn forward(&self, xs:&Tensor, time:&Tensor, context:&Tensor) -> (Tensor, Vec<Tensor>) {
+		let mut xs = xs.shallow_clone();
+		let mut states = vec![];
+		for (i, resnet) in self.resnets.iter().enumerate() {
+			xs = resnet.forward( &xs, Some(time) );
+			if let Some(attention) = self.attentions.get(i) {
+				xs = attention.forward( &xs, Some(context) );
+			}
+			states.push( xs.shallow_clone() );
+		}
+		if let Some(downsampler) = &self.downsampler {
+			xs = xs.apply(downsampler);
+			states.push( xs.shallow_clone() );
+		}
+		(xs, states)
+	}
+}
+
+/// Resnets fed with skip connections, with cross attention after each when `attentions` isn't
+/// empty, then an upsampler
+struct UpBlock {
+	resnets : Vec<Resnet>,
+	attentions : Vec<SpatialTransformer>,
+	upsampler : Option<Conv2d>,
+}
+
+impl UpBlock {
+	/// `skips` are taken from the end, one per resnet
+	fn forward(&self, xs:&Tensor, skips:&[Tensor], time:&Tensor, context:&Tensor, size:Option<(i64, i64)>) -> Tensor {
+		let mut xs = xs.shallow_clone();
+		for (i, resnet) in self.resnets.iter().enumerate() {
+			xs = Tensor::cat( &[&xs, &skips[skips.len() - 1 - i]], 1 );
+			xs = resnet.forward( &xs, Some(time) );
+			if let Some(attention) = self.attentions.get(i) {
+				xs = attention.forward( &xs, Some(context) );
+			}
+		}
+		match &self.upsampler {
+			Some(conv) => upsample( &xs, conv, size ),
+			None => xs,
+		}
+	}
+}
+
+struct MidBlock {
+	first : Resnet,
+	attention : SpatialTransformer,
+	second : Resnet,
+}
+
+pub(crate) struct UNet {
+	config : UNet2DConditionModelConfig,
+	conv_in : Conv2d,
+	time_linear_1 : nn::Linear,
+	time_linear_2 : nn::Linear,
+	down_blocks : Vec<DownBlock>,
+	mid_block : MidBlock,
+	up_blocks : Vec<UpBlock>,
+	conv_norm_out : nn::GroupNorm,
+	conv_out : Conv2d,
+}
+
+impl UNet {
+	pub fn new(vs:nn::Path, in_channels:i64, out_channels:i64, config:UNet2DConditionModelConfig, mode:&Padding) -> Self {
+		let blocks = &config.blocks;
+		let n = blocks.len();
+		let channels = blocks[0].out_channels;
+		let time_channels = channels * 4;
+		let norm = Norm { groups : config.norm_num_groups, eps : config.norm_eps };
+		let resnet = |vs:nn::Path, from:i64, to:i64| Resnet::new( vs, from, to, Some(time_channels), norm, mode );
+		let attention = |vs:nn::Path, block:usize, channels:i64| {
+			let heads = blocks[block].attention_head_dim;
+			let transformer = SpatialTransformerConfig {
+				depth : 1,
+				num_groups : config.norm_num_groups,
+				context_dim : Some(config.cross_attention_dim),
+				// 0 picks a slice size from the head count
+				sliced_attention_size : match config.sliced_attention_size {
+					Some(0) => Some(heads / 2),
+					size => size,
+				},
+				use_linear_projection : config.use_linear_projection,
+			};
+			SpatialTransformer::new( vs, channels, heads, channels / heads, transformer )
+		};
+
+		let vs_down = &vs / "down_blocks";
+		let down_blocks = (0..n).map( |i| {
+			let vs = &vs_down / i;
+			let out = blocks[i].out_channels;
+			let input = if i == 0 { channels } else { blocks[i-1].out_channels };
+			let layers = 0..config.layers_per_block;
+			DownBlock {
+				resnets : layers.clone().map( |l| resnet( &vs / "resnets" / l, if l == 0 { input } else { out }, out ) ).collect(),
+				attentions : match blocks[i].use_cross_attn {
+					true => layers.map( |l| attention( &vs / "attentions" / l, i, out ) ).collect(),
+					false => vec![],
+				},
+				downsampler : (i < n - 1).then( || conv2d( &vs / "downsamplers" / 0 / "conv", out, out, 3, 2, config.downsample_padding, mode ) ),
+			}
+		} ).collect();
+
+		let last = blocks[n-1].out_channels;
+		let vs_mid = &vs / "mid_block";
+		let mid_block = MidBlock {
+			first : resnet( &vs_mid / "resnets" / 0, last, last ).with_output_scale(config.mid_block_scale_factor),
+			attention : attention( &vs_mid / "attentions" / 0, n - 1, last ),
+			second : resnet( &vs_mid / "resnets" / 1, last, last ).with_output_scale(config.mid_block_scale_factor),
+		};
+
+		let vs_up = &vs / "up_blocks";
+		let up_blocks = (0..n).map( |i| {
+			let vs = &vs_up / i;
+			let block = n - 1 - i;
+			let out = blocks[block].out_channels;
+			let previous = if i == 0 { last } else { blocks[block+1].out_channels };
+			// Channels of the skip from the down block below, for the last resnet
+			let skip = blocks[block.saturating_sub(1)].out_channels;
+			let layers = 0..config.layers_per_block + 1;
+			UpBlock {
+				resnets : layers.clone().map( |l| {
+					let input = if l == 0 { previous } else { out };
+					let skip = if l == config.layers_per_block { skip } else { out };
+					resnet( &vs / "resnets" / l, input + skip, out )
+				} ).collect(),
+				attentions : match blocks[block].use_cross_attn {
+					true => layers.map( |l| attention( &vs / "attentions" / l, block, out ) ).collect(),
+					false => vec![],
+				},
+				upsampler : (i < n - 1).then( || conv2d( &vs / "upsamplers" / 0 / "conv", out, out, 3, 1, 1, mode ) ),
+			}
+		} ).collect();
+
+		Self {
+			conv_in : conv2d( &vs / "conv_in", in_channels, channels, 3, 1, 1, mode ),
+			time_linear_1 : nn::linear( &vs / "time_embedding" / "linear_1", channels, time_channels, Default::default() ),
+			time_linear_2 : nn::linear( &vs / "time_embedding" / "linear_2", time_channels, time_channels, Default::default() ),
+			down_blocks,
+			mid_block,
+			up_blocks,
+			conv_norm_out : norm.build( &vs / "conv_norm_out", channels ),
+			conv_out : conv2d( &vs / "conv_out", channels, out_channels, 3, 1, 1, mode ),
+			config,
+		}
+	}
+
+	/// Sinusoidal embedding of `timestep`, `[batch, channels]`
+	fn time_projection(&self, timestep:f64, batch:i64, device:tch::Device) -> Tensor {
+		let half = self.config.blocks[0].out_channels / 2;
+		let exponent = Tensor::arange( half, (Kind::Float, device) ) * (-(10000f64.ln()) / (half as f64 - self.config.freq_shift));
+		let angles = exponent.exp() * timestep;
+		let embedding = match self.config.flip_sin_to_cos {
+			true => Tensor::cat( &[angles.cos(), angles.sin()], -1 ),
+			false => Tensor::cat( &[angles.sin(), angles.cos()], -1 ),
+		};
+		embedding.unsqueeze(0).repeat( &[batch, 1] )
+	}
+
+	/// Noise predicted for `xs` at `timestep`, conditioned on the text embeddings `context`
+	pub fn forward(&self, xs:&Tensor, timestep:f64, context:&Tensor) -> Tensor {
+		let size = xs.size();
+		// Sizes that don't halve evenly down to the last block get the upsampler sizes of the skips
+		let factor = 1 << (self.up_blocks.len() - 1);
+		let odd_size = size[2] % factor != 0 || size[3] % factor != 0;
+		let xs = match self.config.center_input_sample {
+			true => xs * 2. - 1.,
+			false => xs.shallow_clone(),
+		};
+		let time = self.time_projection( timestep, size[0], xs.device() )
+			.apply(&self.time_linear_1)
+			.silu()
+			.apply(&self.time_linear_2);
+
+		let mut xs = xs.apply(&self.conv_in);
+		let mut skips = vec![ xs.shallow_clone() ];
+		for block in &self.down_blocks {
+			let (out, states) = block.forward( &xs, &time, context );
+			skips.extend(states);
+			xs = out;
+		}
+
+		let mid = &self.mid_block;
+		xs = mid.first.forward( &xs, Some(&time) );
+		xs = mid.attention.forward( &xs, Some(context) );
+		xs = mid.second.forward( &xs, Some(&time) );
+
+		for (i, block) in self.up_blocks.iter().enumerate() {
+			let block_skips = skips.split_off( skips.len() - block.resnets.len() );
+			let size = match skips.last() {
+				Some(next) if odd_size && i < self.up_blocks.len() - 1 => Some( (next.size()[2], next.size()[3]) ),
+				_ => None,
+			};
+			xs = block.forward( &xs, &block_skips, &time, context, size );
+		}
+		xs.apply(&self.conv_norm_out).silu().apply(&self.conv_out)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use diffusers::models::unet_2d::{BlockConfig, UNet2DConditionModel};
+	use tch::Device;
+
+	use super::*;
+
+	/// Largest difference allowed between two implementations of the same model
+	const TOLERANCE:f64 = 1e-4;
+
+	/// SD layout at a tiny size : cross attention in every block but the last
+	fn config() -> UNet2DConditionModelConfig {
+		UNet2DConditionModelConfig {
+			center_input_sample : false,
+			flip_sin_to_cos : true,
+			freq_shift : 0.,
+			blocks : vec![
+				BlockConfig { out_channels : 32, use_cross_attn : true, attention_head_dim : 4 },
+				BlockConfig { out_channels : 64, use_cross_attn : true, attention_head_dim : 8 },
+				BlockConfig { out_channels : 64, use_cross_attn : false, attention_head_dim : 8 },
+			],
+			layers_per_block : 1,
+			downsample_padding : 1,
+			mid_block_scale_factor : 1.,
+			norm_num_groups : 8,
+			norm_eps : 1e-5,
+			cross_attention_dim : 16,
+			sliced_attention_size : None,
+			use_linear_projection : false,
+		}
+	}
+
+	/// Randomly initialised `diffusers` UNet and this one with the same weights
+	fn models(padding:&Padding) -> (UNet2DConditionModel, UNet) {
+		tch::manual_seed(0);
+		let reference_vs = nn::VarStore::new(Device::Cpu);
+		let reference = UNet2DConditionModel::new( reference_vs.root(), 4, 4, config() );
+		let mut vs = nn::VarStore::new(Device::Cpu);
+		let unet = UNet::new( vs.root(), 4, 4, config(), padding );
+		assert_eq!( vs.len(), reference_vs.len() );
+		vs.copy(&reference_vs).unwrap();
+		(reference, unet)
+	}
+
+	fn max_difference(a:&Tensor, b:&Tensor) -> f64 {
+		(a - b).abs().max().double_value(&[])
+	}
+
+	#[test]
+	fn matches_diffusers() {
+		let _guard = tch::no_grad_guard();
+		let (reference, unet) = models( &Padding::default() );
+		let xs = Tensor::randn( &[2, 4, 16, 24], (Kind::Float, Device::Cpu) );
+		let context = Tensor::randn( &[2, 7, 16], (Kind::Float, Device::Cpu) );
+		for timestep in [1., 500., 999.] {
+			let difference = max_difference( &unet.forward( &xs, timestep, &context ), &reference.forward( &xs, timestep, &context ) );
+			assert!( difference <= TOLERANCE, "{difference} at timestep {timestep}" );
+		}
+	}
+
+	#[test]
+	fn circular_padding_wraps() {
+		let _guard = tch::no_grad_guard();
+		let padding = Padding::default();
+		let (_, unet) = models(&padding);
+		padding.set_circular(true);
+		let xs = Tensor::randn( &[1, 4, 16, 24], (Kind::Float, Device::Cpu) );
+		let context = Tensor::randn( &[1, 7, 16], (Kind::Float, Device::Cpu) );
+		// A shift by a multiple of the downsampling factor moves the output along with the input
+		let shift = |t:&Tensor| t.roll( &[4, 8], &[2, 3] );
+		let difference = max_difference( &unet.forward( &shift(&xs), 500., &context ), &shift( &unet.forward( &xs, 500., &context ) ) );
+		assert!( difference <= TOLERANCE, "{difference}" );
+	}
+}
@@ -0,0 +1,73 @@
+/// Pixel upscalers.
+///
+/// The [`Upscaler`]s are resampling filters, which work in tiles of the output and stay within a
+/// fixed amount of memory however large the result.
+
+use std::fmt;
+use std::sync::Arc;
+
+use anyhow::Context;
+
+use super::resample::{self, Filter};
+use super::RgbImage;
+
+pub trait Upscaler : Send + Sync {
+	/// Name shown in the UI and written to the metadata
+	fn name(&self) -> &str;
+
+	/// `image` resized to `width` x `height`
+	fn upscale(&self, image:&RgbImage, width:u32, height:u32) -> anyhow::Result<RgbImage>;
+}
+
+/// Upscaler of a resampling filter
+pub struct Resampler {
+	name : &'static str,
+	filter : Filter,
+}
+
+impl Upscaler for Resampler {
+	fn name(&self) -> &str {
+		self.name
+	}
+
+	fn upscale(&self, image:&RgbImage, width:u32, height:u32) -> anyhow::Result<RgbImage> {
+		Ok( resample::resample( image, width, height, &self.filter ) )
+	}
+}
+
+pub const LANCZOS:Resampler = Resampler { name : "Lanczos", filter : resample::LANCZOS3 };
+pub const BICUBIC:Resampler = Resampler { name : "Bicubic", filter : resample::BICUBIC };
+pub const NEAREST:Resampler = Resampler { name : "Nearest", filter : resample::NEAREST };
+
+/// Upscalers to pick from. Provided as a context by `app`
+#[derive(Clone)]
+pub struct Upscalers {
+	list : Arc<Vec<Arc<dyn Upscaler>>>,
+}
+
+impl Default for Upscalers {
+	fn default() -> Self {
+		let builtin:[Arc<dyn Upscaler>;3] = [Arc::new(LANCZOS), Arc::new(BICUBIC), Arc::new(NEAREST)];
+		Self { list : Arc::new( builtin.into() ) }
+	}
+}
+
+impl Upscalers {
+	pub fn all(&self) -> &[Arc<dyn Upscaler>] {
+		&self.list
+	}
+
+	/// Upscaler called `name`, ignoring case
+	pub fn find(&self, name:&str) -> anyhow::Result<Arc<dyn Upscaler>> {
+		self.list.iter()
+			.find( |u| u.name().eq_ignore_ascii_case( name.trim() ) )
+			.cloned()
+			.with_context( || format!("Unknown upscaler '{name}'") )
+	}
+}
+
+impl fmt::Debug for Upscalers {
+	fn fmt(&self, f:&mut fmt::Formatter<'_>) -> fmt::Result {
+		f.debug_list().entries( self.list.iter().map( |u| u.name() ) ).finish()
+	}
+}
@@ -0,0 +1,229 @@
+/// Stable Diffusion autoencoder.
+///
+/// Same layers and weight names as `diffusers`' `AutoEncoderKL`, built on [`Conv2d`] like the
+/// [`UNet`](super::unet::UNet) so tileable requests can switch it to circular padding.
+
+use diffusers::models::attention::{AttentionBlock, AttentionBlockConfig};
+use diffusers::models::vae::AutoEncoderKLConfig;
+use tch::{nn, nn::Module, Tensor};
+
+use super::blocks::{upsample, Norm, Resnet};
+use super::seamless::{self, conv2d, Conv2d, Padding};
+
+const EPS:f64 = 1e-6;
+
+struct MidBlock {
+	first : Resnet,
+	attention : AttentionBlock,
+	second : Resnet,
+}
+
+impl MidBlock {
+	fn new(vs:nn::Path, channels:i64, norm:Norm, mode:&Padding) -> Self {
+		let attention = AttentionBlockConfig { num_head_channels : None, num_groups : norm.groups, rescale_output_factor : 1., eps : norm.eps };
+		Self {
+			first : Resnet::new( &vs / "resnets" / 0, channels, channels, None, norm, mode ),
+			attention : AttentionBlock::new( &vs / "attentions" / 0, channels, attention ),
+			second : Resnet::new( &vs / "resnets" / 1, channels, channels, None, norm, mode ),
+		}
+	}
+
+	fn forward(&self, xs:&Tensor) -> Tensor {
+		let xs = self.first.forward( xs, None ).apply(&self.attention);
+		self.second.forward( &xs, None )
+	}
+}
+
+/// Resnets of one resolution
+fn resnets(vs:nn::Path, layers:i64, input:i64, out:i64, norm:Norm, mode:&Padding) -> Vec<Resnet> {
+	(0..layers).map( |l| Resnet::new( &vs / l, if l == 0 { input } else { out }, out, None, norm, mode ) ).collect()
+}
+
+struct Encoder {
+	conv_in : Conv2d,
+	down_blocks : Vec<(Vec<Resnet>, Option<Conv2d>)>,
+	mid_block : MidBlock,
+	conv_norm_out : nn::GroupNorm,
+	conv_out : Conv2d,
+}
+
+struct Decoder {
+	conv_in : Conv2d,
+	mid_block : MidBlock,
+	up_blocks : Vec<(Vec<Resnet>, Option<Conv2d>)>,
+	conv_norm_out : nn::GroupNorm,
+	conv_out : Conv2d,
+}
+
+pub(crate) struct Vae {
+	encoder : Encoder,
+	decoder : Decoder,
+	quant_conv : Conv2d,
+	post_quant_conv : Conv2d,
+	latent_channels : i64,
+	mode : Padding,
+}
+
+impl Vae {
+	/// 3 channel images, like every Stable Diffusion autoencoder
+	pub fn new(vs:nn::Path, config:&AutoEncoderKLConfig, mode:&Padding) -> Self {
+		let channels = &config.block_out_channels;
+		let n = channels.len();
+		let last = channels[n-1];
+		let layers = config.layers_per_block;
+		let latent = config.latent_channels;
+		let norm = Norm { groups : config.norm_num_groups, eps : EPS };
+
+		let vs_enc = &vs / "encoder";
+		let encoder = Encoder {
+			conv_in : conv2d( &vs_enc / "conv_in", 3, channels[0], 3, 1, 1, mode ),
+			down_blocks : (0..n).map( |i| {
+				let vs = &vs_enc / "down_blocks" / i;
+				let input = if i == 0 { channels[0] } else { channels[i-1] };
+				let out = channels[i];
+				// Padded by hand on the bottom and right before the convolution, see `encode`
+				let downsampler = (i < n - 1).then( || conv2d( &vs / "downsamplers" / 0 / "conv", out, out, 3, 2, 0, mode ) );
+				(resnets( &vs / "resnets", layers, input, out, norm, mode ), downsampler)
+			} ).collect(),
+			mid_block : MidBlock::new( &vs_enc / "mid_block", last, norm, mode ),
+			conv_norm_out : norm.build( &vs_enc / "conv_norm_out", last ),
+			conv_out : conv2d( &vs_enc / "conv_out", last, 2 * latent, 3, 1, 1, mode ),
+		};
+
+		let vs_dec = &vs / "decoder";
+		let decoder = Decoder {
+			conv_in : conv2d( &vs_dec / "conv_in", latent, last, 3, 1, 1, mode ),
+			mid_block : MidBlock::new( &vs_dec / "mid_block", last, norm, mode ),
+			up_blocks : (0..n).map( |i| {
+				let vs = &vs_dec / "up_blocks" / i;
+				let input = if i == 0 { last } else { channels[n-i] };
+				let out = channels[n-1-i];
+				let upsampler = (i < n - 1).then( || conv2d( &vs / "upsamplers" / 0 / "conv", out, out, 3, 1, 1, mode ) );
+				(resnets( &vs / "resnets", layers + 1, input, out, norm, mode ), upsampler)
+			} ).collect(),
+			conv_norm_out : norm.build( &vs_dec / "conv_norm_out", channels[0] ),
+			conv_out : conv2d( &vs_dec / "conv_out", channels[0], 3, 3, 1, 1, mode ),
+		};
+
+		Self {
+			encoder,
+			decoder,
+			quant_conv : conv2d( &vs / "quant_conv", 2 * latent, 2 * latent, 1, 1, 0, mode ),
+			post_quant_conv : conv2d( &vs / "post_quant_conv", latent, latent, 1, 1, 0, mode ),
+			latent_channels : latent,
+			mode : mode.clone(),
+		}
+	}
+
+	/// Latents of `[n, 3, h, w]` pixels in -1..1, sampled from the encoded distribution and not scaled
+	pub fn encode(&self, xs:&Tensor) -> Tensor {
+		let (mean, log_variance) = self.posterior(xs);
+		let noise = mean.randn_like();
+		mean + (log_variance * 0.5).exp() * noise
+	}
+
+	/// Mean and log variance of the latent distribution of `[n, 3, h, w]` pixels
+	pub fn posterior(&self, xs:&Tensor) -> (Tensor, Tensor) {
+		let e = &self.encoder;
+		let mut xs = xs.apply(&e.conv_in);
+		for (resnets, downsampler) in &e.down_blocks {
+			for resnet in resnets {
+				xs = resnet.forward( &xs, None );
+			}
+			if let Some(conv) = downsampler {
+				xs = match self.mode.is_circular() {
+					true => seamless::pad_circular( &xs, [0, 1, 0, 1] ),
+					false => xs.pad( &[0, 1, 0, 1], "constant", Some(0.) ),
+				}.apply(conv);
+			}
+		}
+		let parameters = e.mid_block.forward(&xs)
+			.apply(&e.conv_norm_out)
+			.silu()
+			.apply(&e.conv_out)
+			.apply(&self.quant_conv);
+		let mean = parameters.narrow( 1, 0, self.latent_channels );
+		let log_variance = parameters.narrow( 1, self.latent_channels, self.latent_channels ).clamp( -30., 20. );
+		(mean, log_variance)
+	}
+
+	/// `[n, 3, h*8, w*8]` pixels in -1..1 of unscaled `[n, 4, h, w]` latents, with SD's four blocks
+	pub fn decode(&self, xs:&Tensor) -> Tensor {
+		let d = &self.decoder;
+		let mut xs = d.mid_block.forward( &xs.apply(&self.post_quant_conv).apply(&d.conv_in) );
+		for (resnets, upsampler) in &d.up_blocks {
+			for resnet in resnets {
+				xs = resnet.forward( &xs, None );
+			}
+			if let Some(conv) = upsampler {
+				xs = upsample( &xs, conv, None );
+			}
+		}
+		xs.apply(&d.conv_norm_out).silu().apply(&d.conv_out)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use diffusers::models::vae::AutoEncoderKL;
+	use tch::{Device, Kind};
+
+	use super::*;
+
+	/// Largest difference allowed between two implementations of the same model
+	const TOLERANCE:f64 = 1e-4;
+
+	fn config() -> AutoEncoderKLConfig {
+		AutoEncoderKLConfig { block_out_channels : vec![32, 64, 64], layers_per_block : 1, latent_channels : 4, norm_num_groups : 8 }
+	}
+
+	/// Randomly initialised `diffusers` autoencoder and this one with the same weights
+	fn models(padding:&Padding) -> (AutoEncoderKL, Vae) {
+		tch::manual_seed(0);
+		let reference_vs = nn::VarStore::new(Device::Cpu);
+		let reference = AutoEncoderKL::new( reference_vs.root(), 3, 3, config() );
+		let mut vs = nn::VarStore::new(Device::Cpu);
+		let vae = Vae::new( vs.root(), &config(), padding );
+		assert_eq!( vs.len(), reference_vs.len() );
+		vs.copy(&reference_vs).unwrap();
+		(reference, vae)
+	}
+
+	fn max_difference(a:&Tensor, b:&Tensor) -> f64 {
+		(a - b).abs().max().double_value(&[])
+	}
+
+	#[test]
+	fn decode_matches_diffusers() {
+		let _guard = tch::no_grad_guard();
+		let (reference, vae) = models( &Padding::default() );
+		let latents = Tensor::randn( &[2, 4, 8, 12], (Kind::Float, Device::Cpu) );
+		let difference = max_difference( &vae.decode(&latents), &reference.decode(&latents) );
+		assert!( difference <= TOLERANCE, "{difference}" );
+	}
+
+	#[test]
+	fn encode_matches_diffusers() {
+		let _guard = tch::no_grad_guard();
+		let (reference, vae) = models( &Padding::default() );
+		let image = Tensor::rand( &[2, 3, 32, 48], (Kind::Float, Device::Cpu) ) * 2. - 1.;
+		// Both sample the posterior with the same noise
+		tch::manual_seed(1);
+		let latents = vae.encode(&image);
+		tch::manual_seed(1);
+		let expected = reference.encode(&image).sample();
+		let difference = max_difference( &latents, &expected );
+		assert!( difference <= TOLERANCE, "{difference}" );
+	}
+
+	#[test]
+	fn circular_padding_wraps() {
+		let _guard = tch::no_grad_guard();
+		let padding = Padding::default();
+		let (_, vae) = models(&padding);
+		padding.set_circular(true);
+		let latents = Tensor::randn( &[1, 4, 8, 12], (Kind::Float, Device::Cpu) );
+		let difference = max_difference( &vae.decode( &latents.roll( &[3], &[3] ) ), &vae.decode(&latents).roll( &[3 * 4], &[3] ) );
+		assert!( difference <= TOLERANCE, "{difference}" );
+	}
+}
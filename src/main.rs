@@ -4,33 +4,156 @@
 )]
 
 use std::borrow::Cow;
+use std::sync::Arc;
 
 use freya::prelude::*;
 
+mod animation;
+mod canvas;
 mod component;
+mod generation;
+mod metadata;
+mod models;
+mod panel;
+mod plot;
+mod prompt;
+mod queue;
 
+use crate::animation::Animations;
+use crate::canvas::OpenCanvas;
 use crate::component::prelude::*;
+use crate::generation::{GenerationBackend, GenerationRequest};
+use crate::generation::mock::MockBackend;
+use crate::generation::SdVersion;
+use crate::generation::upscale::Upscalers;
+use crate::generation::sd::{DiffusersBackend, DiffusersConfig};
+use crate::models::ModelRegistry;
+use crate::panel::prelude::*;
+use crate::plot::Plots;
+use crate::prompt::clip::{ClipTokenizer, VOCAB_FILE};
+use crate::prompt::dynamic::Wildcards;
+use crate::queue::{JobQueue, QueueEvent};
 
 fn main() {
     launch(app);
 }
 
 pub fn make_item<'a,T>( index:usize, cx:Scope<'a, T>) -> Element<'a> {
+    let outputs = cx.consume_context::<Outputs>()?;
+    let item = outputs.get(index)?;
+    let image_data = bytes_to_data(cx, &item.png);
+    let border = if outputs.selected_index() == Some(index) { "rgb(255,221,210)" } else { "transparent" };
     render!( rect {
-        label { "{index}" }
+        width : "100%",
+        height : "100%",
+        padding : "2",
+        background : border,
+        onclick : move |_| outputs.select(index),
+        image {
+            image_data : image_data,
+            width : "100%",
+            height : "100%",
+        }
     } )
 }
 
+/// Diffusers pipeline when `SD_MODELS_DIR` points at the weights, the mock backend otherwise
+fn default_backend(upscalers:Upscalers) -> Box<dyn GenerationBackend> {
+    match std::env::var_os("SD_MODELS_DIR") {
+        Some(dir) => Box::new( DiffusersBackend::new( DiffusersConfig::from_dir(dir, SdVersion::V1_5) ).with_upscalers(upscalers) ),
+        None => Box::new( MockBackend::default().with_upscalers(upscalers) ),
+    }
+}
+
+/// `SD_MODELS_DIR` or `./models`
+fn models_dir() -> std::path::PathBuf {
+    std::env::var_os("SD_MODELS_DIR").map_or_else( || "models".into(), Into::into )
+}
+
+/// `SD_WILDCARDS_DIR` or `./wildcards`
+fn wildcards_dir() -> std::path::PathBuf {
+    std::env::var_os("SD_WILDCARDS_DIR").map_or_else( || "wildcards".into(), Into::into )
+}
+
+/// `SD_OUTPUTS_DIR` or `./outputs`
+fn outputs_dir() -> std::path::PathBuf {
+    std::env::var_os("SD_OUTPUTS_DIR").map_or_else( || "outputs".into(), Into::into )
+}
+
 fn app(cx: Scope) -> Element {
     use_init_focus(cx);
-    let mut value = use_state(cx, String::new);
-    let mut times = use_state(cx, || 1);
     let mut item_size = use_state(cx, || 50f32);
+    use_shared_state_provider(cx, FormValues::default);
+    let outputs = cx.use_hook(|| cx.provide_context(Outputs::new(outputs_dir(), cx.schedule_update())) );
+    cx.use_hook(|| {
+        let models = cx.provide_context( Models::new( ModelRegistry::open( models_dir() ), cx.schedule_update() ) );
+        models.rescan();
+    });
+    cx.use_hook(|| cx.provide_context( Wildcards::new( wildcards_dir() ) ) );
+    let upscalers = cx.use_hook(|| cx.provide_context( Upscalers::default() ) );
+    cx.use_hook(|| {
+        // Only used to show how prompts are tokenized, the form works without it
+        match ClipTokenizer::load( &models_dir().join(VOCAB_FILE), SdVersion::V1_5 ) {
+            Ok(tokenizer) => { cx.provide_context( Arc::new(tokenizer) ); }
+            Err(e) => eprintln!("no CLIP tokenizer : {e}"),
+        }
+    });
+    let plots = cx.use_hook(|| cx.provide_context( Plots::default() ) );
+    let animations = cx.use_hook(|| cx.provide_context( Animations::default() ) );
+    let canvas = cx.use_hook(|| cx.provide_context( OpenCanvas::default() ) );
+    let queue = cx.use_hook(|| {
+        let (outputs, plots, animations, canvas, update) = (outputs.clone(), plots.clone(), animations.clone(), canvas.clone(), cx.schedule_update());
+        cx.provide_context( JobQueue::spawn( default_backend( upscalers.clone() ), move |event| {
+            let (grid, walk) = match event {
+                QueueEvent::Finished { id, request, images } => {
+                    let grid = plots.finished( id, images.first() );
+                    let walk = animations.finished( id, images.first(), &outputs_dir() );
+                    canvas.finished( id, images.first() );
+                    for image in images {
+                        if let Err(e) = outputs.keep( OutputImage::new(image, request.clone()) ) {
+                            eprintln!("failed to save output : {e}");
+                        }
+                    }
+                    (grid, walk)
+                }
+                QueueEvent::Dropped { id } => {
+                    canvas.finished( id, None );
+                    (plots.finished( id, None ), animations.finished( id, None, &outputs_dir() ))
+                }
+                QueueEvent::Changed => (None, None),
+            };
+            if let Some(Err(e)) = walk {
+                eprintln!("failed to save walk : {e}");
+            }
+            // Finished plots open in the gallery
+            if let Some((image, request)) = grid {
+                if let Err(e) = outputs.keep( OutputImage::new(image, request) ) {
+                    eprintln!("failed to save output : {e}");
+                }
+                outputs.select( outputs.len() - 1 );
+            }
+            update();
+        }) )
+    });
 
     let (node_ref, node) = use_node(cx);
+    let values = use_shared_state::<FormValues>(cx).unwrap();
+    let models = cx.consume_context::<Models>();
 
-    let values = cx.use_hook(|| vec!["A".to_string(), "B".to_string(), "C".to_string()]);
-    let selected_dropdown = use_state(cx, || "A".to_string());
+    let onsubmit = move |jobs:Vec<GenerationRequest>| {
+        jobs.into_iter().for_each( |request| { queue.push(request); } );
+    };
+
+    // Dropped PNGs fill the form with their generation parameters
+    let onfiledrop = move |e:FileEvent| {
+        let Some(path) = e.file_path.clone() else { return };
+        let models = models.as_ref().map( Models::model_sets ).unwrap_or_default();
+        let result = std::fs::read(&path).map_err( |e| e.to_string() )
+            .and_then( |bytes| values.write().import_png( &bytes, &models ) );
+        if let Err(e) = result {
+            eprintln!("can't import '{}' : {e}", path.display());
+        }
+    };
 
     render!(
         rect {
@@ -39,6 +162,7 @@ fn app(cx: Scope) -> Element {
             background: "rgb(0, 109, 119)",
             direction: "vertical",
             reference : node_ref,
+            onfiledrop : onfiledrop,
             rect {
                 width : "100%",
                 height : "100%",
@@ -46,60 +170,19 @@ fn app(cx: Scope) -> Element {
                 Split {
                     initial_size : 200,
                     direction : SplitDirection::Horizontal,
-                    first_child : render!( rect {
-                        width:"100%",
-                        height:"100%",
-                        Detail {
-                            summary : Cow::Borrowed("Local Device Info"),
-                            Detail {
-                                depth : 1,
-                                summary : Cow::Borrowed("Summer Time!"),
-                                Detail {
-                                    depth : 2,
-                                    summary : Cow::Borrowed("Hello"),
-                                }
-                            }
-                        }
-                        HR {}
-                    }),
+                    first_child : render!( QueuePanel {} ),
                     second_child : render!( 
                         Split {
                             direction : SplitDirection::Vertical,
-                            first_child : render!( 
-                                rect {
-                                    width : "100%",
-                                    direction : "horizontal",
-                                    SimpleWordComplete {
-                                        get_word_hints : |last| {
-                                            const hints:[&'static str;79] = [
-                                                "Alice", "Bob", "Car","Dog","Elephant","Fish","Giraffe","Horse","Ice cream","Jaguar","Kangaroo","Lion","Monkey","Nectarine","Octopus","Penguin","Queen","Rabbit","Snake","Tiger","Umbrella","Vase","Whale","Xylophone","Yak","Zebra",
-                                                "Aardvark","Bison","Cheetah","Dolphin","Elephant","Falcon","Gorilla","Hippopotamus","Ibis","Jaguar","Kangaroo","Lion","Moose","Nightingale","Ostrich","Penguin","Quokka","Raccoon","Squirrel","Tiger","Umbrella","Vulture","Walrus","X-ray tetra","Yak","Zebra",
-                                                "Alligator","Bear","Cat","Dog","Elephant","Fox","Giraffe","Horse","Iguana","Jaguar","Kangaroo","Lion","Monkey","Nightingale","Octopus","Penguin","Quokka","Rabbit","Snake","Tiger","Umbrella","Vulture","Whale","X-ray fish","Yak","Zoo",
-                                                "Alpine"];
-                                            hints.iter().filter( |h| h.len() != last.len() && h.starts_with(last) )
-                                                .map( |e| e.to_string() ).collect::<Vec<String>>()
-                                        }
-                                    },
-                                    Dropdown {
-                                        value: selected_dropdown.get().clone(),
-                                        values.iter().map(|ch| {
-                                            rsx!(
-                                                DropdownItem {
-                                                    value: ch.to_string(),
-                                                    onclick: move |_| selected_dropdown.set(ch.to_string()),
-                                                    label { "{ch}" }
-                                                }
-                                            )
-                                        })
-                                    },
-                                    Input { value : value.get().clone(), onchange : |e| { value.set(e) } },
-                                }
-                            )
+                            initial_size : 260,
+                            first_child : render!( Txt2Img { onsubmit : onsubmit } ),
                             second_child : render!( rect {
                                 width:"100%",
                                 height:"100%",
                                 rect {
                                     width:"100%",
+                                    direction : "horizontal",
+                                    GalleryToolbar {}
                                     FloatRight {
                                         Slider {
                                             width: 100.0,
@@ -110,13 +193,17 @@ fn app(cx: Scope) -> Element {
                                         }
                                     }
                                 }
+                                Detail {
+                                    summary : Cow::Borrowed("Tiled preview"),
+                                    TiledPreview {}
+                                }
                                 
                                 SquareGrid {
                                     h_gap : 5.,
                                     v_gap : 5.,
                                     item_width : *item_size.get(),
                                     item_height : *item_size.get(),
-                                    item_length : 30,
+                                    item_length : outputs.len(),
                                     builder : make_item
                                     // builder : Box::new( |i,cx:Scope<'_,VirtualScrollViewProps<'_,()>>| {
                                     //     render!( rect {
@@ -0,0 +1,220 @@
+/// Automatic1111 `parameters` text.
+///
+/// ```text
+/// a photo of a cat
+/// Negative prompt: blurry
+/// Steps: 20, Sampler: Euler a, CFG scale: 7, Seed: 1234, Size: 512x512, Model hash: 6ce0161689, Model: v1-5
+/// ```
+///
+/// The last line is a list of `key: value` settings. Values with `,`, `:` or `"` are written as
+/// JSON strings, the same way A1111 quotes them.
+
+use std::fmt;
+
+use crate::generation::{GenerationMode, GenerationRequest};
+
+const NEGATIVE_PREFIX:&str = "Negative prompt:";
+
+#[derive(Debug, Clone, Default, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct Parameters {
+	pub prompt : String,
+	pub negative_prompt : String,
+
+	/// Settings of the last line, in order
+	pub settings : Vec<(String, String)>,
+}
+
+impl Parameters {
+	pub fn from_request(r:&GenerationRequest, seed:u64) -> Self {
+		let mut p = Self {
+			prompt : r.prompt.clone(),
+			negative_prompt : r.negative_prompt.clone(),
+			settings : vec![],
+		};
+		p.set( "Steps", r.steps );
+		p.set( "Sampler", r.sampler );
+		p.set( "CFG scale", r.cfg_scale );
+		p.set( "Seed", seed );
+		if let Some(variation) = &r.variation {
+			p.set( "Variation seed", variation.seed );
+			p.set( "Variation seed strength", variation.strength );
+		}
+		p.set( "Size", format!("{}x{}", r.width, r.height) );
+		if let Some(model) = &r.model {
+			if let Some(hash) = &model.hash {
+				p.set( "Model hash", hash );
+			}
+			p.set( "Model", &model.name );
+		}
+		if let Some(blend) = &r.prompt_blend {
+			p.set( "Prompt blend", &blend.prompt );
+			p.set( "Prompt blend weight", blend.weight );
+		}
+		if !r.long_prompts {
+			p.set( "Long prompts", "off" );
+		}
+		if r.tileable {
+			p.set( "Tiling", "True" );
+		}
+		match &r.mode {
+			GenerationMode::Txt2Img => {}
+			GenerationMode::Img2Img { strength, .. } | GenerationMode::Inpaint { strength, .. } => p.set( "Denoising strength", strength ),
+		}
+		// Same keys as A1111, which also stores the hi-res strength as `Denoising strength`
+		if let Some(hires) = r.hires() {
+			p.set( "Denoising strength", hires.strength );
+			p.set( "Hires upscale", hires.scale );
+			if hires.steps != 0 {
+				p.set( "Hires steps", hires.steps );
+			}
+			p.set( "Hires upscaler", &hires.upscaler );
+		}
+		p
+	}
+
+	pub fn get(&self, key:&str) -> Option<&str> {
+		self.settings.iter().find( |(k, _)| k == key ).map( |(_, v)| v.as_str() )
+	}
+
+	/// Replace `key` in place or append it
+	pub fn set(&mut self, key:&str, value:impl fmt::Display) {
+		let value = value.to_string();
+		match self.settings.iter_mut().find( |(k, _)| k == key ) {
+			Some(entry) => entry.1 = value,
+			None => self.settings.push( (key.to_string(), value) ),
+		}
+	}
+
+	/// Parse A1111 text. `None` when there is no settings line
+	pub fn parse(text:&str) -> Option<Self> {
+		let text = text.trim();
+		let (body, last) = match text.rfind('\n') {
+			Some(i) => (&text[..i], text[i+1..].trim()),
+			None => ("", text),
+		};
+		if !last.starts_with("Steps:") {
+			return None
+		}
+		let settings = parse_settings(last);
+
+		let (prompt, negative_prompt) = match body.find(NEGATIVE_PREFIX) {
+			Some(i) if i == 0 || body[..i].ends_with('\n') => (
+				body[..i].trim_end_matches('\n'),
+				body[i + NEGATIVE_PREFIX.len()..].trim_start_matches(' '),
+			),
+			_ => (body, ""),
+		};
+		Some( Self { prompt : prompt.to_string(), negative_prompt : negative_prompt.to_string(), settings } )
+	}
+}
+
+fn quote(value:&str) -> String {
+	if value.contains( [',', ':', '"', '\n'] ) {
+		serde_json::to_string(value).unwrap_or_else( |_| value.to_string() )
+	} else {
+		value.to_string()
+	}
+}
+
+/// `key: value, key: "quoted, value", ...`
+fn parse_settings(line:&str) -> Vec<(String, String)> {
+	let mut out = vec![];
+	let mut rest = line;
+	loop {
+		rest = rest.trim_start_matches( |c:char| c == ',' || c.is_whitespace() );
+		let Some(colon) = rest.find(':') else { break };
+		let key = rest[..colon].trim().to_string();
+		rest = rest[colon+1..].trim_start();
+
+		let value = if rest.starts_with('"') {
+			// Find the closing quote, skipping escaped ones
+			let mut end = None;
+			let mut escaped = false;
+			for (i, c) in rest.char_indices().skip(1) {
+				match c {
+					'\\' if !escaped => escaped = true,
+					'"' if !escaped => { end = Some(i); break }
+					_ => escaped = false,
+				}
+			}
+			// An unterminated quote runs to the end of the line
+			let quoted = end.map_or( rest, |e| &rest[..=e] );
+			rest = &rest[quoted.len()..];
+			serde_json::from_str::<String>(quoted).unwrap_or_else( |_| quoted.trim_matches('"').to_string() )
+		} else {
+			let end = rest.find(',').unwrap_or( rest.len() );
+			let value = rest[..end].trim().to_string();
+			rest = &rest[end..];
+			value
+		};
+		out.push( (key, value) );
+	}
+	out
+}
+
+impl fmt::Display for Parameters {
+	fn fmt(&self, f:&mut fmt::Formatter<'_>) -> fmt::Result {
+		writeln!( f, "{}", self.prompt )?;
+		if !self.negative_prompt.is_empty() {
+			writeln!( f, "{NEGATIVE_PREFIX} {}", self.negative_prompt )?;
+		}
+		let settings = self.settings.iter()
+			.map( |(k, v)| format!("{k}: {}", quote(v)) )
+			.collect::<Vec<_>>()
+			.join(", ");
+		f.write_str(&settings)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn round_trip(p:&Parameters) {
+		assert_eq!( Parameters::parse( &p.to_string() ).as_ref(), Some(p) );
+	}
+
+	fn params(prompt:&str, negative_prompt:&str, settings:&[(&str, &str)]) -> Parameters {
+		Parameters {
+			prompt : prompt.to_string(),
+			negative_prompt : negative_prompt.to_string(),
+			settings : settings.iter().map( |(k, v)| (k.to_string(), v.to_string()) ).collect(),
+		}
+	}
+
+	#[test]
+	fn quoted_value_with_comma() {
+		round_trip( &params( "a cat", "blurry", &[("Steps", "20"), ("Prompt blend", "a dog, running"), ("Seed", "1")] ) );
+		round_trip( &params( "a cat", "", &[("Steps", "20"), ("Model", "say \"hi\": now")] ) );
+	}
+
+	#[test]
+	fn multi_line_prompt() {
+		round_trip( &params( "a cat\non a mat\n\nat night", "blurry,\nlow quality", &[("Steps", "20"), ("Seed", "1234")] ) );
+	}
+
+	#[test]
+	fn empty_negative_prompt() {
+		let p = params( "a cat", "", &[("Steps", "20"), ("Sampler", "Euler a")] );
+		assert!( !p.to_string().contains(NEGATIVE_PREFIX) );
+		round_trip(&p);
+	}
+
+	#[test]
+	fn non_ascii() {
+		round_trip( &params( "猫が座っている, ÉTÉ", "ぼやけた", &[("Steps", "20"), ("Model", "モデル, v1"), ("Size", "512x512")] ) );
+	}
+
+	#[test]
+	fn unterminated_quote_runs_to_the_end() {
+		assert_eq!( parse_settings( "Steps: 20, Model: \"v1, é" ), vec![
+			("Steps".to_string(), "20".to_string()),
+			("Model".to_string(), "v1, é".to_string()),
+		] );
+	}
+
+	#[test]
+	fn no_settings_line() {
+		assert_eq!( Parameters::parse("just a prompt\nNegative prompt: blurry"), None );
+	}
+}
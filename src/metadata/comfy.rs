@@ -0,0 +1,207 @@
+/// ComfyUI `prompt` chunk.
+///
+/// The chunk is the API graph : node id to `{ class_type, inputs }`, where a linked input is
+/// `[node_id, output_index]`. The sampler node is followed back to its text encoders, latent
+/// and checkpoint loader. Sampler inputs we don't map are kept as extra settings.
+/// The `workflow` chunk is the editor layout, only its node count is kept.
+
+use serde_json::{Map, Value};
+
+use super::a1111::Parameters;
+
+/// ComfyUI sampler names to ours
+fn sampler_name(comfy:&str) -> Option<&'static str> {
+	Some( match comfy {
+		"ddim" => "DDIM",
+		"ddpm" => "DDPM",
+		"euler" => "Euler",
+		"euler_ancestral" => "Euler a",
+		"dpmpp_2m" => "DPM++ 2M",
+		"heun" => "Heun",
+		"lms" => "LMS",
+		_ => return None,
+	} )
+}
+
+fn scalar(v:&Value) -> Option<String> {
+	match v {
+		Value::String(s) => Some( s.clone() ),
+		Value::Number(n) => Some( n.to_string() ),
+		Value::Bool(b) => Some( b.to_string() ),
+		_ => None,
+	}
+}
+
+struct Graph<'a>(&'a Map<String, Value>);
+
+impl<'a> Graph<'a> {
+	fn inputs(&self, id:&str) -> Option<&'a Map<String, Value>> {
+		self.0.get(id)?.get("inputs")?.as_object()
+	}
+
+	fn class(&self, id:&str) -> Option<&'a str> {
+		self.0.get(id)?.get("class_type")?.as_str()
+	}
+
+	/// Node id a linked input points at
+	fn link(&self, inputs:&Map<String, Value>, name:&str) -> Option<String> {
+		let link = inputs.get(name)?.as_array()?;
+		scalar( link.first()? )
+	}
+
+	/// Follow `name` until a node with a `text` input, through conditioning combiners and the like
+	fn text(&self, inputs:&Map<String, Value>, name:&str) -> Option<String> {
+		let mut id = self.link(inputs, name)?;
+		for _ in 0..16 {
+			let node = self.inputs(&id)?;
+			if let Some(text) = node.get("text").and_then( Value::as_str ) {
+				return Some( text.to_string() )
+			}
+			id = ["conditioning", "conditioning_1", "conditioning_to"].iter()
+				.find_map( |n| self.link(node, n) )?;
+		}
+		None
+	}
+}
+
+/// Parameters from the `prompt` chunk and optionally the `workflow` chunk
+pub fn parse(prompt:&str, workflow:Option<&str>) -> Option<Parameters> {
+	let graph:Map<String, Value> = serde_json::from_str(prompt).ok()?;
+	let graph = Graph(&graph);
+
+	let (_, sampler) = graph.0.keys()
+		.filter_map( |id| Some( (id, graph.class(id)?) ) )
+		.find( |(_, class)| class.starts_with("KSampler") )
+		.and_then( |(id, _)| Some( (id, graph.inputs(id)?) ) )?;
+
+	let mut p = Parameters {
+		prompt : graph.text(sampler, "positive").unwrap_or_default(),
+		negative_prompt : graph.text(sampler, "negative").unwrap_or_default(),
+		settings : vec![],
+	};
+
+	const LINKED:[&str;4] = ["model", "positive", "negative", "latent_image"];
+	for (key, value) in sampler {
+		if LINKED.contains(&key.as_str()) {
+			continue
+		}
+		let Some(value) = scalar(value) else { continue };
+		match key.as_str() {
+			"seed" | "noise_seed" => p.set( "Seed", value ),
+			"steps" => p.set( "Steps", value ),
+			"cfg" => p.set( "CFG scale", value ),
+			"denoise" if value != "1" && value != "1.0" => p.set( "Denoising strength", value ),
+			"denoise" => {}
+			"sampler_name" => match sampler_name(&value) {
+				Some(name) => p.set( "Sampler", name ),
+				None => p.set( "Sampler", value ),
+			},
+			"scheduler" => p.set( "Schedule type", value ),
+			_ => p.set( key, value ),
+		}
+	}
+
+	if let Some(latent) = graph.link(sampler, "latent_image").and_then( |id| graph.inputs(&id) ) {
+		if let (Some(w), Some(h)) = ( latent.get("width").and_then(scalar), latent.get("height").and_then(scalar) ) {
+			p.set( "Size", format!("{w}x{h}") );
+		}
+	}
+
+	let checkpoint = graph.link(sampler, "model")
+		.and_then( |id| graph.inputs(&id) )
+		.and_then( |m| m.get("ckpt_name").or( m.get("unet_name") ) )
+		.and_then( scalar );
+	if let Some(name) = checkpoint {
+		let stem = name.rsplit( ['/', '\\'] ).next().unwrap_or(&name);
+		p.set( "Model", stem.rsplit_once('.').map_or( stem, |(s, _)| s ) );
+	}
+
+	if let Some(nodes) = workflow
+		.and_then( |w| serde_json::from_str::<Value>(w).ok() )
+		.and_then( |w| w.get("nodes")?.as_array().map( Vec::len ) )
+	{
+		p.set( "ComfyUI workflow nodes", nodes );
+	}
+	Some(p)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/// Default text to image graph of the ComfyUI editor
+	const BASIC:&str = r#"{
+		"3": { "class_type": "KSampler", "inputs": {
+			"seed": 156680208700286, "steps": 20, "cfg": 8, "sampler_name": "euler_ancestral", "scheduler": "normal", "denoise": 1,
+			"model": ["4", 0], "positive": ["6", 0], "negative": ["7", 0], "latent_image": ["5", 0]
+		} },
+		"4": { "class_type": "CheckpointLoaderSimple", "inputs": { "ckpt_name": "sd15/v1-5-pruned-emaonly.safetensors" } },
+		"5": { "class_type": "EmptyLatentImage", "inputs": { "width": 512, "height": 768, "batch_size": 1 } },
+		"6": { "class_type": "CLIPTextEncode", "inputs": { "text": "a bottle with a galaxy inside", "clip": ["4", 1] } },
+		"7": { "class_type": "CLIPTextEncode", "inputs": { "text": "text, watermark", "clip": ["4", 1] } },
+		"8": { "class_type": "VAEDecode", "inputs": { "samples": ["3", 0], "vae": ["4", 2] } },
+		"9": { "class_type": "SaveImage", "inputs": { "filename_prefix": "ComfyUI", "images": ["8", 0] } }
+	}"#;
+
+	/// Settings sorted by key, the graph's key order depends on `serde_json` features
+	fn settings(p:&Parameters) -> Vec<(&str, &str)> {
+		let mut out = p.settings.iter().map( |(k, v)| (k.as_str(), v.as_str()) ).collect::<Vec<_>>();
+		out.sort_unstable();
+		out
+	}
+
+	#[test]
+	fn basic_graph() {
+		let p = parse( BASIC, Some(r#"{ "nodes": [{}, {}, {}, {}, {}, {}, {}] }"#) ).unwrap();
+		assert_eq!( p.prompt, "a bottle with a galaxy inside" );
+		assert_eq!( p.negative_prompt, "text, watermark" );
+		assert_eq!( settings(&p), [
+			("CFG scale", "8"),
+			("ComfyUI workflow nodes", "7"),
+			("Model", "v1-5-pruned-emaonly"),
+			("Sampler", "Euler a"),
+			("Schedule type", "normal"),
+			("Seed", "156680208700286"),
+			("Size", "512x768"),
+			("Steps", "20"),
+		] );
+	}
+
+	#[test]
+	fn combined_conditioning() {
+		let graph = BASIC
+			.replace( r#""positive": ["6", 0]"#, r#""positive": ["10", 0]"# )
+			.replace( r#""9": {"#, r#""10": { "class_type": "ConditioningCombine", "inputs": { "conditioning_1": ["6", 0], "conditioning_2": ["7", 0] } }, "9": {"# );
+		assert_eq!( parse( &graph, None ).unwrap().prompt, "a bottle with a galaxy inside" );
+	}
+
+	#[test]
+	fn missing_and_linked_inputs() {
+		let graph = r#"{
+			"1": { "class_type": "KSamplerAdvanced", "inputs": {
+				"noise_seed": ["2", 0], "steps": 30, "sampler_name": "dpmpp_sde", "denoise": 0.6, "add_noise": "enable",
+				"positive": ["8", 0], "model": ["9", 0]
+			} },
+			"2": { "class_type": "PrimitiveNode", "inputs": { "value": 5 } },
+			"9": { "class_type": "UNETLoader", "inputs": { "unet_name": "flux\\dev.sft" } }
+		}"#;
+		let p = parse( graph, Some("not json") ).unwrap();
+		// The positive link points nowhere, there is no negative link
+		assert_eq!( (p.prompt.as_str(), p.negative_prompt.as_str()), ("", "") );
+		// A linked seed isn't a value, unknown samplers and inputs stay as they are
+		assert_eq!( settings(&p), [
+			("Denoising strength", "0.6"),
+			("Model", "dev"),
+			("Sampler", "dpmpp_sde"),
+			("Steps", "30"),
+			("add_noise", "enable"),
+		] );
+	}
+
+	#[test]
+	fn not_a_sampler_graph() {
+		assert_eq!( parse( r#"{ "1": { "class_type": "SaveImage", "inputs": {} } }"#, None ), None );
+		assert_eq!( parse( "[1, 2]", None ), None );
+		assert_eq!( parse( "", None ), None );
+	}
+}
@@ -0,0 +1,92 @@
+//! Generation parameters stored inside output PNGs.
+//!
+//! Outputs get the A1111 `parameters` chunk, which most tools read, and our own `sd-freya` chunk
+//! with the same parameters as JSON, which survives prompts that don't round trip through the
+//! A1111 text. Reading also understands ComfyUI's `prompt`/`workflow` chunks.
+
+pub mod a1111;
+pub mod comfy;
+
+pub use a1111::Parameters;
+
+use crate::generation::{GeneratedImage, GenerationRequest};
+
+/// tEXt keyword Automatic1111 uses for its parameters
+pub const PARAMETERS_KEY:&str = "parameters";
+
+/// Keyword of our own JSON chunk
+pub const NATIVE_KEY:&str = "sd-freya";
+
+/// Keywords of the ComfyUI chunks
+pub const COMFY_PROMPT_KEY:&str = "prompt";
+pub const COMFY_WORKFLOW_KEY:&str = "workflow";
+
+/// Encode `image` as PNG with the parameters of `request` and the backend's extra info
+pub fn encode_png(image:&GeneratedImage, request:&GenerationRequest) -> Result<Vec<u8>, png::EncodingError> {
+	let mut params = Parameters::from_request(request, image.seed);
+	for (key, value) in &image.info {
+		params.set( key, value );
+	}
+	let text = params.to_string();
+	let json = serde_json::to_string(&params).unwrap_or_default();
+	let mut out = Vec::new();
+	image.image.write_png_with_text( &mut out, &[(PARAMETERS_KEY, &text), (NATIVE_KEY, &json)] )?;
+	Ok(out)
+}
+
+/// Where read parameters came from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Source {
+	Native,
+	A1111,
+	ComfyUI,
+}
+
+/// Parameters read back from a PNG
+#[derive(Debug, Clone, PartialEq)]
+pub struct Imported {
+	pub source : Source,
+	pub parameters : Parameters,
+
+	/// Text chunks that aren't parameters, like `Software`
+	pub other : Vec<(String, String)>,
+}
+
+/// Every text chunk of a PNG, tEXt, zTXt and iTXt alike, in file order per kind
+pub fn read_text_chunks(bytes:&[u8]) -> Result<Vec<(String, String)>, png::DecodingError> {
+	let reader = png::Decoder::new(bytes).read_info()?;
+	let info = reader.info();
+	let mut out = info.uncompressed_latin1_text.iter()
+		.map( |c| (c.keyword.clone(), c.text.clone()) )
+		.collect::<Vec<_>>();
+	for c in &info.compressed_latin1_text {
+		out.push( (c.keyword.clone(), c.get_text()?) );
+	}
+	for c in &info.utf8_text {
+		out.push( (c.keyword.clone(), c.get_text()?) );
+	}
+	Ok(out)
+}
+
+/// Generation parameters of a PNG. Our own chunk wins over A1111's, which wins over ComfyUI's.
+/// `None` when the file has none of them
+pub fn read_png(bytes:&[u8]) -> Option<Imported> {
+	let chunks = read_text_chunks(bytes).ok()?;
+	let chunk = |key:&str| chunks.iter().find( |(k, _)| k == key ).map( |(_, v)| v.as_str() );
+
+	let (source, parameters, used):(_, _, &[&str]) = if let Some(p) = chunk(NATIVE_KEY).and_then( |j| serde_json::from_str(j).ok() ) {
+		(Source::Native, p, &[NATIVE_KEY, PARAMETERS_KEY])
+	} else if let Some(p) = chunk(PARAMETERS_KEY).and_then( Parameters::parse ) {
+		(Source::A1111, p, &[PARAMETERS_KEY])
+	} else if let Some(p) = chunk(COMFY_PROMPT_KEY).and_then( |prompt| comfy::parse(prompt, chunk(COMFY_WORKFLOW_KEY)) ) {
+		(Source::ComfyUI, p, &[COMFY_PROMPT_KEY, COMFY_WORKFLOW_KEY])
+	} else {
+		return None
+	};
+
+	let other = chunks.iter()
+		.filter( |(k, _)| !used.contains(&k.as_str()) )
+		.cloned()
+		.collect();
+	Some( Imported { source, parameters, other } )
+}
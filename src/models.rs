@@ -0,0 +1,333 @@
+/// Local model registry.
+///
+/// Scans a directory for `.safetensors`, `.ot` and `.ckpt` files and guesses what each file is.
+/// Safetensors headers are read for tensor names and shapes, the other formats can only be
+/// guessed from their file name. Results are cached next to the models, keyed by file size and
+/// modification time, so a rescan only opens new or changed files.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::generation::{ModelSet, SdVersion};
+
+const CACHE_FILE:&str = ".sd-freya-models.json";
+const EXTENSIONS:[&str;3] = ["safetensors", "ot", "ckpt"];
+
+/// What a weight file contains
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Serialize, Deserialize)]
+pub enum ModelKind {
+	/// Single file checkpoint with UNet, VAE and text encoder (original SD layout)
+	Checkpoint,
+	Unet,
+	Vae,
+	Clip,
+	Lora,
+	Embedding,
+	Unknown,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ModelEntry {
+	pub path : PathBuf,
+	pub kind : ModelKind,
+
+	/// `None` when the file doesn't tell, like a VAE which is shared between versions
+	pub version : Option<SdVersion>,
+
+	/// First 10 hex digits of the sha256 of the file, like Automatic1111's model hash
+	pub hash : String,
+
+	size : u64,
+	modified : u64,
+}
+
+impl ModelEntry {
+	pub fn name(&self) -> String {
+		self.path.file_stem().map( |s| s.to_string_lossy().into_owned() ).unwrap_or_default()
+	}
+
+	fn matches(&self, version:SdVersion) -> bool {
+		self.version.map_or( true, |v| v == version )
+	}
+}
+
+/// Name to `(dtype, shape)` of every tensor in a safetensors file, without reading the data
+pub fn read_safetensors_header(path:&Path) -> anyhow::Result<HashMap<String, (String, Vec<i64>)>> {
+	let mut f = File::open(path)?;
+	let mut len = [0u8;8];
+	f.read_exact(&mut len)?;
+	let len = u64::from_le_bytes(len);
+	anyhow::ensure!( len < 100_000_000, "header too large" );
+	let mut header = vec![0u8; len as usize];
+	f.read_exact(&mut header)?;
+
+	#[derive(Deserialize)]
+	struct Info {
+		dtype : String,
+		shape : Vec<i64>,
+	}
+	let raw:HashMap<String, serde_json::Value> = serde_json::from_slice(&header)?;
+	Ok( raw.into_iter()
+		.filter( |(k, _)| k != "__metadata__" )
+		.filter_map( |(k, v)| serde_json::from_value::<Info>(v).ok().map( |i| (k, (i.dtype, i.shape)) ) )
+		.collect() )
+}
+
+/// Guess kind and version from tensor names and shapes
+pub fn classify(tensors:&HashMap<String, (String, Vec<i64>)>) -> (ModelKind, Option<SdVersion>) {
+	let has = |prefix:&str| tensors.keys().any( |k| k.starts_with(prefix) );
+	let contains = |part:&str| tensors.keys().any( |k| k.contains(part) );
+	// Text context width : 768 for SD 1.x (CLIP ViT-L), 1024 for SD 2.x (OpenCLIP ViT-H)
+	let version_of = |dim:i64| match dim {
+		768 => Some(SdVersion::V1_5),
+		1024 => Some(SdVersion::V2_1),
+		_ => None,
+	};
+	let shape_dim = |name_part:&str, axis:usize| tensors.iter()
+		.find( |(k, _)| k.contains(name_part) )
+		.and_then( |(_, (_, shape))| shape.get(axis).copied() );
+
+	if contains("lora_up") || contains("lora_down") || contains("lora_A") {
+		(ModelKind::Lora, None)
+	} else if has("emb_params") || has("string_to_param") || (has("clip_l") && tensors.len() <= 2) {
+		(ModelKind::Embedding, None)
+	} else if has("model.diffusion_model.") {
+		let version = if has("cond_stage_model.model.transformer") { Some(SdVersion::V2_1) } else { Some(SdVersion::V1_5) };
+		(ModelKind::Checkpoint, version)
+	} else if has("conv_in.") && contains("attn2.to_k.weight") {
+		(ModelKind::Unet, shape_dim("attn2.to_k.weight", 1).and_then(version_of))
+	} else if has("encoder.conv_in.") && has("decoder.conv_out.") {
+		(ModelKind::Vae, None)
+	} else if has("text_model.embeddings.token_embedding") {
+		(ModelKind::Clip, shape_dim("text_model.embeddings.token_embedding", 1).and_then(version_of))
+	} else {
+		(ModelKind::Unknown, None)
+	}
+}
+
+/// Guess from the file name, for formats whose header can't be read cheaply
+fn classify_by_name(path:&Path) -> (ModelKind, Option<SdVersion>) {
+	let name = path.file_stem().map( |s| s.to_string_lossy().to_lowercase() ).unwrap_or_default();
+	let version = if name.contains("v2") || name.contains("2.1") || name.contains("768") { Some(SdVersion::V2_1) } else { None };
+	let kind = if name.contains("unet") {
+		ModelKind::Unet
+	} else if name.contains("vae") {
+		ModelKind::Vae
+	} else if name.contains("clip") || name.contains("text") {
+		ModelKind::Clip
+	} else if name.contains("lora") {
+		ModelKind::Lora
+	} else if path.extension().map_or( false, |e| e == "ckpt" ) {
+		ModelKind::Checkpoint
+	} else {
+		ModelKind::Unknown
+	};
+	let version = match kind {
+		ModelKind::Unet | ModelKind::Clip | ModelKind::Checkpoint => version.or( Some(SdVersion::V1_5) ),
+		_ => version,
+	};
+	(kind, version)
+}
+
+pub fn file_hash(path:&Path) -> anyhow::Result<String> {
+	let mut reader = BufReader::new( File::open(path)? );
+	let mut hasher = Sha256::new();
+	let mut buf = vec![0u8; 1 << 20];
+	loop {
+		let n = reader.read(&mut buf)?;
+		if n == 0 {
+			break
+		}
+		hasher.update( &buf[..n] );
+	}
+	Ok( hasher.finalize().iter().take(5).map( |b| format!("{b:02x}") ).collect() )
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct ModelRegistry {
+	dir : PathBuf,
+	entries : Vec<ModelEntry>,
+}
+
+impl ModelRegistry {
+	/// Registry of `dir` with the cached entries of the last scan. Call [`ModelRegistry::scan`] to refresh
+	pub fn open(dir:impl Into<PathBuf>) -> Self {
+		let dir = dir.into();
+		let entries = std::fs::read(dir.join(CACHE_FILE)).ok()
+			.and_then( |b| serde_json::from_slice(&b).ok() )
+			.unwrap_or_default();
+		Self { dir, entries }
+	}
+
+	pub fn dir(&self) -> &Path {
+		&self.dir
+	}
+
+	pub fn entries(&self) -> &[ModelEntry] {
+		&self.entries
+	}
+
+	pub fn of_kind(&self, kind:ModelKind) -> impl Iterator<Item = &ModelEntry> {
+		self.entries.iter().filter( move |e| e.kind == kind )
+	}
+
+	/// Walk the directory again. Unchanged files keep their cached entry.
+	/// Files and folders that can't be read are logged and skipped. When the directory itself
+	/// can't be read the entries stay as they were
+	pub fn scan(&mut self) -> anyhow::Result<()> {
+		let mut files = vec![];
+		collect_files( &self.dir, &mut files )?;
+
+		let cached = self.entries.iter()
+			.map( |e| (&e.path, e) )
+			.collect::<HashMap<_, _>>();
+		let mut entries = vec![];
+		for path in files {
+			match entry( &path, cached.get(&path).copied() ) {
+				Ok(e) => entries.push(e),
+				Err(e) => eprintln!("skipping model '{}' : {e:#}", path.display()),
+			}
+		}
+		entries.sort_by( |a, b| a.path.cmp(&b.path) );
+		self.entries = entries;
+		std::fs::write( self.dir.join(CACHE_FILE), serde_json::to_vec_pretty(&self.entries)? )?;
+		Ok(())
+	}
+
+	/// Pipeline for `unet` with the VAE and CLIP of the same version.
+	/// Files in the same folder are preferred
+	pub fn model_set(&self, unet:&ModelEntry) -> Option<ModelSet> {
+		let version = unet.version?;
+		let pick = |kind:ModelKind| {
+			let mut candidates = self.of_kind(kind).filter( |e| e.matches(version) ).collect::<Vec<_>>();
+			candidates.sort_by_key( |e| (e.path.parent() != unet.path.parent(), e.version.is_none()) );
+			candidates.first().map( |e| e.path.clone() )
+		};
+		Some( ModelSet {
+			name : unet.name(),
+			version,
+			unet : unet.path.clone(),
+			vae : pick(ModelKind::Vae)?,
+			clip : pick(ModelKind::Clip)?,
+			hash : Some( unet.hash.clone() ),
+		} )
+	}
+
+	/// Every UNet that has a VAE and CLIP to go with it, then every checkpoint that can be loaded
+	pub fn model_sets(&self) -> Vec<ModelSet> {
+		let unets = self.of_kind(ModelKind::Unet).filter_map( |u| self.model_set(u) );
+		// `.ckpt` files are pickles, only safetensors checkpoints are read
+		let checkpoints = self.of_kind(ModelKind::Checkpoint)
+			.filter( |c| is_safetensors(&c.path) )
+			.filter_map( |c| Some( ModelSet::checkpoint( c.name(), c.version?, c.path.clone(), Some( c.hash.clone() ) ) ) );
+		unets.chain(checkpoints).collect()
+	}
+}
+
+fn is_safetensors(path:&Path) -> bool {
+	path.extension().map_or( false, |e| e == "safetensors" )
+}
+
+/// Entry of `path`, `cached` when the file didn't change since
+fn entry(path:&Path, cached:Option<&ModelEntry>) -> anyhow::Result<ModelEntry> {
+	let meta = std::fs::metadata(path)?;
+	let size = meta.len();
+	let modified = meta.modified().ok()
+		.and_then( |t| t.duration_since(UNIX_EPOCH).ok() )
+		.map_or( 0, |d| d.as_secs() );
+
+	match cached {
+		Some(e) if e.size == size && e.modified == modified => Ok( e.clone() ),
+		_ => {
+			let (kind, version) = match is_safetensors(path) {
+				true => read_safetensors_header(path).map( |t| classify(&t) ).unwrap_or( (ModelKind::Unknown, None) ),
+				false => classify_by_name(path),
+			};
+			let hash = file_hash(path)?;
+			Ok( ModelEntry { path : path.to_path_buf(), kind, version, hash, size, modified } )
+		}
+	}
+}
+
+/// Weight files under `dir`. Subfolders that can't be read are logged and skipped
+fn collect_files(dir:&Path, out:&mut Vec<PathBuf>) -> std::io::Result<()> {
+	for entry in std::fs::read_dir(dir)? {
+		let path = match entry {
+			Ok(entry) => entry.path(),
+			Err(e) => {
+				eprintln!("skipping an entry of '{}' : {e}", dir.display());
+				continue
+			}
+		};
+		if path.is_dir() {
+			if let Err(e) = collect_files(&path, out) {
+				eprintln!("skipping '{}' : {e}", path.display());
+			}
+		} else if path.extension().map_or( false, |e| EXTENSIONS.iter().any( |x| e == *x ) ) {
+			out.push(path);
+		}
+	}
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn entry(path:&str, kind:ModelKind, version:Option<SdVersion>) -> ModelEntry {
+		ModelEntry { path : PathBuf::from(path), kind, version, hash : "0123456789".to_string(), size : 0, modified : 0 }
+	}
+
+	#[test]
+	fn model_sets_include_checkpoints() {
+		let registry = ModelRegistry {
+			dir : PathBuf::from("models"),
+			entries : vec![
+				entry( "models/unet.safetensors", ModelKind::Unet, Some(SdVersion::V1_5) ),
+				entry( "models/vae.safetensors", ModelKind::Vae, None ),
+				entry( "models/clip.safetensors", ModelKind::Clip, Some(SdVersion::V1_5) ),
+				entry( "models/dreamy.safetensors", ModelKind::Checkpoint, Some(SdVersion::V2_1) ),
+				// Pickles can't be loaded
+				entry( "models/old.ckpt", ModelKind::Checkpoint, Some(SdVersion::V1_5) ),
+			],
+		};
+		let sets = registry.model_sets();
+		assert_eq!( sets.iter().map( |s| s.name.as_str() ).collect::<Vec<_>>(), ["unet", "dreamy"] );
+		assert!( !sets[0].is_checkpoint() );
+		assert!( sets[1].is_checkpoint() );
+		assert_eq!( sets[1].version, SdVersion::V2_1 );
+		assert_eq!( sets[1].vae, PathBuf::from("models/dreamy.safetensors") );
+	}
+
+	#[test]
+	fn scan_skips_unreadable_files() {
+		let dir = std::env::temp_dir().join( format!("sd-freya-models-{}", std::process::id()) );
+		std::fs::create_dir_all(&dir).unwrap();
+		std::fs::write( dir.join("clip.ot"), b"weights" ).unwrap();
+		// Dangling link : listed by the directory walk, but can't be opened
+		#[cfg(unix)]
+		std::os::unix::fs::symlink( dir.join("missing"), dir.join("unet.safetensors") ).unwrap();
+
+		let mut registry = ModelRegistry::open(&dir);
+		let result = registry.scan();
+		std::fs::remove_dir_all(&dir).unwrap();
+		result.unwrap();
+		assert_eq!( registry.entries().iter().map( ModelEntry::name ).collect::<Vec<_>>(), ["clip"] );
+	}
+
+	#[test]
+	fn failed_scan_keeps_entries() {
+		let mut registry = ModelRegistry {
+			dir : std::env::temp_dir().join( format!("sd-freya-no-models-{}", std::process::id()) ),
+			entries : vec![ entry( "models/unet.safetensors", ModelKind::Unet, Some(SdVersion::V1_5) ) ],
+		};
+		assert!( registry.scan().is_err() );
+		assert_eq!( registry.entries().len(), 1 );
+	}
+}
@@ -0,0 +1,148 @@
+use std::path::Path;
+
+use freya::prelude::*;
+
+use crate::canvas::{Canvas, Extend, Fill, OpenCanvas, Side};
+use crate::panel::outputs::Outputs;
+use crate::panel::txt2img::FormValues;
+use crate::prompt::dynamic::Wildcards;
+use crate::queue::JobQueue;
+
+/// Width of the canvas preview
+const PREVIEW_WIDTH:u32 = 256;
+
+/// Outpainting canvas : start one from the selected image or a file, extend it, save it.
+/// Prompt and sampling settings come from the form.
+/// Needs the [`JobQueue`], [`OpenCanvas`] and [`Outputs`] contexts and the shared [`FormValues`]
+#[allow(non_snake_case)]
+pub fn OutpaintPanel(cx:Scope) -> Element {
+	let values = use_shared_state::<FormValues>(cx).unwrap();
+	let side = use_state(cx, Side::default);
+	let fill = use_state(cx, Fill::default);
+	let pixels = use_state(cx, || "128".to_string());
+	let context = use_state(cx, || "256".to_string());
+	let overlap = use_state(cx, || "32".to_string());
+	let strength = use_state(cx, || "1".to_string());
+	let path = use_state(cx, || "canvas.png".to_string());
+	let error = use_state(cx, || None::<String>);
+	let queue = cx.consume_context::<JobQueue>()?;
+	let open = cx.consume_context::<OpenCanvas>()?;
+	let outputs = cx.consume_context::<Outputs>();
+	let wildcards = cx.consume_context::<Wildcards>().unwrap_or_default();
+
+	let canvas = open.get();
+	let pending = open.is_pending();
+	let preview = canvas.as_ref().map( |_| bytes_to_data(cx, &open.png()) );
+	let status = match &canvas {
+		Some(c) if pending => format!("{}x{}, outpainting...", c.image.width, c.image.height),
+		Some(c) => format!("{}x{}, {} outpaints", c.image.width, c.image.height, c.steps.len()),
+		None => "No canvas".to_string(),
+	};
+	let preview_height = canvas.as_ref().map_or( 0, |c| PREVIEW_WIDTH * c.image.height / c.image.width.max(1) );
+
+	let (o_new, o_open, o_save, o_outpaint) = (open.clone(), open.clone(), open.clone(), open);
+	let selected = outputs.clone();
+	let from_selected = move |_| match selected.as_ref().and_then( Outputs::selected ) {
+		Some(o) => {
+			error.set(None);
+			o_new.set( Canvas::new(o.image.image) );
+		}
+		None => error.set( Some("Select an image in the grid first".to_string()) ),
+	};
+	let from_file = move |_| match Canvas::open( Path::new( path.get() ) ) {
+		Ok(canvas) => {
+			error.set(None);
+			o_open.set(canvas);
+		}
+		Err(e) => error.set( Some(e) ),
+	};
+	let save = move |_| {
+		let result = o_save.get().ok_or_else( || "No canvas to save".to_string() )
+			.and_then( |c| c.save( Path::new( path.get() ) ) );
+		error.set( result.err() );
+	};
+	let outpaint = move |_| {
+		let last_seed = outputs.as_ref().and_then( Outputs::last ).map( |o| o.image.seed );
+		let result = (|| {
+			let canvas = o_outpaint.get().ok_or_else( || "Start or open a canvas first".to_string() )?;
+			if o_outpaint.is_pending() {
+				return Err( "The last outpaint is still in the queue".to_string() )
+			}
+			let template = values.read().to_jobs(last_seed, &wildcards)?.into_iter().next().ok_or_else( || "Nothing to outpaint".to_string() )?;
+			let parse = |name:&str, v:&str| v.trim().parse::<u32>().map_err( |_| format!("Invalid {name} : '{v}'") );
+			let extend = Extend {
+				side : *side.get(),
+				pixels : parse( "pixels", pixels )?,
+				fill : *fill.get(),
+				context : parse( "context", context )?,
+				overlap : parse( "overlap", overlap )?,
+			};
+			let strength = strength.trim().parse::<f64>().ok().filter( |s| (0. ..= 1.).contains(s) )
+				.ok_or_else( || "strength must be between 0 and 1".to_string() )?;
+			let (request, pending) = canvas.outpaint( &extend, &template, strength );
+			queue.push_all( vec![request], |ids| o_outpaint.start( ids[0], pending ) );
+			Ok(())
+		})();
+		error.set( result.err() );
+	};
+
+	render!( rect {
+		direction : "vertical",
+		rect {
+			direction : "horizontal",
+			margin : "2 5",
+			Button { onclick : from_selected, label { "New from selected" } }
+			Input { value : path.get().clone(), onchange : move |e| path.set(e) }
+			Button { onclick : from_file, label { "Open" } }
+			Button { onclick : save, label { "Save" } }
+			label { margin : "6", "{status}" }
+		}
+		if let Some(data) = preview {
+			rsx!( image { margin : "2 5", image_data : data, width : "{PREVIEW_WIDTH}", height : "{preview_height}" } )
+		}
+		rect {
+			direction : "horizontal",
+			margin : "2 5",
+			Dropdown {
+				value : side.get().to_string(),
+				Side::ALL.iter().map( |&s| rsx!(
+					DropdownItem {
+						value : s.to_string(),
+						onclick : move |_| side.set(s),
+						label { "{s}" }
+					}
+				) )
+			}
+			Dropdown {
+				value : fill.get().to_string(),
+				Fill::ALL.iter().map( |&f| rsx!(
+					DropdownItem {
+						value : f.to_string(),
+						onclick : move |_| fill.set(f),
+						label { "{f}" }
+					}
+				) )
+			}
+			label { margin : "6", "Pixels" }
+			Input { value : pixels.get().clone(), onchange : move |e| pixels.set(e) }
+		}
+		rect {
+			direction : "horizontal",
+			margin : "2 5",
+			label { margin : "6", "Context" }
+			Input { value : context.get().clone(), onchange : move |e| context.set(e) }
+			label { margin : "6", "Overlap" }
+			Input { value : overlap.get().clone(), onchange : move |e| overlap.set(e) }
+			label { margin : "6", "Denoise" }
+			Input { value : strength.get().clone(), onchange : move |e| strength.set(e) }
+		}
+		rect {
+			direction : "horizontal",
+			margin : "2 5",
+			Button { onclick : outpaint, label { "Outpaint" } }
+			if let Some(e) = error.get() {
+				rsx!( label { color : "rgb(255,120,120)", margin : "6", "{e}" } )
+			}
+		}
+	} )
+}
@@ -0,0 +1,167 @@
+use std::sync::{Arc, Mutex};
+
+use freya::prelude::*;
+
+use crate::generation::{random_seed, variations, GeneratedImage};
+use crate::generation::upscale::{Upscaler, Upscalers};
+use crate::panel::models::Models;
+use crate::panel::outputs::{Outputs, OutputImage};
+use crate::panel::txt2img::FormValues;
+use crate::queue::JobQueue;
+
+/// Error of the last upscale, written by its thread
+#[derive(Clone)]
+struct UpscaleError {
+	error : Arc<Mutex<Option<String>>>,
+
+	/// Repaints the toolbar
+	update : Arc<dyn Fn() + Send + Sync>,
+}
+
+/// Upscale `output` by `factor` on its own thread, then keep it and select it
+fn upscale_output(outputs:Outputs, output:OutputImage, upscaler:Arc<dyn Upscaler>, factor:u32, error:UpscaleError) {
+	*error.error.lock().unwrap() = None;
+	std::thread::spawn( move || {
+		let image = &output.image.image;
+		let result = upscaler.upscale( image, image.width * factor, image.height * factor ).and_then( |upscaled| {
+			let mut info = output.image.info.clone();
+			info.push( ("Postprocess upscale by".to_string(), factor.to_string()) );
+			info.push( ("Postprocess upscaler".to_string(), upscaler.name().to_string()) );
+			let image = GeneratedImage { image : upscaled, seed : output.image.seed, info };
+			let saved = outputs.keep( OutputImage::new( image, output.request.clone() ) );
+			outputs.select( outputs.len() - 1 );
+			if let Err(e) = saved {
+				anyhow::bail!( "can't save the upscaled image : {e}" )
+			}
+			Ok(())
+		} );
+		*error.error.lock().unwrap() = result.err().map( |e| format!("Upscale failed : {e:#}") );
+		(error.update)();
+	} );
+}
+
+/// Actions on the image selected in the result grid.
+/// Needs the [`Outputs`], [`JobQueue`] and [`Upscalers`] contexts and the shared [`FormValues`].
+#[allow(non_snake_case)]
+pub fn GalleryToolbar(cx:Scope) -> Element {
+	let values = use_shared_state::<FormValues>(cx).unwrap();
+	let outputs = cx.consume_context::<Outputs>()?;
+	let queue = cx.consume_context::<JobQueue>()?;
+	let models = cx.consume_context::<Models>();
+	let variation_count = use_state(cx, || "4".to_string());
+	let variation_step = use_state(cx, || "0.1".to_string());
+	let error = use_state(cx, || None::<String>);
+	let upscalers = cx.consume_context::<Upscalers>().unwrap_or_default();
+	let upscaler_index = use_state(cx, || 0usize);
+	let upscale_error = cx.use_hook( || UpscaleError { error : Default::default(), update : cx.schedule_update() } ).clone();
+	let selected = outputs.selected();
+
+	let Some(selected) = selected else {
+		return render!( label { margin : "6", "Select an image" } )
+	};
+	let seed = selected.image.seed;
+	let request = selected.request.with_seed(seed);
+	let reuse = request.clone();
+	let png = selected.png.clone();
+	let vary = request.clone();
+	let vary_queue = queue.clone();
+	let upscaler = upscalers.all().get( *upscaler_index.get() ).or( upscalers.all().first() )?.clone();
+	let upscaler_name = upscaler.name().to_string();
+	let upscale_by = |factor:u32| {
+		let (outputs, output, upscaler, status) = (outputs.clone(), selected.clone(), upscaler.clone(), upscale_error.clone());
+		move |_:MouseEvent| upscale_output( outputs.clone(), output.clone(), upscaler.clone(), factor, status.clone() )
+	};
+	let upscale_failure = upscale_error.error.lock().unwrap().clone();
+
+	// One new variation seed, each job a step further away from the selected image
+	let make_variations = move |_| {
+		let jobs = match (variation_count.trim().parse::<usize>(), variation_step.trim().parse::<f64>()) {
+			(Ok(count), Ok(step)) => variations( &vary, random_seed(), count, step ),
+			(Err(_), _) => Err( format!("Invalid variation count : '{}'", variation_count.get()) ),
+			(_, Err(_)) => Err( format!("Invalid variation step : '{}'", variation_step.get()) ),
+		};
+		match jobs {
+			Ok(jobs) => {
+				error.set(None);
+				for job in jobs {
+					vary_queue.push(job);
+				}
+			}
+			Err(e) => error.set( Some(e) ),
+		}
+	};
+
+	render!( rect {
+		direction : "horizontal",
+		label { margin : "6", "Seed {seed}" }
+		Button {
+			onclick : move |_| { queue.push( request.clone() ); },
+			label { "Reproduce" }
+		}
+		Button {
+			onclick : move |_| *values.write() = FormValues::from_request(&reuse),
+			label { "Use settings" }
+		}
+		Button {
+			onclick : move |_| {
+				let models = models.as_ref().map( Models::model_sets ).unwrap_or_default();
+				if let Err(e) = values.write().import_png( &png, &models ) {
+					eprintln!("{e}");
+				}
+			},
+			label { "Send to txt2img" }
+		}
+		Button { onclick : make_variations, label { "Make variations" } }
+		Input { value : variation_count.get().clone(), onchange : move |e| variation_count.set(e) }
+		label { margin : "6", "x" }
+		Input { value : variation_step.get().clone(), onchange : move |e| variation_step.set(e) }
+		Dropdown {
+			value : upscaler_name,
+			upscalers.all().iter().enumerate().map( |(i, u)| {
+				let name = u.name().to_string();
+				rsx!(
+					DropdownItem {
+						value : name.clone(),
+						onclick : move |_| upscaler_index.set(i),
+						label { "{name}" }
+					}
+				)
+			} )
+		}
+		Button { onclick : upscale_by(2), label { "Upscale 2x" } }
+		Button { onclick : upscale_by(4), label { "Upscale 4x" } }
+		if let Some(e) = error.get() {
+			rsx!( label { color : "rgb(255,120,120)", margin : "6", "{e}" } )
+		}
+		if let Some(e) = upscale_failure {
+			rsx!( label { color : "rgb(255,120,120)", margin : "6", "{e}" } )
+		}
+	} )
+}
+
+/// Side of one tile of [`TiledPreview`]
+const PREVIEW_TILE:u32 = 128;
+
+/// The selected image repeated 3x3, to check the seams of tileable images.
+/// Needs the [`Outputs`] context
+#[allow(non_snake_case)]
+pub fn TiledPreview(cx:Scope) -> Element {
+	let outputs = cx.consume_context::<Outputs>()?;
+	let Some(selected) = outputs.selected() else {
+		return render!( label { margin : "6", "Select an image" } )
+	};
+	let image = &selected.image.image;
+	let (width, height) = (PREVIEW_TILE, PREVIEW_TILE * image.height / image.width.max(1));
+
+	render!( rect {
+		direction : "vertical",
+		(0..3).map( |row| rsx!( rect {
+			key : "{row}",
+			direction : "horizontal",
+			(0..3).map( |col| {
+				let data = bytes_to_data(cx, &selected.png);
+				rsx!( image { key : "{col}", image_data : data, width : "{width}", height : "{height}" } )
+			} )
+		} ) )
+	} )
+}
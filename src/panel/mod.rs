@@ -0,0 +1,19 @@
+pub mod canvas;
+pub mod gallery;
+pub mod models;
+pub mod outputs;
+pub mod plot;
+pub mod queue;
+pub mod txt2img;
+pub mod walk;
+
+pub mod prelude {
+	pub use super::canvas::OutpaintPanel;
+	pub use super::gallery::{GalleryToolbar, TiledPreview};
+	pub use super::models::{Models, ModelPicker};
+	pub use super::outputs::{Outputs, OutputImage};
+	pub use super::plot::PlotPanel;
+	pub use super::queue::QueuePanel;
+	pub use super::walk::WalkPanel;
+	pub use super::txt2img::{Txt2Img, Txt2ImgProps, FormValues, FormMode, SourceImage, Completions};
+}
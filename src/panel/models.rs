@@ -0,0 +1,98 @@
+use std::sync::{Arc, Mutex};
+
+use freya::prelude::*;
+
+use crate::generation::ModelSet;
+use crate::models::ModelRegistry;
+use crate::prompt::embeddings;
+use crate::panel::txt2img::FormValues;
+
+/// Registry shared with the scan thread. Provided as a context by `app`
+#[derive(Clone)]
+pub struct Models {
+	registry : Arc<Mutex<ModelRegistry>>,
+
+	/// Names of the textual inversion embeddings in `embeddings` next to the models
+	embeddings : Arc<Mutex<Vec<String>>>,
+	scanning : Arc<Mutex<Option<String>>>,
+	update : Arc<dyn Fn() + Send + Sync>,
+}
+
+impl Models {
+	pub fn new(registry:ModelRegistry, update:Arc<dyn Fn() + Send + Sync>) -> Self {
+		Self { registry : Arc::new( Mutex::new(registry) ), embeddings : Default::default(), scanning : Default::default(), update }
+	}
+
+	pub fn model_sets(&self) -> Vec<ModelSet> {
+		self.registry.lock().unwrap().model_sets()
+	}
+
+	pub fn embedding_names(&self) -> Vec<String> {
+		self.embeddings.lock().unwrap().clone()
+	}
+
+	pub fn with<R>(&self, f:impl FnOnce(&ModelRegistry) -> R) -> R {
+		f( &self.registry.lock().unwrap() )
+	}
+
+	/// Scan status text. `None` when idle and the last scan went fine
+	pub fn status(&self) -> Option<String> {
+		self.scanning.lock().unwrap().clone()
+	}
+
+	/// Rescan the models and list the embeddings again on a background thread, hashing new files
+	/// can take a while. The old entries stay visible until the scan is done
+	pub fn rescan(&self) {
+		let this = self.clone();
+		*self.scanning.lock().unwrap() = Some( "Scanning...".to_string() );
+		(self.update)();
+		std::thread::spawn( move || {
+			let mut registry = this.registry.lock().unwrap().clone();
+			let result = registry.scan();
+			let names = embeddings::list( &registry.dir().join("embeddings") ).into_iter().map( |e| e.name ).collect();
+			*this.embeddings.lock().unwrap() = names;
+			if result.is_ok() {
+				*this.registry.lock().unwrap() = registry;
+			}
+			*this.scanning.lock().unwrap() = result.err().map( |e| format!("Scan failed : {e:#}") );
+			(this.update)();
+		});
+	}
+}
+
+/// Active model picker with a rescan button. Writes the shared [`FormValues`]
+#[allow(non_snake_case)]
+pub fn ModelPicker(cx:Scope) -> Element {
+	let values = use_shared_state::<FormValues>(cx).unwrap();
+	let models = cx.consume_context::<Models>()?;
+	let sets = models.model_sets();
+	let current = values.read().model.as_ref().map_or( "Default".to_string(), |m| m.name.clone() );
+	let status = models.status();
+
+	render!( rect {
+		direction : "horizontal",
+		margin : "2 5",
+		label { width : "90", "Model" }
+		Dropdown {
+			value : current,
+			DropdownItem {
+				value : "Default".to_string(),
+				onclick : move |_| values.write().model = None,
+				label { "Default" }
+			}
+			sets.into_iter().map( |set| {
+				let name = set.name.clone();
+				let text = format!("{} ({})", set.name, set.version);
+				rsx!( DropdownItem {
+					value : name,
+					onclick : move |_| values.write().model = Some( set.clone() ),
+					label { "{text}" }
+				} )
+			} )
+		}
+		Button { onclick : move |_| models.rescan(), label { "Rescan" } }
+		if let Some(status) = status {
+			rsx!( label { margin : "6", "{status}" } )
+		}
+	} )
+}
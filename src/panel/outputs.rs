@@ -0,0 +1,94 @@
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::generation::{GenerationRequest, GeneratedImage};
+use crate::metadata;
+
+/// Finished image as shown in the result grid
+#[derive(Debug, Clone)]
+pub struct OutputImage {
+	pub image : GeneratedImage,
+
+	/// Request that made the image
+	pub request : GenerationRequest,
+
+	/// PNG with the generation parameters. Encoded once so the grid doesn't encode on every paint
+	pub png : Arc<Vec<u8>>,
+}
+
+impl OutputImage {
+	pub fn new(image:GeneratedImage, request:GenerationRequest) -> Self {
+		let png = metadata::encode_png( &image, &request ).unwrap_or_default();
+		Self { image, request, png : Arc::new(png) }
+	}
+
+	/// Write the PNG to `dir` as `<unix millis>-<seed>.png`
+	pub fn save(&self, dir:&Path) -> std::io::Result<PathBuf> {
+		std::fs::create_dir_all(dir)?;
+		let millis = SystemTime::now().duration_since(UNIX_EPOCH).map_or( 0, |d| d.as_millis() );
+		let path = dir.join( format!("{millis}-{}.png", self.image.seed) );
+		std::fs::write( &path, self.png.as_slice() )?;
+		Ok(path)
+	}
+}
+
+/// Generated images and the grid selection, shared between the UI and the generation thread.
+///
+/// Provided as a context by `app` so `make_item` can reach it from inside the grid builder.
+#[derive(Clone)]
+pub struct Outputs {
+	/// Where kept images are saved
+	dir : PathBuf,
+	images : Arc<Mutex<Vec<OutputImage>>>,
+	selected : Arc<Mutex<Option<usize>>>,
+
+	/// Repaints the owner of the grid
+	update : Arc<dyn Fn() + Send + Sync>,
+}
+
+impl Outputs {
+	pub fn new(dir:impl Into<PathBuf>, update:Arc<dyn Fn() + Send + Sync>) -> Self {
+		Self { dir : dir.into(), images : Default::default(), selected : Default::default(), update }
+	}
+
+	pub fn dir(&self) -> &Path {
+		&self.dir
+	}
+
+	pub fn push(&self, image:OutputImage) {
+		self.images.lock().unwrap().push(image);
+	}
+
+	/// Add `image` to the grid and save it to [`Outputs::dir`]. It stays in the grid when saving fails
+	pub fn keep(&self, image:OutputImage) -> std::io::Result<PathBuf> {
+		let saved = image.save(&self.dir);
+		self.push(image);
+		saved
+	}
+
+	pub fn get(&self, index:usize) -> Option<OutputImage> {
+		self.images.lock().unwrap().get(index).cloned()
+	}
+
+	pub fn len(&self) -> usize {
+		self.images.lock().unwrap().len()
+	}
+
+	pub fn last(&self) -> Option<OutputImage> {
+		self.images.lock().unwrap().last().cloned()
+	}
+
+	pub fn select(&self, index:usize) {
+		*self.selected.lock().unwrap() = Some(index);
+		(self.update)();
+	}
+
+	pub fn selected_index(&self) -> Option<usize> {
+		*self.selected.lock().unwrap()
+	}
+
+	pub fn selected(&self) -> Option<OutputImage> {
+		self.get( self.selected_index()? )
+	}
+}
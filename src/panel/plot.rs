@@ -0,0 +1,89 @@
+use freya::prelude::*;
+
+use crate::panel::models::Models;
+use crate::panel::outputs::Outputs;
+use crate::panel::txt2img::FormValues;
+use crate::plot::{self, Axis, AxisKind, Plot, Plots};
+use crate::prompt::dynamic::Wildcards;
+use crate::queue::JobQueue;
+
+const AXIS_NAMES:[&str;3] = ["X", "Y", "Z"];
+
+/// Axis picker of a plot row, `None` leaves the axis out
+#[derive(Debug, Default, Clone, PartialEq)]
+struct AxisInput {
+	kind : Option<AxisKind>,
+	values : String,
+}
+
+/// X/Y/Z plot settings. Queues one job per combination of the form's settings and the axis values.
+/// Needs the [`JobQueue`], [`Plots`] and [`Outputs`] contexts and the shared [`FormValues`]
+#[allow(non_snake_case)]
+pub fn PlotPanel(cx:Scope) -> Element {
+	let values = use_shared_state::<FormValues>(cx).unwrap();
+	let inputs = use_state(cx, <[AxisInput;3]>::default);
+	let error = use_state(cx, || None::<String>);
+	let queue = cx.consume_context::<JobQueue>()?;
+	let plots = cx.consume_context::<Plots>()?;
+	let outputs = cx.consume_context::<Outputs>();
+	let models = cx.consume_context::<Models>();
+	let wildcards = cx.consume_context::<Wildcards>().unwrap_or_default();
+
+	let onclick = move |_| {
+		let models = models.as_ref().map( Models::model_sets ).unwrap_or_default();
+		let axes = inputs.get().iter()
+			.filter_map( |i| i.kind.map( |kind| Axis::parse( kind, &i.values, &models ) ) )
+			.collect::<Result<Vec<_>, _>>();
+		let last_seed = outputs.as_ref().and_then( Outputs::last ).map( |o| o.image.seed );
+		let result = axes.and_then( |axes| {
+			if axes.is_empty() {
+				return Err( "Pick at least one axis".to_string() )
+			}
+			// The first job of the form has the seed and dynamic prompts settled, every cell shares them
+			let template = values.read().to_jobs(last_seed, &wildcards)?.into_iter().next().ok_or_else( || "Nothing to plot".to_string() )?;
+			let template = template.with_seed( template.seed() );
+			let jobs = plot::jobs( &template, &axes )?;
+			queue.push_all( jobs, |ids| plots.add( Plot::new( template, axes, ids ) ) );
+			Ok(())
+		} );
+		error.set( result.err() );
+	};
+
+	let rows = inputs.get().clone();
+	render!( rect {
+		direction : "vertical",
+		rows.into_iter().enumerate().map( |(i, input)| {
+			let name = AXIS_NAMES[i];
+			let kind = input.kind.map_or( "None".to_string(), |k| k.to_string() );
+			rsx!( rect {
+				direction : "horizontal",
+				margin : "2 5",
+				label { width : "20", margin : "6 0", "{name}" }
+				Dropdown {
+					value : kind,
+					DropdownItem {
+						value : "None".to_string(),
+						onclick : move |_| inputs.with_mut( |inputs| inputs[i].kind = None ),
+						label { "None" }
+					}
+					AxisKind::ALL.iter().map( |&k| rsx!(
+						DropdownItem {
+							value : k.to_string(),
+							onclick : move |_| inputs.with_mut( |inputs| inputs[i].kind = Some(k) ),
+							label { "{k}" }
+						}
+					) )
+				}
+				Input { value : input.values, onchange : move |e| inputs.with_mut( |inputs| inputs[i].values = e ) }
+			} )
+		} )
+		rect {
+			direction : "horizontal",
+			margin : "2 5",
+			Button { onclick : onclick, label { "Generate plot" } }
+			if let Some(e) = error.get() {
+				rsx!( label { color : "rgb(255,120,120)", margin : "6", "{e}" } )
+			}
+		}
+	} )
+}
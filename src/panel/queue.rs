@@ -0,0 +1,93 @@
+use freya::prelude::*;
+
+use crate::component::prelude::*;
+use crate::generation::Progress;
+use crate::queue::{Job, JobQueue, JobState};
+
+fn state_text(state:&JobState) -> String {
+	match state {
+		JobState::Queued => "Queued".to_string(),
+		JobState::Running(Progress::Loading) => "Loading model".to_string(),
+		JobState::Running(Progress::Step { step, total }) => format!("Step {step}/{total}"),
+		JobState::Running(Progress::Decoding) => "Decoding".to_string(),
+		JobState::Done => "Done".to_string(),
+		JobState::Failed(e) => format!("Failed : {e}"),
+		JobState::Cancelled => "Cancelled".to_string(),
+	}
+}
+
+/// 0 ~ 100
+fn percent(state:&JobState) -> f32 {
+	match state {
+		JobState::Running(Progress::Step { step, total }) => *step as f32 * 100. / (*total).max(1) as f32,
+		JobState::Running(Progress::Decoding) | JobState::Done => 100.,
+		_ => 0.,
+	}
+}
+
+#[inline_props]
+fn JobRow(cx:Scope, job:Job) -> Element {
+	let queue = cx.consume_context::<JobQueue>()?;
+	let id = job.id;
+	let text = state_text(&job.state);
+	let progress = percent(&job.state);
+	let queued = job.state == JobState::Queued;
+	let prompt = job.request.prompt.chars().take(40).collect::<String>();
+	let r = &job.request;
+	let summary = format!("{}, {} steps, {}x{}", r.sampler, r.steps, r.width, r.height);
+
+	let (q_up, q_down, q_remove) = (queue.clone(), queue.clone(), queue);
+	render!( rect {
+		width : "100%",
+		padding : "4",
+		direction : "vertical",
+		label { "#{id} {prompt}" }
+		label { color : "rgb(180,180,180)", "{summary}" }
+		rect {
+			width : "100%",
+			direction : "horizontal",
+			label { width : "calc(100% - 90)", "{text}" }
+			if queued {
+				rsx!(
+					Button { onclick : move |_| q_up.move_job(id, -1), label { "▲" } }
+					Button { onclick : move |_| q_down.move_job(id, 1), label { "▼" } }
+				)
+			}
+			Button { onclick : move |_| q_remove.remove(id), label { "✕" } }
+		}
+		rect {
+			width : "100%",
+			height : "3",
+			background : "rgb(50,50,50)",
+			rect { width : "{progress}%", height : "100%", background : "rgb(131,197,190)" }
+		}
+	} )
+}
+
+/// Job list with progress, cancel and reordering.
+/// Needs a [`JobQueue`] context.
+#[allow(non_snake_case)]
+pub fn QueuePanel(cx:Scope) -> Element {
+	let queue = cx.consume_context::<JobQueue>()?;
+	let jobs = queue.jobs();
+
+	render!( rect {
+		width : "100%",
+		height : "100%",
+		direction : "vertical",
+		rect {
+			width : "100%",
+			direction : "horizontal",
+			label { margin : "6", "Queue ({jobs.len()})" }
+			Button { onclick : move |_| queue.clear_finished(), label { "Clear finished" } }
+		}
+		HR {}
+		ScrollView {
+			width : "100%",
+			height : "calc(100% - 60)",
+			for job in jobs {
+				JobRow { key : "{job.id}", job : job }
+			}
+		}
+	} )
+}
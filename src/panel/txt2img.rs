@@ -0,0 +1,713 @@
+use std::borrow::Cow;
+use std::fmt;
+use std::sync::Arc;
+
+use freya::prelude::*;
+
+use crate::component::prelude::*;
+use crate::component::auto_complete::last_word;
+use crate::generation::{plan_batches, random_seed, GenerationMode, GenerationRequest, HiresFix, Mask, ModelSet, RgbImage, Sampler, SeedMode, Variation, LATENT_UPSCALER};
+use crate::generation::upscale::Upscalers;
+use crate::metadata::{self, Imported, Parameters};
+use crate::panel::models::{ModelPicker, Models};
+use crate::prompt::chunks;
+use crate::prompt::clip::ClipTokenizer;
+use crate::prompt::dynamic::{self, DynamicMode, Wildcards};
+use crate::panel::outputs::Outputs;
+use crate::panel::canvas::OutpaintPanel;
+use crate::panel::plot::PlotPanel;
+use crate::panel::walk::WalkPanel;
+
+/// Form mode
+#[derive(Debug, Default, PartialEq, Clone, Copy)]
+pub enum FormMode {
+	#[default]
+	Txt2Img,
+	Img2Img,
+	Inpaint,
+}
+
+impl FormMode {
+	pub const ALL:[FormMode;3] = [FormMode::Txt2Img, FormMode::Img2Img, FormMode::Inpaint];
+
+	pub fn uses_source(&self) -> bool {
+		matches!( self, Self::Img2Img | Self::Inpaint )
+	}
+}
+
+impl fmt::Display for FormMode {
+	fn fmt(&self, f:&mut fmt::Formatter<'_>) -> fmt::Result {
+		f.write_str( match self {
+			Self::Txt2Img => "txt2img",
+			Self::Img2Img => "img2img",
+			Self::Inpaint => "inpaint",
+		} )
+	}
+}
+
+/// Source image of img2img with its encoded preview
+#[derive(Debug, Clone, PartialEq)]
+pub struct SourceImage {
+	pub image : Arc<RgbImage>,
+	pub png : Arc<Vec<u8>>,
+}
+
+impl SourceImage {
+	pub fn new(image:Arc<RgbImage>) -> Self {
+		let png = Arc::new( image.to_png().unwrap_or_default() );
+		Self { image, png }
+	}
+
+	pub fn load(path:&str) -> Result<Self, String> {
+		let bytes = std::fs::read(path).map_err( |e| format!("Can't read '{path}' : {e}") )?;
+		let image = RgbImage::from_png(&bytes).map_err( |e| format!("Can't decode '{path}' : {e}") )?;
+		Ok( Self { image : Arc::new(image), png : Arc::new(bytes) } )
+	}
+}
+
+/// Raw text of the txt2img form.
+///
+/// Kept as strings so half typed numbers don't get lost. Provided as shared state by `app`
+/// so other panels can fill the form.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FormValues {
+	pub mode : FormMode,
+
+	/// `None` uses the backend default
+	pub model : Option<ModelSet>,
+	pub prompt : String,
+	pub negative_prompt : String,
+	pub long_prompts : bool,
+
+	/// How `{a|b}` choices and `__wildcards__` expand
+	pub dynamic_mode : DynamicMode,
+	pub width : String,
+	pub height : String,
+	pub tileable : bool,
+	pub steps : String,
+	pub sampler : Sampler,
+	pub cfg_scale : String,
+	pub seed_mode : SeedMode,
+	pub seed : String,
+
+	/// Strength 0 turns variations off
+	pub variation_seed : String,
+	pub variation_strength : String,
+
+	/// Jobs queued per click
+	pub batch_count : String,
+
+	/// Images per job
+	pub batch_size : String,
+
+	/// img2img denoising strength
+	pub strength : String,
+	pub source_path : String,
+	pub source : Option<SourceImage>,
+
+	/// Inpaint mask, same size as `source`
+	pub mask : Option<Mask>,
+
+	/// txt2img hi-res pass
+	pub hires : bool,
+	pub hires_scale : String,
+	pub hires_upscaler : String,
+
+	/// 0 uses `steps`
+	pub hires_steps : String,
+	pub hires_strength : String,
+
+	/// Settings of the last imported image the form has no field for
+	pub extra : Vec<(String, String)>,
+}
+
+impl Default for FormValues {
+	fn default() -> Self {
+		Self::from_request( &GenerationRequest::default() )
+	}
+}
+
+impl FormValues {
+	pub fn from_request(r:&GenerationRequest) -> Self {
+		let (mode, strength, source, mask) = match &r.mode {
+			GenerationMode::Txt2Img => (FormMode::Txt2Img, 0.75, None, None),
+			GenerationMode::Img2Img { source, strength } => (FormMode::Img2Img, *strength, Some( SourceImage::new(source.clone()) ), None),
+			GenerationMode::Inpaint { source, mask, strength } => (FormMode::Inpaint, *strength, Some( SourceImage::new(source.clone()) ), Some( (**mask).clone() )),
+		};
+		let hires = r.hires.clone().unwrap_or_default();
+		let variation = r.variation.unwrap_or( Variation { seed : 0, strength : 0. } );
+		Self {
+			mode,
+			model : r.model.clone(),
+			prompt : r.prompt.clone(),
+			negative_prompt : r.negative_prompt.clone(),
+			long_prompts : r.long_prompts,
+			dynamic_mode : DynamicMode::default(),
+			width : r.width.to_string(),
+			height : r.height.to_string(),
+			tileable : r.tileable,
+			steps : r.steps.to_string(),
+			sampler : r.sampler,
+			cfg_scale : r.cfg_scale.to_string(),
+			seed_mode : SeedMode::Fixed,
+			seed : r.seed().to_string(),
+			variation_seed : variation.seed.to_string(),
+			variation_strength : variation.strength.to_string(),
+			batch_count : "1".to_string(),
+			batch_size : r.seeds.len().max(1).to_string(),
+			strength : strength.to_string(),
+			source_path : String::new(),
+			source,
+			mask,
+			hires : r.hires.is_some(),
+			hires_scale : hires.scale.to_string(),
+			hires_upscaler : hires.upscaler.clone(),
+			hires_steps : hires.steps.to_string(),
+			hires_strength : hires.strength.to_string(),
+			extra : vec![],
+		}
+	}
+
+	/// Fill the form from the parameters embedded in PNG `bytes`
+	pub fn import_png(&mut self, bytes:&[u8], models:&[ModelSet]) -> Result<(), String> {
+		let imported = metadata::read_png(bytes).ok_or("No generation parameters in this image")?;
+		self.apply_import( &imported, models );
+		Ok(())
+	}
+
+	/// Fill the form from parameters read out of a PNG. Settings that don't map to a field,
+	/// and a model that isn't in `models`, end up in [`FormValues::extra`]
+	pub fn apply_import(&mut self, imported:&Imported, models:&[ModelSet]) {
+		self.apply_parameters( &imported.parameters, models );
+		self.extra.extend( imported.other.iter().cloned() );
+	}
+
+	pub fn apply_parameters(&mut self, p:&Parameters, models:&[ModelSet]) {
+		self.prompt = p.prompt.clone();
+		self.negative_prompt = p.negative_prompt.clone();
+		self.extra.clear();
+		self.batch_count = "1".to_string();
+		self.batch_size = "1".to_string();
+		self.long_prompts = true;
+		self.tileable = false;
+		self.variation_strength = "0".to_string();
+		self.hires = false;
+		// With a hi-res pass, A1111 stores its strength as `Denoising strength`
+		let has_hires = p.get("Hires upscale").is_some();
+
+		let hash = p.get("Model hash");
+		let name = p.get("Model");
+		let model = models.iter().find( |m| hash.is_some() && m.hash.as_deref() == hash )
+			.or_else( || models.iter().find( |m| name.is_some() && Some(m.name.as_str()) == name ) );
+		if let Some(model) = model {
+			self.model = Some( model.clone() );
+		}
+
+		for (key, value) in &p.settings {
+			let known = match key.as_str() {
+				"Steps" => { self.steps = value.clone(); true }
+				"CFG scale" => { self.cfg_scale = value.clone(); true }
+				"Seed" => {
+					self.seed = value.clone();
+					self.seed_mode = SeedMode::Fixed;
+					true
+				}
+				"Batch size" => { self.batch_size = value.clone(); true }
+				"Variation seed" => { self.variation_seed = value.clone(); true }
+				"Variation seed strength" => { self.variation_strength = value.clone(); true }
+				"Long prompts" => { self.long_prompts = value != "off"; true }
+				"Tiling" => { self.tileable = value.eq_ignore_ascii_case("true"); true }
+				"Denoising strength" if has_hires => { self.hires_strength = value.clone(); true }
+				"Denoising strength" => { self.strength = value.clone(); true }
+				"Hires upscale" => {
+					self.hires = true;
+					self.hires_scale = value.clone();
+					true
+				}
+				"Hires steps" => { self.hires_steps = value.clone(); true }
+				"Hires upscaler" => { self.hires_upscaler = value.clone(); true }
+				"Size" => match value.split_once('x') {
+					Some((w, h)) => {
+						self.width = w.trim().to_string();
+						self.height = h.trim().to_string();
+						true
+					}
+					None => false,
+				},
+				"Sampler" => match value.parse() {
+					Ok(sampler) => { self.sampler = sampler; true }
+					Err(_) => false,
+				},
+				"Model" | "Model hash" => model.is_some(),
+				_ => false,
+			};
+			if !known {
+				self.extra.push( (key.clone(), value.clone()) );
+			}
+		}
+	}
+
+	/// Use `source` for img2img or inpainting and take over its size. Resets the mask
+	pub fn set_source(&mut self, source:SourceImage) {
+		self.width = source.image.width.to_string();
+		self.height = source.image.height.to_string();
+		if !self.mode.uses_source() {
+			self.mode = FormMode::Img2Img;
+		}
+		self.mask = Some( Mask::new( source.image.width, source.image.height ) );
+		self.source = Some(source);
+	}
+
+	/// Build the jobs of one click. `last_seed` is the seed of the newest output, for [`SeedMode::ReuseLast`].
+	/// Dynamic prompts are expanded here, so every job carries its final prompt
+	pub fn to_jobs(&self, last_seed:Option<u64>, wildcards:&Wildcards) -> Result<Vec<GenerationRequest>, String> {
+		let template = self.to_request()?;
+		let count = parse::<usize>("batch count", &self.batch_count)?;
+		let size = parse::<usize>("batch size", &self.batch_size)?;
+		let plan = |template:&GenerationRequest| plan_batches( template, self.seed_mode, template.seed(), last_seed, count, size, random_seed );
+
+		match self.dynamic_mode {
+			DynamicMode::Random => plan(&template).into_iter().map( |mut job| {
+				job.prompt = dynamic::expand_random( &job.prompt, wildcards, job.seed() )?;
+				job.negative_prompt = dynamic::expand_random( &job.negative_prompt, wildcards, job.seed() )?;
+				Ok(job)
+			} ).collect(),
+			DynamicMode::Combinatorial => {
+				let prompts = dynamic::expand_all( &template.prompt, wildcards )?;
+				let negatives = dynamic::expand_all( &template.negative_prompt, wildcards )?;
+				let total = prompts.len().saturating_mul( negatives.len() ).saturating_mul( count.max(1) );
+				if total > dynamic::MAX_COMBINATIONS {
+					return Err( format!("{total} jobs, at most {} are allowed", dynamic::MAX_COMBINATIONS) )
+				}
+				let mut jobs = vec![];
+				for prompt in &prompts {
+					for negative_prompt in &negatives {
+						let template = GenerationRequest { prompt : prompt.clone(), negative_prompt : negative_prompt.clone(), ..template.clone() };
+						jobs.extend( plan(&template) );
+					}
+				}
+				Ok(jobs)
+			}
+		}
+	}
+
+	/// Build a single image request. Returns the first invalid field on error
+	pub fn to_request(&self) -> Result<GenerationRequest, String> {
+		fn size(name:&str, v:&str) -> Result<u32, String> {
+			match parse::<u32>(name, v)? {
+				0 => Err( format!("{name} must be greater than 0") ),
+				s => Ok( (s + 7) / 8 * 8 ),
+			}
+		}
+
+		let steps = match parse::<usize>("steps", &self.steps)? {
+			0 => return Err( "steps must be greater than 0".to_string() ),
+			s => s,
+		};
+
+		let mode = match self.mode {
+			FormMode::Txt2Img => GenerationMode::Txt2Img,
+			FormMode::Img2Img | FormMode::Inpaint => {
+				let source = self.source.as_ref().ok_or("Pick a source image")?.image.clone();
+				let strength = parse::<f64>("strength", &self.strength)?;
+				if !(0. ..= 1.).contains(&strength) {
+					return Err( "strength must be between 0 and 1".to_string() )
+				}
+				if self.mode == FormMode::Img2Img {
+					GenerationMode::Img2Img { source, strength }
+				} else {
+					match &self.mask {
+						Some(mask) if !mask.is_empty() => GenerationMode::Inpaint { source, mask : Arc::new( mask.clone() ), strength },
+						_ => return Err( "Paint a mask first".to_string() ),
+					}
+				}
+			}
+		};
+		let variation = match parse::<f64>("variation strength", &self.variation_strength)? {
+			s if s == 0. => None,
+			s if (0. ..= 1.).contains(&s) => Some( Variation { seed : parse("variation seed", &self.variation_seed)?, strength : s } ),
+			_ => return Err( "variation strength must be between 0 and 1".to_string() ),
+		};
+		let hires = match (self.mode, self.hires) {
+			(FormMode::Txt2Img, true) => {
+				let scale = parse::<f64>("hires upscale", &self.hires_scale)?;
+				let strength = parse::<f64>("hires strength", &self.hires_strength)?;
+				if scale <= 0. {
+					return Err( "hires upscale must be greater than 0".to_string() )
+				}
+				if !(0. ..= 1.).contains(&strength) {
+					return Err( "hires strength must be between 0 and 1".to_string() )
+				}
+				Some( HiresFix { scale, upscaler : self.hires_upscaler.clone(), steps : parse("hires steps", &self.hires_steps)?, strength } )
+			}
+			_ => None,
+		};
+
+		Ok( GenerationRequest {
+			mode,
+			model : self.model.clone(),
+			prompt : self.prompt.clone(),
+			negative_prompt : self.negative_prompt.clone(),
+			long_prompts : self.long_prompts,
+			width : size("width", &self.width)?,
+			height : size("height", &self.height)?,
+			tileable : self.tileable,
+			steps,
+			sampler : self.sampler,
+			cfg_scale : parse("CFG scale", &self.cfg_scale)?,
+			seeds : vec![ if self.seed_mode.uses_seed() { parse("seed", &self.seed)? } else { 0 } ],
+			variation,
+			hires,
+		} )
+	}
+}
+
+fn parse<T:std::str::FromStr>(name:&str, v:&str) -> Result<T, String> {
+	v.trim().parse().map_err( |_| format!("Invalid {name} : '{v}'") )
+}
+
+/// One labeled single line input
+#[inline_props]
+fn Field<'a>(cx:Scope<'a>, name:&'static str, value:String, onchange:EventHandler<'a, String>) -> Element<'a> {
+	render!( rect {
+		direction : "horizontal",
+		margin : "2 5",
+		label { width : "90", "{name}" }
+		Input { value : value.clone(), onchange : move |e| onchange.call(e) }
+	} )
+}
+
+/// img2img source picker. Takes the selected grid image or a PNG file
+#[allow(non_snake_case)]
+fn SourcePicker(cx:Scope) -> Element {
+	let values = use_shared_state::<FormValues>(cx).unwrap();
+	let error = use_state(cx, || None::<String>);
+	let outputs = cx.consume_context::<Outputs>();
+
+	let v = values.read().clone();
+	let preview = v.source.as_ref().map( |s| bytes_to_data(cx, &s.png) );
+
+	let from_selected = move |_| {
+		match outputs.as_ref().and_then( Outputs::selected ) {
+			Some(o) => {
+				error.set(None);
+				values.write().set_source( SourceImage::new( Arc::new(o.image.image) ) );
+			}
+			None => error.set( Some("Select an image in the grid first".to_string()) ),
+		}
+	};
+	let from_file = move |_| {
+		let path = values.read().source_path.clone();
+		match SourceImage::load(&path) {
+			Ok(source) => {
+				error.set(None);
+				values.write().set_source(source);
+			}
+			Err(e) => error.set( Some(e) ),
+		}
+	};
+
+	render!( rect {
+		direction : "horizontal",
+		margin : "2 5",
+		rect {
+			width : "64",
+			height : "64",
+			background : "rgb(50,50,50)",
+			if let Some(data) = preview {
+				rsx!( image { image_data : data, width : "64", height : "64" } )
+			}
+		}
+		rect {
+			direction : "vertical",
+			rect {
+				direction : "horizontal",
+				Button { onclick : from_selected, label { "Use selected" } }
+				Input { value : v.source_path, onchange : move |e| values.write().source_path = e }
+				Button { onclick : from_file, label { "Load" } }
+			}
+			Field { name : "Strength", value : v.strength, onchange : move |e| values.write().strength = e }
+			if let Some(e) = error.get() {
+				rsx!( label { color : "rgb(255,120,120)", "{e}" } )
+			}
+		}
+	} )
+}
+
+/// Words offered while typing a prompt, like embedding trigger words
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Completions {
+	pub words : Vec<String>,
+}
+
+impl Completions {
+	/// Up to `limit` words that complete the last word of `text`
+	pub fn matches(&self, text:&str, limit:usize) -> Vec<String> {
+		let partial = last_word(text).to_lowercase();
+		if partial.chars().count() < 2 {
+			return vec![]
+		}
+		self.words.iter()
+			.filter( |w| w.to_lowercase().starts_with(&partial) && w.len() > partial.len() )
+			.take(limit)
+			.cloned()
+			.collect()
+	}
+}
+
+/// Prompt input with its CLIP token count, completing embedding names from the [`Models`] context.
+/// The count is the longest prompt the text expands and schedules to over `steps` steps, per
+/// chunk for long prompts. Long prompts list their chunks below the input, truncated ones show the
+/// cut off words in red. Needs the `Arc<ClipTokenizer>` context for the count
+#[inline_props]
+fn PromptField<'a>(cx:Scope<'a>, name:&'static str, value:String, steps:usize, long_prompts:bool, onchange:EventHandler<'a, String>) -> Element<'a> {
+	let wildcards = cx.consume_context::<Wildcards>().unwrap_or_default();
+	let split = cx.consume_context::<Arc<ClipTokenizer>>()
+		.and_then( |tokenizer| chunks::split_longest( value, *steps, &wildcards, &*tokenizer ).ok() );
+	let count = split.as_deref().map_or( 0, chunks::token_count );
+	let counts = match (*long_prompts, &split) {
+		(true, Some(split)) => split.iter().map( |c| format!("{}/{}", c.len(), chunks::CHUNK_LEN) ).collect::<Vec<_>>().join(" + "),
+		_ => format!("{count}/{}", chunks::CHUNK_LEN),
+	};
+	let over = !*long_prompts && count > chunks::CHUNK_LEN;
+	let count_color = if over { "rgb(255,120,120)" } else { "rgb(180,180,180)" };
+	let overflow = match (&split, over) {
+		(Some(split), true) => split.iter().flat_map( |c| &c.tokens ).skip( chunks::CHUNK_LEN )
+			.filter( |t| !t.word.is_empty() )
+			.map( |t| t.word.as_str() )
+			.collect::<Vec<_>>()
+			.join(" "),
+		_ => String::new(),
+	};
+	let shown_chunks = split.filter( |s| *long_prompts && s.len() > 1 ).unwrap_or_default();
+	let hints = cx.consume_context::<Models>()
+		.map( |m| Completions { words : m.embedding_names() }.matches( value, 8 ) )
+		.unwrap_or_default();
+
+	render!( rect {
+		direction : "vertical",
+		rect {
+			direction : "horizontal",
+			margin : "2 5",
+			label { width : "90", "{name}" }
+			SimpleWordComplete { value : value.clone(), hints : hints, onchange : move |e| onchange.call(e) }
+			if count > 0 {
+				rsx!( label { margin : "6 0", color : count_color, "{counts}" } )
+			}
+		}
+		if !overflow.is_empty() {
+			rsx!( label { margin : "0 5 2 95", color : "rgb(255,120,120)", "Truncated : {overflow}" } )
+		}
+		shown_chunks.iter().enumerate().map( |(i, chunk)| {
+			let (n, len, text) = (i + 1, chunk.len(), chunk.text());
+			rsx!( label { margin : "0 5 0 95", color : "rgb(180,180,180)", "Chunk {n} ({len}/{chunks::CHUNK_LEN}) : {text}" } )
+		} )
+	} )
+}
+
+/// Imported settings the form can't use, with a button to drop them
+#[inline_props]
+fn ImportedExtra(cx:Scope, extra:Vec<(String, String)>) -> Element {
+	let values = use_shared_state::<FormValues>(cx).unwrap();
+	let text = extra.iter().map( |(k, v)| format!("{k}: {v}") ).collect::<Vec<_>>().join(", ");
+	render!( rect {
+		direction : "horizontal",
+		margin : "2 5",
+		label { width : "90", "Not applied" }
+		label { color : "rgb(200,200,200)", "{text}" }
+		Button { onclick : move |_| values.write().extra.clear(), label { "✕" } }
+	} )
+}
+
+/// [`Txt2Img`] component properties.
+#[derive(Props)]
+pub struct Txt2ImgProps<'a> {
+	/// Called with the jobs built from the form
+	onsubmit : EventHandler<'a, Vec<GenerationRequest>>,
+}
+
+/// Generation form. Reads and writes the shared [`FormValues`].
+#[allow(non_snake_case)]
+pub fn Txt2Img<'a>(cx:Scope<'a, Txt2ImgProps<'a>>) -> Element<'a> {
+	let values = use_shared_state::<FormValues>(cx).unwrap();
+	let error = use_state(cx, || None::<String>);
+	let outputs = cx.consume_context::<Outputs>();
+	let wildcards = cx.consume_context::<Wildcards>().unwrap_or_default();
+	let upscalers = cx.consume_context::<Upscalers>().unwrap_or_default();
+
+	let v = values.read().clone();
+	let steps = v.steps.trim().parse().unwrap_or( GenerationRequest::default().steps );
+	let is_dynamic = dynamic::is_dynamic(&v.prompt) || dynamic::is_dynamic(&v.negative_prompt);
+	let long_prompts_label = if v.long_prompts { "Long prompts : chunked" } else { "Long prompts : truncated" };
+	let hires_label = if v.hires { "Hi-res fix : on" } else { "Hi-res fix : off" };
+	let tileable_label = if v.tileable { "Tileable : on" } else { "Tileable : off" };
+	let onclick = move |_| {
+		let last_seed = outputs.as_ref().and_then( Outputs::last ).map( |o| o.image.seed );
+		match values.read().to_jobs(last_seed, &wildcards) {
+			Ok(jobs) => {
+				error.set(None);
+				cx.props.onsubmit.call(jobs);
+			}
+			Err(e) => error.set(Some(e)),
+		}
+	};
+
+	render!( rect {
+		width : "100%",
+		direction : "vertical",
+		ModelPicker {}
+		Dropdown {
+			value : v.mode.to_string(),
+			FormMode::ALL.iter().map( |&mode| rsx!(
+				DropdownItem {
+					value : mode.to_string(),
+					onclick : move |_| values.write().mode = mode,
+					label { "{mode}" }
+				}
+			) )
+		}
+		PromptField { name : "Prompt", value : v.prompt, steps : steps, long_prompts : v.long_prompts, onchange : move |e| values.write().prompt = e }
+		PromptField { name : "Negative", value : v.negative_prompt, steps : steps, long_prompts : v.long_prompts, onchange : move |e| values.write().negative_prompt = e }
+		rect {
+			direction : "horizontal",
+			margin : "2 5",
+			Button {
+				onclick : move |_| { let mut form = values.write(); form.long_prompts = !form.long_prompts; },
+				label { "{long_prompts_label}" }
+			}
+			if is_dynamic {
+				rsx!( Dropdown {
+					value : v.dynamic_mode.to_string(),
+					DynamicMode::ALL.iter().map( |&mode| rsx!(
+						DropdownItem {
+							value : mode.to_string(),
+							onclick : move |_| values.write().dynamic_mode = mode,
+							label { "{mode}" }
+						}
+					) )
+				} )
+			}
+		}
+		if v.mode.uses_source() {
+			rsx!( SourcePicker {} )
+		}
+		if let (FormMode::Inpaint, Some(source), Some(mask)) = (v.mode, &v.source, &v.mask) {
+			rsx!( MaskPainter {
+				source_png : source.png.clone(),
+				mask : mask.clone(),
+				onchange : move |m| values.write().mask = Some(m),
+			} )
+		}
+		rect {
+			direction : "horizontal",
+			Field { name : "Width", value : v.width, onchange : move |e| values.write().width = e }
+			Field { name : "Height", value : v.height, onchange : move |e| values.write().height = e }
+			Button {
+				onclick : move |_| { let mut form = values.write(); form.tileable = !form.tileable; },
+				label { "{tileable_label}" }
+			}
+		}
+		rect {
+			direction : "horizontal",
+			Field { name : "Steps", value : v.steps, onchange : move |e| values.write().steps = e }
+			Dropdown {
+				value : v.sampler.to_string(),
+				Sampler::ALL.iter().map( |&sampler| rsx!(
+					DropdownItem {
+						value : sampler.to_string(),
+						onclick : move |_| values.write().sampler = sampler,
+						label { "{sampler}" }
+					}
+				) )
+			}
+			Field { name : "CFG scale", value : v.cfg_scale, onchange : move |e| values.write().cfg_scale = e }
+		}
+		if v.mode == FormMode::Txt2Img {
+			rsx!( Detail {
+				summary : Cow::Borrowed("Hi-res fix"),
+				rect {
+					direction : "vertical",
+					rect {
+						direction : "horizontal",
+						margin : "2 5",
+						Button {
+							onclick : move |_| { let mut form = values.write(); form.hires = !form.hires; },
+							label { "{hires_label}" }
+						}
+						Dropdown {
+							value : v.hires_upscaler.clone(),
+							std::iter::once(LATENT_UPSCALER).chain( upscalers.all().iter().map( |u| u.name() ) ).map( |name| {
+								let (name, picked) = (name.to_string(), name.to_string());
+								rsx!(
+									DropdownItem {
+										value : name.clone(),
+										onclick : move |_| values.write().hires_upscaler = picked.clone(),
+										label { "{name}" }
+									}
+								)
+							} )
+						}
+					}
+					rect {
+						direction : "horizontal",
+						Field { name : "Upscale by", value : v.hires_scale, onchange : move |e| values.write().hires_scale = e }
+						Field { name : "Hires steps", value : v.hires_steps, onchange : move |e| values.write().hires_steps = e }
+						Field { name : "Denoise", value : v.hires_strength, onchange : move |e| values.write().hires_strength = e }
+					}
+				}
+			} )
+		}
+		rect {
+			direction : "horizontal",
+			Dropdown {
+				value : v.seed_mode.to_string(),
+				SeedMode::ALL.iter().map( |&mode| rsx!(
+					DropdownItem {
+						value : mode.to_string(),
+						onclick : move |_| values.write().seed_mode = mode,
+						label { "{mode}" }
+					}
+				) )
+			}
+			if v.seed_mode.uses_seed() {
+				rsx!( Field { name : "Seed", value : v.seed, onchange : move |e| values.write().seed = e } )
+			}
+			Field { name : "Batch count", value : v.batch_count, onchange : move |e| values.write().batch_count = e }
+			Field { name : "Batch size", value : v.batch_size, onchange : move |e| values.write().batch_size = e }
+		}
+		Detail {
+			summary : Cow::Borrowed("Variation seed"),
+			rect {
+				direction : "horizontal",
+				Field { name : "Var. seed", value : v.variation_seed, onchange : move |e| values.write().variation_seed = e }
+				Field { name : "Var. strength", value : v.variation_strength, onchange : move |e| values.write().variation_strength = e }
+			}
+		}
+		Detail {
+			summary : Cow::Borrowed("X/Y/Z plot"),
+			PlotPanel {}
+		}
+		Detail {
+			summary : Cow::Borrowed("Latent walk"),
+			WalkPanel {}
+		}
+		Detail {
+			summary : Cow::Borrowed("Outpaint"),
+			OutpaintPanel {}
+		}
+		if !v.extra.is_empty() {
+			rsx!( ImportedExtra { extra : v.extra } )
+		}
+		rect {
+			direction : "horizontal",
+			margin : "2 5",
+			Button {
+				onclick : onclick,
+				label { "Generate" }
+			}
+			if let Some(e) = error.get() {
+				rsx!( label { color : "rgb(255,120,120)", margin : "6", "{e}" } )
+			}
+		}
+	} )
+}
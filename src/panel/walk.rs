@@ -0,0 +1,95 @@
+use freya::prelude::*;
+
+use crate::animation::{AnimationFormat, Animations, Keyframe, Walk};
+use crate::panel::outputs::Outputs;
+use crate::panel::txt2img::FormValues;
+use crate::prompt::dynamic::Wildcards;
+use crate::queue::JobQueue;
+
+/// Keyframe row. Empty fields use the prompt and seed of the form
+#[derive(Debug, Default, Clone, PartialEq)]
+struct KeyframeInput {
+	prompt : String,
+	seed : String,
+}
+
+/// Latent walk settings. Queues one job per frame, the animation is written when the last is done.
+/// Needs the [`JobQueue`], [`Animations`] and [`Outputs`] contexts and the shared [`FormValues`]
+#[allow(non_snake_case)]
+pub fn WalkPanel(cx:Scope) -> Element {
+	let values = use_shared_state::<FormValues>(cx).unwrap();
+	let keyframes = use_state(cx, || vec![KeyframeInput::default(); 2]);
+	let frames_between = use_state(cx, || "8".to_string());
+	let fps = use_state(cx, || "8".to_string());
+	let format = use_state(cx, AnimationFormat::default);
+	let error = use_state(cx, || None::<String>);
+	let queue = cx.consume_context::<JobQueue>()?;
+	let animations = cx.consume_context::<Animations>()?;
+	let outputs = cx.consume_context::<Outputs>();
+	let wildcards = cx.consume_context::<Wildcards>().unwrap_or_default();
+
+	let onclick = move |_| {
+		let last_seed = outputs.as_ref().and_then( Outputs::last ).map( |o| o.image.seed );
+		let result = (|| {
+			let template = values.read().to_jobs(last_seed, &wildcards)?.into_iter().next().ok_or_else( || "Nothing to walk".to_string() )?;
+			let keyframes = keyframes.get().iter().map( |k| Ok( Keyframe {
+				prompt : if k.prompt.trim().is_empty() { template.prompt.clone() } else { k.prompt.clone() },
+				seed : match k.seed.trim() {
+					"" => template.seed(),
+					seed => seed.parse().map_err( |_| format!("Invalid keyframe seed : '{seed}'") )?,
+				},
+			} ) ).collect::<Result<Vec<_>, String>>()?;
+			let walk = Walk {
+				keyframes,
+				frames_between : frames_between.trim().parse().map_err( |_| format!("Invalid frame count : '{frames_between}'") )?,
+				fps : fps.trim().parse().map_err( |_| format!("Invalid fps : '{fps}'") )?,
+				format : *format.get(),
+			};
+			let frames = walk.frames(&template)?;
+			queue.push_all( frames, |ids| animations.add( walk, ids ) );
+			Ok(())
+		})();
+		error.set( result.err() );
+	};
+
+	let rows = keyframes.get().clone();
+	render!( rect {
+		direction : "vertical",
+		rows.into_iter().enumerate().map( |(i, k)| rsx!( rect {
+			direction : "horizontal",
+			margin : "2 5",
+			label { width : "90", margin : "6 0", "Keyframe {i}" }
+			Input { value : k.prompt, onchange : move |e| keyframes.with_mut( |k| k[i].prompt = e ) }
+			label { margin : "6", "Seed" }
+			Input { value : k.seed, onchange : move |e| keyframes.with_mut( |k| k[i].seed = e ) }
+			Button { onclick : move |_| keyframes.with_mut( |k| { if k.len() > 2 { k.remove(i); } } ), label { "✕" } }
+		} ) )
+		rect {
+			direction : "horizontal",
+			margin : "2 5",
+			Button { onclick : move |_| keyframes.with_mut( |k| k.push( KeyframeInput::default() ) ), label { "Add keyframe" } }
+			label { margin : "6", "Frames between" }
+			Input { value : frames_between.get().clone(), onchange : move |e| frames_between.set(e) }
+			label { margin : "6", "FPS" }
+			Input { value : fps.get().clone(), onchange : move |e| fps.set(e) }
+			Dropdown {
+				value : format.get().to_string(),
+				AnimationFormat::ALL.iter().map( |&f| rsx!(
+					DropdownItem {
+						value : f.to_string(),
+						onclick : move |_| format.set(f),
+						label { "{f}" }
+					}
+				) )
+			}
+		}
+		rect {
+			direction : "horizontal",
+			margin : "2 5",
+			Button { onclick : onclick, label { "Queue walk" } }
+			if let Some(e) = error.get() {
+				rsx!( label { color : "rgb(255,120,120)", margin : "6", "{e}" } )
+			}
+		}
+	} )
+}
@@ -0,0 +1,77 @@
+/// 5x8 bitmap font for grid labels, printable ASCII only.
+
+use crate::generation::RgbImage;
+
+/// One byte per column, lowest bit on top. Characters `' '` to `'~'`
+const GLYPHS:[[u8;5];95] = [
+	[0x00,0x00,0x00,0x00,0x00], [0x00,0x00,0x5F,0x00,0x00], [0x00,0x07,0x00,0x07,0x00], [0x14,0x7F,0x14,0x7F,0x14],
+	[0x24,0x2A,0x7F,0x2A,0x12], [0x23,0x13,0x08,0x64,0x62], [0x36,0x49,0x56,0x20,0x50], [0x00,0x08,0x07,0x03,0x00],
+	[0x00,0x1C,0x22,0x41,0x00], [0x00,0x41,0x22,0x1C,0x00], [0x2A,0x1C,0x7F,0x1C,0x2A], [0x08,0x08,0x3E,0x08,0x08],
+	[0x00,0x80,0x70,0x30,0x00], [0x08,0x08,0x08,0x08,0x08], [0x00,0x00,0x60,0x60,0x00], [0x20,0x10,0x08,0x04,0x02],
+	[0x3E,0x51,0x49,0x45,0x3E], [0x00,0x42,0x7F,0x40,0x00], [0x72,0x49,0x49,0x49,0x46], [0x21,0x41,0x49,0x4D,0x33],
+	[0x18,0x14,0x12,0x7F,0x10], [0x27,0x45,0x45,0x45,0x39], [0x3C,0x4A,0x49,0x49,0x31], [0x41,0x21,0x11,0x09,0x07],
+	[0x36,0x49,0x49,0x49,0x36], [0x46,0x49,0x49,0x29,0x1E], [0x00,0x00,0x14,0x00,0x00], [0x00,0x40,0x34,0x00,0x00],
+	[0x00,0x08,0x14,0x22,0x41], [0x14,0x14,0x14,0x14,0x14], [0x00,0x41,0x22,0x14,0x08], [0x02,0x01,0x59,0x09,0x06],
+	[0x3E,0x41,0x5D,0x59,0x4E], [0x7C,0x12,0x11,0x12,0x7C], [0x7F,0x49,0x49,0x49,0x36], [0x3E,0x41,0x41,0x41,0x22],
+	[0x7F,0x41,0x41,0x41,0x3E], [0x7F,0x49,0x49,0x49,0x41], [0x7F,0x09,0x09,0x09,0x01], [0x3E,0x41,0x41,0x51,0x73],
+	[0x7F,0x08,0x08,0x08,0x7F], [0x00,0x41,0x7F,0x41,0x00], [0x20,0x40,0x41,0x3F,0x01], [0x7F,0x08,0x14,0x22,0x41],
+	[0x7F,0x40,0x40,0x40,0x40], [0x7F,0x02,0x1C,0x02,0x7F], [0x7F,0x04,0x08,0x10,0x7F], [0x3E,0x41,0x41,0x41,0x3E],
+	[0x7F,0x09,0x09,0x09,0x06], [0x3E,0x41,0x51,0x21,0x5E], [0x7F,0x09,0x19,0x29,0x46], [0x26,0x49,0x49,0x49,0x32],
+	[0x03,0x01,0x7F,0x01,0x03], [0x3F,0x40,0x40,0x40,0x3F], [0x1F,0x20,0x40,0x20,0x1F], [0x3F,0x40,0x38,0x40,0x3F],
+	[0x63,0x14,0x08,0x14,0x63], [0x03,0x04,0x78,0x04,0x03], [0x61,0x59,0x49,0x4D,0x43], [0x00,0x7F,0x41,0x41,0x41],
+	[0x02,0x04,0x08,0x10,0x20], [0x00,0x41,0x41,0x41,0x7F], [0x04,0x02,0x01,0x02,0x04], [0x40,0x40,0x40,0x40,0x40],
+	[0x00,0x03,0x07,0x08,0x00], [0x20,0x54,0x54,0x78,0x40], [0x7F,0x28,0x44,0x44,0x38], [0x38,0x44,0x44,0x44,0x28],
+	[0x38,0x44,0x44,0x28,0x7F], [0x38,0x54,0x54,0x54,0x18], [0x00,0x08,0x7E,0x09,0x02], [0x18,0xA4,0xA4,0x9C,0x78],
+	[0x7F,0x08,0x04,0x04,0x78], [0x00,0x44,0x7D,0x40,0x00], [0x20,0x40,0x40,0x3D,0x00], [0x7F,0x10,0x28,0x44,0x00],
+	[0x00,0x41,0x7F,0x40,0x00], [0x7C,0x04,0x78,0x04,0x78], [0x7C,0x08,0x04,0x04,0x78], [0x38,0x44,0x44,0x44,0x38],
+	[0xFC,0x18,0x24,0x24,0x18], [0x18,0x24,0x24,0x18,0xFC], [0x7C,0x08,0x04,0x04,0x08], [0x48,0x54,0x54,0x54,0x24],
+	[0x04,0x04,0x3F,0x44,0x24], [0x3C,0x40,0x40,0x20,0x7C], [0x1C,0x20,0x40,0x20,0x1C], [0x3C,0x40,0x30,0x40,0x3C],
+	[0x44,0x28,0x10,0x28,0x44], [0x4C,0x90,0x90,0x90,0x7C], [0x44,0x64,0x54,0x4C,0x44], [0x00,0x08,0x36,0x41,0x00],
+	[0x00,0x00,0x77,0x00,0x00], [0x00,0x41,0x36,0x08,0x00], [0x02,0x01,0x02,0x04,0x02],
+];
+
+/// Advance and line height of a glyph at scale 1, spacing included
+pub const ADVANCE:u32 = 6;
+pub const LINE_HEIGHT:u32 = 9;
+
+fn glyph(c:char) -> &'static [u8;5] {
+	match c {
+		' '..='~' => &GLYPHS[ c as usize - ' ' as usize ],
+		_ => &GLYPHS[ '?' as usize - ' ' as usize ],
+	}
+}
+
+pub fn text_width(text:&str, scale:u32) -> u32 {
+	text.chars().count() as u32 * ADVANCE * scale
+}
+
+/// `text` cut to fit in `width` pixels, ending in `..` when cut
+pub fn fit(text:&str, width:u32, scale:u32) -> String {
+	if text_width(text, scale) <= width {
+		return text.to_string()
+	}
+	let max = (width / (ADVANCE * scale)).saturating_sub(2) as usize;
+	format!( "{}..", text.chars().take(max).collect::<String>() )
+}
+
+/// Draw `text` with its top left corner at `x`, `y`. Pixels outside the image are skipped
+pub fn draw(image:&mut RgbImage, x:u32, y:u32, text:&str, scale:u32, color:[u8;3]) {
+	for (i, c) in text.chars().enumerate() {
+		let left = x + i as u32 * ADVANCE * scale;
+		for (col, bits) in glyph(c).iter().enumerate() {
+			for row in 0..8 {
+				if bits & (1 << row) == 0 {
+					continue
+				}
+				for dy in 0..scale {
+					for dx in 0..scale {
+						let (px, py) = (left + col as u32 * scale + dx, y + row * scale + dy);
+						if px < image.width && py < image.height {
+							image.put( px, py, color );
+						}
+					}
+				}
+			}
+		}
+	}
+}
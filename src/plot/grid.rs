@@ -0,0 +1,74 @@
+/// Labeled grid image of a plot.
+
+use crate::generation::RgbImage;
+
+use super::font;
+
+const BACKGROUND:[u8;3] = [255, 255, 255];
+const TEXT:[u8;3] = [0, 0, 0];
+const MISSING:[u8;3] = [128, 128, 128];
+
+/// Cell size when no cell has an image
+const EMPTY_CELL:u32 = 64;
+
+/// Grid of `cells`, with `x_labels` above the columns and `y_labels` left of the rows.
+/// Each Z label heads a block of its own, blocks are stacked top to bottom.
+///
+/// Cell `x, y, z` is `cells[x + nx*(y + ny*z)]`, an axis without labels counts as one value.
+/// `None` cells, of failed jobs, are left gray
+pub fn compose(cells:&[Option<&RgbImage>], x_labels:&[String], y_labels:&[String], z_labels:&[String]) -> RgbImage {
+	let (nx, ny, nz) = (x_labels.len().max(1), y_labels.len().max(1), z_labels.len().max(1));
+	let cell_w = cells.iter().flatten().map( |c| c.width ).max().unwrap_or(EMPTY_CELL);
+	let cell_h = cells.iter().flatten().map( |c| c.height ).max().unwrap_or(EMPTY_CELL);
+
+	let scale = (cell_w / 256).max(1);
+	let pad = 4 * scale;
+	let line = font::LINE_HEIGHT * scale + 2 * pad;
+	let header_h = if x_labels.is_empty() { 0 } else { line };
+	let title_h = if z_labels.is_empty() { 0 } else { line };
+	let left_w = y_labels.iter()
+		.map( |l| font::text_width(l, scale) + 2 * pad )
+		.max()
+		.map_or( 0, |w| w.min(cell_w) );
+	let block_h = title_h + header_h + ny as u32 * cell_h;
+
+	let mut out = RgbImage::solid( left_w + nx as u32 * cell_w, nz as u32 * block_h, BACKGROUND );
+	for z in 0..nz {
+		let top = z as u32 * block_h;
+		if let Some(title) = z_labels.get(z) {
+			let text = font::fit( title, out.width - 2 * pad, scale );
+			font::draw( &mut out, pad, top + pad, &text, scale, TEXT );
+		}
+		for (x, label) in x_labels.iter().enumerate() {
+			let text = font::fit( label, cell_w - 2 * pad, scale );
+			let left = left_w + x as u32 * cell_w + cell_w.saturating_sub( font::text_width(&text, scale) ) / 2;
+			font::draw( &mut out, left, top + title_h + pad, &text, scale, TEXT );
+		}
+		let cells_top = top + title_h + header_h;
+		for (y, label) in y_labels.iter().enumerate() {
+			let text = font::fit( label, left_w - 2 * pad, scale );
+			let row_top = cells_top + y as u32 * cell_h + cell_h.saturating_sub( font::LINE_HEIGHT * scale ) / 2;
+			font::draw( &mut out, pad, row_top, &text, scale, TEXT );
+		}
+		for y in 0..ny {
+			for x in 0..nx {
+				let (left, top) = (left_w + x as u32 * cell_w, cells_top + y as u32 * cell_h);
+				match cells.get( x + nx * (y + ny * z) ).copied().flatten() {
+					Some(cell) => paste( &mut out, cell, left, top ),
+					None => paste( &mut out, &RgbImage::solid( cell_w, cell_h, MISSING ), left, top ),
+				}
+			}
+		}
+	}
+	out
+}
+
+/// Copy `image` into `out` with its top left corner at `x`, `y`
+fn paste(out:&mut RgbImage, image:&RgbImage, x:u32, y:u32) {
+	let w = image.width.min( out.width.saturating_sub(x) ) as usize;
+	for row in 0..image.height.min( out.height.saturating_sub(y) ) {
+		let src = (row * image.width) as usize * 3;
+		let dst = ((y + row) * out.width + x) as usize * 3;
+		out.pixels[dst..dst + w*3].copy_from_slice( &image.pixels[src..src + w*3] );
+	}
+}
@@ -0,0 +1,301 @@
+//! X/Y/Z plots.
+//!
+//! Every combination of the values of up to three axes becomes one job. [`Plots`] collects the
+//! finished images by job id and puts them together into a labeled grid once every cell has an
+//! image or failed.
+
+pub mod font;
+pub mod grid;
+
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+use crate::generation::{GeneratedImage, GenerationRequest, ModelSet, Sampler};
+use crate::queue::JobId;
+
+/// Most cells a plot may have
+pub const MAX_CELLS:usize = 500;
+
+/// Parameter changed along an axis
+#[derive(Debug, Default, PartialEq, Clone, Copy)]
+pub enum AxisKind {
+	#[default]
+	Seed,
+	Steps,
+	CfgScale,
+	Sampler,
+	Model,
+
+	/// The first value is searched in the prompts and replaced by each value in turn
+	PromptSr,
+}
+
+impl AxisKind {
+	pub const ALL:[AxisKind;6] = [AxisKind::Seed, AxisKind::Steps, AxisKind::CfgScale, AxisKind::Sampler, AxisKind::Model, AxisKind::PromptSr];
+}
+
+impl fmt::Display for AxisKind {
+	fn fmt(&self, f:&mut fmt::Formatter<'_>) -> fmt::Result {
+		f.write_str( match self {
+			Self::Seed => "Seed",
+			Self::Steps => "Steps",
+			Self::CfgScale => "CFG Scale",
+			Self::Sampler => "Sampler",
+			Self::Model => "Checkpoint name",
+			Self::PromptSr => "Prompt S/R",
+		} )
+	}
+}
+
+/// One value of an axis
+#[derive(Debug, Clone, PartialEq)]
+pub enum AxisValue {
+	Seed(u64),
+	Steps(usize),
+	CfgScale(f64),
+	Sampler(Sampler),
+	Model(ModelSet),
+	Prompt { search : String, replace : String },
+}
+
+impl AxisValue {
+	pub fn apply(&self, r:&mut GenerationRequest) {
+		match self {
+			Self::Seed(seed) => r.seeds = vec![*seed],
+			Self::Steps(steps) => r.steps = *steps,
+			Self::CfgScale(scale) => r.cfg_scale = *scale,
+			Self::Sampler(sampler) => r.sampler = *sampler,
+			Self::Model(model) => r.model = Some( model.clone() ),
+			Self::Prompt { search, replace } => {
+				r.prompt = r.prompt.replace( search.as_str(), replace );
+				r.negative_prompt = r.negative_prompt.replace( search.as_str(), replace );
+			}
+		}
+	}
+}
+
+impl fmt::Display for AxisValue {
+	fn fmt(&self, f:&mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			Self::Seed(seed) => write!( f, "{seed}" ),
+			Self::Steps(steps) => write!( f, "{steps}" ),
+			Self::CfgScale(scale) => write!( f, "{scale}" ),
+			Self::Sampler(sampler) => write!( f, "{sampler}" ),
+			Self::Model(model) => f.write_str( &model.name ),
+			Self::Prompt { replace, .. } => f.write_str(replace),
+		}
+	}
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Axis {
+	pub kind : AxisKind,
+	pub values : Vec<AxisValue>,
+}
+
+impl Axis {
+	/// Parse comma separated values. Seeds and steps also take ranges, `1-4` or `10-30 (+10)`.
+	/// Models are looked up by name in `models`
+	pub fn parse(kind:AxisKind, text:&str, models:&[ModelSet]) -> Result<Self, String> {
+		let items = split_values(text);
+		if items.is_empty() {
+			return Err( format!("{kind} : no values") )
+		}
+		let invalid = |v:&str| format!("{kind} : invalid value '{v}'");
+		let mut values = vec![];
+		// Ranges are counted before they are expanded, `1-4000000000` would fill the memory
+		let range = |values:&Vec<AxisValue>, item:&str| {
+			let range = int_range(item).ok_or_else( || invalid(item) )?;
+			match values.len().saturating_add( range.size_hint().0 ) > MAX_CELLS {
+				true => Err( format!("{kind} : more than {MAX_CELLS} values") ),
+				false => Ok(range),
+			}
+		};
+		for item in &items {
+			match kind {
+				AxisKind::Seed => values.extend( range(&values, item)?.map( AxisValue::Seed ) ),
+				AxisKind::Steps => values.extend( range(&values, item)?.map( |s| AxisValue::Steps(s as usize) ) ),
+				AxisKind::CfgScale => values.push( AxisValue::CfgScale( item.parse().map_err( |_| invalid(item) )? ) ),
+				AxisKind::Sampler => values.push( AxisValue::Sampler( item.parse()? ) ),
+				AxisKind::Model => {
+					let model = models.iter().find( |m| m.name.eq_ignore_ascii_case(item) ).ok_or_else( || format!("Unknown model '{item}'") )?;
+					values.push( AxisValue::Model( model.clone() ) );
+				}
+				AxisKind::PromptSr => values.push( AxisValue::Prompt { search : items[0].clone(), replace : item.clone() } ),
+			}
+		}
+		Ok( Self { kind, values } )
+	}
+
+	/// `Kind: value` header of each value
+	pub fn labels(&self) -> Vec<String> {
+		self.values.iter().map( |v| format!("{}: {v}", self.kind) ).collect()
+	}
+
+	/// Values as written back to the metadata, quoted when they hold a comma
+	pub fn values_text(&self) -> String {
+		self.values.iter()
+			.map( |v| v.to_string() )
+			.map( |v| if v.contains(',') { format!("\"{}\"", v.replace('"', "")) } else { v } )
+			.collect::<Vec<_>>()
+			.join(", ")
+	}
+}
+
+/// Comma separated values, `"..."` keeps commas inside a value
+fn split_values(text:&str) -> Vec<String> {
+	let mut out = vec![];
+	let mut current = String::new();
+	let mut quoted = false;
+	for c in text.chars() {
+		match c {
+			'"' => quoted = !quoted,
+			',' if !quoted => out.push( std::mem::take(&mut current) ),
+			c => current.push(c),
+		}
+	}
+	out.push(current);
+	out.into_iter().map( |v| v.trim().to_string() ).filter( |v| !v.is_empty() ).collect()
+}
+
+/// `n`, `a-b` or `a-b (+step)`, inclusive
+fn int_range(text:&str) -> Option<impl Iterator<Item = u64>> {
+	let (range, step) = match text.split_once('(') {
+		Some((range, step)) => (range.trim(), step.trim().trim_end_matches(')').trim().trim_start_matches('+').parse().ok()?),
+		None => (text, 1),
+	};
+	let (from, to) = match range.split_once('-') {
+		Some((from, to)) => (from.trim().parse::<u64>().ok()?, to.trim().parse::<u64>().ok()?),
+		None => { let n = range.parse().ok()?; (n, n) }
+	};
+	( step > 0 && from <= to ).then( || (from..=to).step_by(step) )
+}
+
+/// Request of every cell, X varying fastest, then Y, then Z
+pub fn jobs(template:&GenerationRequest, axes:&[Axis]) -> Result<Vec<GenerationRequest>, String> {
+	for axis in axes {
+		if let Some(AxisValue::Prompt { search, .. }) = axis.values.first() {
+			if !template.prompt.contains(search.as_str()) && !template.negative_prompt.contains(search.as_str()) {
+				return Err( format!("Prompt S/R : '{search}' isn't in the prompt") )
+			}
+		}
+	}
+	let cells = axes.iter().map( |a| a.values.len() ).product::<usize>();
+	if cells > MAX_CELLS {
+		return Err( format!("{cells} cells, at most {MAX_CELLS} are allowed") )
+	}
+
+	let mut jobs = vec![ template.clone() ];
+	// Outer axes first, so the last axis applied, X, ends up varying fastest
+	for axis in axes.iter().rev() {
+		jobs = jobs.into_iter()
+			.flat_map( |job| axis.values.iter().map( move |v| {
+				let mut job = job.clone();
+				v.apply(&mut job);
+				job
+			} ) )
+			.collect();
+	}
+	Ok(jobs)
+}
+
+#[derive(Debug, Clone)]
+enum Cell {
+	Pending(JobId),
+	Done(GeneratedImage),
+	Failed,
+}
+
+/// A plot waiting for its jobs
+#[derive(Debug, Clone)]
+pub struct Plot {
+	template : GenerationRequest,
+	axes : Vec<Axis>,
+	cells : Vec<Cell>,
+}
+
+impl Plot {
+	/// `jobs` are the ids of the requests of [`jobs`], in the same order
+	pub fn new(template:GenerationRequest, axes:Vec<Axis>, jobs:&[JobId]) -> Self {
+		Self { template, axes, cells : jobs.iter().map( |&id| Cell::Pending(id) ).collect() }
+	}
+
+	fn is_complete(&self) -> bool {
+		!self.cells.iter().any( |c| matches!( c, Cell::Pending(_) ) )
+	}
+
+	/// Grid image and the request it is saved with. `None` when every job failed
+	fn grid(&self) -> Option<(GeneratedImage, GenerationRequest)> {
+		let images = self.cells.iter()
+			.map( |c| match c { Cell::Done(image) => Some(&image.image), _ => None } )
+			.collect::<Vec<_>>();
+		if images.iter().all( Option::is_none ) {
+			return None
+		}
+		let labels = |i:usize| self.axes.get(i).map( Axis::labels ).unwrap_or_default();
+		let image = grid::compose( &images, &labels(0), &labels(1), &labels(2) );
+
+		let info = self.axes.iter().zip( ["X", "Y", "Z"] )
+			.flat_map( |(axis, name)| [
+				(format!("{name} Type"), axis.kind.to_string()),
+				(format!("{name} Values"), axis.values_text()),
+			] )
+			.collect();
+		let seed = self.template.seed();
+		Some( (GeneratedImage { image, seed, info }, self.template.clone()) )
+	}
+}
+
+/// Plots whose jobs are in the queue. Provided as a context by `app`, fed by the queue listener
+#[derive(Clone, Default)]
+pub struct Plots {
+	plots : Arc<Mutex<Vec<Plot>>>,
+}
+
+impl Plots {
+	pub fn add(&self, plot:Plot) {
+		self.plots.lock().unwrap().push(plot);
+	}
+
+	/// Record the first image of job `id`, `None` when it failed or was cancelled.
+	/// Returns the grid, and the request to save it with, of the plot this completes
+	pub fn finished(&self, id:JobId, image:Option<&GeneratedImage>) -> Option<(GeneratedImage, GenerationRequest)> {
+		let mut plots = self.plots.lock().unwrap();
+		let index = plots.iter().position( |p| p.cells.iter().any( |c| matches!( c, Cell::Pending(j) if *j == id ) ) )?;
+		let plot = &mut plots[index];
+		for cell in &mut plot.cells {
+			if matches!( cell, Cell::Pending(j) if *j == id ) {
+				*cell = image.map_or( Cell::Failed, |i| Cell::Done( i.clone() ) );
+			}
+		}
+		if !plot.is_complete() {
+			return None
+		}
+		plots.remove(index).grid()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn seeds(text:&str) -> Result<Vec<u64>, String> {
+		let axis = Axis::parse( AxisKind::Seed, text, &[] )?;
+		Ok( axis.values.iter().map( |v| match v { AxisValue::Seed(s) => *s, _ => unreachable!() } ).collect() )
+	}
+
+	#[test]
+	fn ranges() {
+		assert_eq!( seeds("1-4, 10").unwrap(), [1, 2, 3, 4, 10] );
+		assert_eq!( seeds("10-30 (+10)").unwrap(), [10, 20, 30] );
+		assert!( seeds("4-1").is_err() );
+		assert!( seeds("1-4 (+0)").is_err() );
+	}
+
+	#[test]
+	fn long_ranges_fail_before_expanding() {
+		assert!( seeds("1-4000000000").unwrap_err().contains("more than") );
+		assert!( seeds( &format!("1-{MAX_CELLS}, 1") ).is_err() );
+		assert_eq!( seeds( &format!("1-{MAX_CELLS}") ).unwrap().len(), MAX_CELLS );
+	}
+}
@@ -0,0 +1,234 @@
+/// Prompts longer than the CLIP window.
+///
+/// The weighted prompt is split into chunks of [`CHUNK_LEN`] tokens, each encoded on its own and
+/// concatenated, the same way Automatic1111 does it. A chunk that fills up moves its tail to the
+/// next chunk when there is a comma in the last [`COMMA_LOOKBACK`] tokens, so phrases are not cut
+/// in half. `BREAK` ends the current chunk early.
+
+use super::dynamic::{self, Wildcards};
+use super::{emphasis, schedule, tags};
+
+/// Tokens per chunk, without the start and end tokens
+pub const CHUNK_LEN:usize = 75;
+
+/// How far back from the end of a full chunk a comma still moves the boundary
+pub const COMMA_LOOKBACK:usize = 20;
+
+/// Word that ends a chunk
+pub const BREAK:&str = "BREAK";
+
+/// Text to CLIP token ids, without start and end tokens
+pub trait Tokenize {
+	fn tokenize(&self, text:&str) -> anyhow::Result<Vec<i64>>;
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Token {
+	pub id : i64,
+	pub weight : f32,
+
+	/// Word the token starts, empty for the following tokens of a word
+	pub word : String,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Chunk {
+	pub tokens : Vec<Token>,
+}
+
+impl Chunk {
+	pub fn len(&self) -> usize {
+		self.tokens.len()
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.tokens.is_empty()
+	}
+
+	/// Words of the chunk, space separated
+	pub fn text(&self) -> String {
+		self.tokens.iter().filter( |t| !t.word.is_empty() ).map( |t| t.word.as_str() ).collect::<Vec<_>>().join(" ")
+	}
+}
+
+/// Split `prompt` into weighted chunks. Never empty, an empty prompt is one empty chunk
+pub fn split(prompt:&str, tokenizer:&impl Tokenize) -> anyhow::Result<Vec<Chunk>> {
+	let comma = tokenizer.tokenize(",")?.first().copied();
+	let mut chunks = vec![];
+	let mut chunk = Chunk::default();
+	let mut last_comma = None;
+
+	for run in emphasis::parse(prompt) {
+		for word in run.text.split_whitespace() {
+			if word == BREAK {
+				chunks.push( std::mem::take(&mut chunk) );
+				last_comma = None;
+				continue
+			}
+			for (i, id) in tokenizer.tokenize(word)?.into_iter().enumerate() {
+				let is_comma = Some(id) == comma;
+				if is_comma {
+					last_comma = Some( chunk.len() );
+				} else if let Some(at) = last_comma.filter( |&c| chunk.len() == CHUNK_LEN && chunk.len() - c <= COMMA_LOOKBACK ) {
+					let tail = chunk.tokens.split_off( at + 1 );
+					chunks.push( std::mem::replace( &mut chunk, Chunk { tokens : tail } ) );
+					last_comma = None;
+				}
+				if chunk.len() == CHUNK_LEN {
+					chunks.push( std::mem::take(&mut chunk) );
+					// A comma arriving now starts the next chunk
+					last_comma = is_comma.then_some(0);
+				}
+				let word = if i == 0 { word.to_string() } else { String::new() };
+				chunk.tokens.push( Token { id, weight : run.weight, word } );
+			}
+		}
+	}
+	if !chunk.is_empty() || chunks.is_empty() {
+		chunks.push(chunk);
+	}
+	Ok(chunks)
+}
+
+/// Chunks of the longest prompt `prompt` turns into : every expansion of its choices and
+/// wildcards, or one when there are too many, LoRA tags left out, at every step of a `steps` step
+/// schedule
+pub fn split_longest(prompt:&str, steps:usize, wildcards:&Wildcards, tokenizer:&impl Tokenize) -> anyhow::Result<Vec<Chunk>> {
+	let expanded = dynamic::expand_all( prompt, wildcards )
+		.or_else( |_| dynamic::expand_random( prompt, wildcards, 0 ).map( |p| vec![p] ) )
+		.map_err( anyhow::Error::msg )?;
+	let mut prompts = expanded.iter()
+		.flat_map( |p| schedule::schedule( &tags::extract_loras(p).0, steps ) )
+		.map( |s| s.prompt )
+		.collect::<Vec<_>>();
+	prompts.sort_unstable();
+	prompts.dedup();
+	let mut longest = vec![ Chunk::default() ];
+	for prompt in prompts {
+		let split = split( &prompt, tokenizer )?;
+		if token_count(&split) > token_count(&longest) {
+			longest = split;
+		}
+	}
+	Ok(longest)
+}
+
+/// Everything in one chunk, cut after [`CHUNK_LEN`] tokens. Ignores `BREAK`
+pub fn truncate(chunks:Vec<Chunk>) -> Chunk {
+	Chunk { tokens : chunks.into_iter().flat_map( |c| c.tokens ).take(CHUNK_LEN).collect() }
+}
+
+/// Total tokens of the chunks
+pub fn token_count(chunks:&[Chunk]) -> usize {
+	chunks.iter().map( Chunk::len ).sum()
+}
+
+/// Pad chunk lists with empty chunks so they all encode to the same length
+pub fn pad_to_match<'a>(lists:impl IntoIterator<Item = &'a mut Vec<Chunk>>) {
+	let mut lists = lists.into_iter().collect::<Vec<_>>();
+	let len = lists.iter().map( |l| l.len() ).max().unwrap_or_default();
+	lists.iter_mut().for_each( |l| l.resize( len, Chunk::default() ) );
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/// One token per comma and per run of other characters, numbered by length
+	struct Letters;
+
+	impl Tokenize for Letters {
+		fn tokenize(&self, text:&str) -> anyhow::Result<Vec<i64>> {
+			let mut out = vec![];
+			for (i, part) in text.split(',').enumerate() {
+				if i > 0 {
+					out.push(0);
+				}
+				if !part.is_empty() {
+					out.push( part.len() as i64 );
+				}
+			}
+			Ok(out)
+		}
+	}
+
+	fn lens(chunks:&[Chunk]) -> Vec<usize> {
+		chunks.iter().map( Chunk::len ).collect()
+	}
+
+	fn words(n:usize) -> String {
+		vec!["a"; n].join(" ")
+	}
+
+	#[test]
+	fn full_chunks() {
+		assert_eq!( lens( &split( &words(75), &Letters ).unwrap() ), [75] );
+		assert_eq!( lens( &split( &words(160), &Letters ).unwrap() ), [75, 75, 10] );
+		assert_eq!( split( "", &Letters ).unwrap(), vec![Chunk::default()] );
+	}
+
+	#[test]
+	fn comma_moves_the_boundary() {
+		// The comma is token 71 : the first chunk ends after it, the 3 tokens past it move on
+		let chunks = split( &format!("{} bb, {}", words(70), words(10)), &Letters ).unwrap();
+		assert_eq!( lens(&chunks), [72, 10] );
+		assert_eq!( chunks[0].tokens[71].id, 0 );
+		assert_eq!( chunks[1].text(), words(10) );
+
+		// Further back than the look-back, the chunk is cut where it fills
+		let chunks = split( &format!("{} bb, {}", words(40), words(40)), &Letters ).unwrap();
+		assert_eq!( lens(&chunks), [75, 7] );
+	}
+
+	#[test]
+	fn comma_as_the_chunk_fills() {
+		// Token 76 is a comma, it starts the second chunk
+		let chunks = split( &format!("{} , {}", words(75), words(80)), &Letters ).unwrap();
+		assert_eq!( lens(&chunks), [75, 75, 6] );
+		assert_eq!( chunks[1].tokens[0].id, 0 );
+
+		// A comma just before the end moves nothing but still ends the chunk
+		let chunks = split( &format!("{} , {}", words(74), words(5)), &Letters ).unwrap();
+		assert_eq!( lens(&chunks), [75, 5] );
+	}
+
+	#[test]
+	fn weights_and_words() {
+		let chunks = split( "(red:1.5) ccc,dd", &Letters ).unwrap();
+		let tokens = &chunks[0].tokens;
+		assert_eq!( tokens.iter().map( |t| t.id ).collect::<Vec<_>>(), [3, 3, 0, 2] );
+		assert_eq!( tokens.iter().map( |t| t.weight ).collect::<Vec<_>>(), [1.5, 1., 1., 1.] );
+		assert_eq!( chunks[0].text(), "red ccc,dd" );
+	}
+
+	#[test]
+	fn break_ends_the_chunk() {
+		let chunks = split( "aa b BREAK ccc BREAK d", &Letters ).unwrap();
+		assert_eq!( chunks.iter().map( Chunk::text ).collect::<Vec<_>>(), ["aa b", "ccc", "d"] );
+		// A full chunk before it isn't followed by an empty one
+		assert_eq!( lens( &split( &format!("{} BREAK a", words(75)), &Letters ).unwrap() ), [75, 1] );
+		assert_eq!( truncate( split( "aa BREAK b", &Letters ).unwrap() ).text(), "aa b" );
+	}
+
+	#[test]
+	fn longest_of_schedule_and_expansions() {
+		let wildcards = Wildcards::new( "no wildcards here" );
+		let text = |prompt:&str, steps:usize| split_longest( prompt, steps, &wildcards, &Letters ).unwrap()[0].text();
+		assert_eq!( text( "a [bb:cccc ddd:5]", 20 ), "a cccc ddd" );
+		assert_eq!( text( "a [bb cc dd|e]", 20 ), "a bb cc dd" );
+		assert_eq!( text( "{x|yy zz} <lora:detail:0.5>", 20 ), "yy zz" );
+		// The edit happens after the last step
+		assert_eq!( text( "a [bb:cccc ddd:30]", 20 ), "a bb" );
+		assert!( split_longest( "__missing__", 20, &wildcards, &Letters ).is_err() );
+	}
+
+	#[test]
+	fn padding() {
+		let mut short = split( "a", &Letters ).unwrap();
+		let mut long = split( &words(200), &Letters ).unwrap();
+		pad_to_match( [&mut short, &mut long] );
+		assert_eq!( lens(&short), [1, 0, 0] );
+		assert_eq!( lens(&long), [75, 75, 50] );
+		assert_eq!( token_count(&long), 200 );
+	}
+}
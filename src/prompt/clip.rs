@@ -0,0 +1,115 @@
+/// CLIP BPE tokenizer of the `diffusers` crate, shared by the backend and the prompt UI.
+
+use std::path::Path;
+
+use anyhow::Context;
+use diffusers::pipelines::stable_diffusion::StableDiffusionConfig;
+use diffusers::transformers::clip::Tokenizer;
+
+use crate::generation::SdVersion;
+
+use super::chunks::{Chunk, Tokenize};
+use super::embeddings;
+
+/// Vocabulary file name of the `diffusers` examples
+pub const VOCAB_FILE:&str = "bpe_simple_vocab_16e6.txt";
+
+/// Word that maps to token ids past the vocabulary, like a textual inversion trigger
+#[derive(Debug, Clone, PartialEq)]
+pub struct AddedWord {
+	pub word : String,
+
+	/// Id of the first vector, the others follow
+	pub first : i64,
+	pub count : usize,
+}
+
+/// Special tokens of the tokenizer, read from the encoding of an empty prompt
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TokenLayout {
+	pub start : i64,
+	pub end : i64,
+	pub pad : i64,
+
+	/// Tokens per encoder input, start and end included
+	pub len : usize,
+}
+
+impl TokenLayout {
+	fn of(tokenizer:&Tokenizer) -> anyhow::Result<Self> {
+		let empty = tokenizer.encode("")?;
+		anyhow::ensure!( empty.len() >= 3, "unexpected tokenizer output" );
+		Ok( Self { start : empty[0] as i64, end : empty[1] as i64, pad : empty[2] as i64, len : empty.len() } )
+	}
+}
+
+pub struct ClipTokenizer {
+	inner : Tokenizer,
+	pub layout : TokenLayout,
+	added : Vec<AddedWord>,
+}
+
+impl ClipTokenizer {
+	pub fn load(vocab:&Path, version:SdVersion) -> anyhow::Result<Self> {
+		let sd_config = match version {
+			SdVersion::V1_5 => StableDiffusionConfig::v1_5( None, None, None ),
+			SdVersion::V2_1 => StableDiffusionConfig::v2_1( None, None, None ),
+		};
+		let path = vocab.to_str().with_context( || format!("invalid path {vocab:?}") )?;
+		let inner = Tokenizer::create( path, &sd_config.clip )?;
+		Ok( Self { layout : TokenLayout::of(&inner)?, inner, added : vec![] } )
+	}
+
+	/// Replace the added words. Matched as whole words, ignoring case, before BPE runs
+	pub fn set_added(&mut self, added:Vec<AddedWord>) {
+		self.added = added;
+	}
+
+	/// Added word a token id belongs to
+	pub fn added_word(&self, id:i64) -> Option<&str> {
+		self.added.iter()
+			.find( |a| (a.first .. a.first + a.count as i64).contains(&id) )
+			.map( |a| a.word.as_str() )
+	}
+
+	fn bpe(&self, text:&str) -> anyhow::Result<Vec<i64>> {
+		Ok( self.inner.encode_pad(text, None)?.into_iter()
+			.skip(1)
+			.map( |t| t as i64 )
+			.take_while( |&t| t != self.layout.end )
+			.collect() )
+	}
+
+	/// Encoder input of `chunk` : start, tokens, end, then padding. Weights are 1 outside the chunk
+	pub fn chunk_input(&self, chunk:&Chunk) -> (Vec<i64>, Vec<f32>) {
+		let layout = &self.layout;
+		let mut tokens = vec![layout.start];
+		let mut weights = vec![1f32];
+		for t in chunk.tokens.iter().take( layout.len - 2 ) {
+			tokens.push(t.id);
+			weights.push(t.weight);
+		}
+		tokens.push(layout.end);
+		tokens.resize( layout.len, layout.pad );
+		weights.resize( layout.len, 1. );
+		(tokens, weights)
+	}
+}
+
+impl Tokenize for ClipTokenizer {
+	fn tokenize(&self, text:&str) -> anyhow::Result<Vec<i64>> {
+		let mut out = vec![];
+		let mut rest = text;
+		loop {
+			let next = self.added.iter()
+				.filter_map( |a| embeddings::find_word(rest, &a.word).first().map( |&range| (range, a) ) )
+				.min_by_key( |((start, _), _)| *start );
+			let Some(((start, end), added)) = next else { break };
+			out.extend( self.bpe( &rest[..start] )? );
+			out.extend( added.first .. added.first + added.count as i64 );
+			rest = &rest[end..];
+		}
+		out.extend( self.bpe(rest)? );
+		Ok(out)
+	}
+}
@@ -0,0 +1,252 @@
+/// Dynamic prompts : `{a|b|c}` choices and `__name__` wildcards.
+///
+/// A wildcard stands for one line of `name.txt` in the wildcards directory. Names may contain
+/// `/` for sub directories, empty lines and lines starting with `#` are skipped. Choices nest and
+/// both may appear inside wildcard lines, up to [`MAX_DEPTH`] levels deep.
+///
+/// Prompts are expanded when the jobs are built, so the backend and the saved metadata only ever
+/// see the final text.
+
+use std::fmt;
+use std::path::{Component, Path, PathBuf};
+
+use crate::generation::SplitMix;
+
+/// Nesting limit of wildcards inside wildcards
+pub const MAX_DEPTH:usize = 8;
+
+/// Upper bound on the prompts of a combinatorial run, and on its jobs once they are combined
+/// with the negative prompts and the batch count
+pub const MAX_COMBINATIONS:usize = 1000;
+
+/// How a dynamic prompt turns into prompts
+#[derive(Debug, Default, PartialEq, Clone, Copy)]
+pub enum DynamicMode {
+	/// One pick per job, driven by the job's seed
+	#[default]
+	Random,
+
+	/// One job per combination
+	Combinatorial,
+}
+
+impl DynamicMode {
+	pub const ALL:[DynamicMode;2] = [DynamicMode::Random, DynamicMode::Combinatorial];
+}
+
+impl fmt::Display for DynamicMode {
+	fn fmt(&self, f:&mut fmt::Formatter<'_>) -> fmt::Result {
+		f.write_str( match self {
+			Self::Random => "Random picks",
+			Self::Combinatorial => "All combinations",
+		} )
+	}
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Part {
+	Text(String),
+	Choice(Vec<Vec<Part>>),
+	Wildcard(String),
+}
+
+/// Parse up to one of `stop`, which is not consumed
+fn parse(rest:&mut &str, stop:&[char]) -> Vec<Part> {
+	let mut parts = vec![];
+	let mut text = String::new();
+	while let Some(c) = rest.chars().next() {
+		if stop.contains(&c) {
+			break
+		}
+		if c == '{' {
+			let saved = *rest;
+			*rest = &rest[1..];
+			let mut options = vec![];
+			let closed = loop {
+				options.push( parse(rest, &['|', '}']) );
+				match rest.chars().next() {
+					Some('|') => *rest = &rest[1..],
+					Some(_) => { *rest = &rest[1..]; break true }
+					None => break false,
+				}
+			};
+			if closed {
+				if !text.is_empty() {
+					parts.push( Part::Text( std::mem::take(&mut text) ) );
+				}
+				parts.push( Part::Choice(options) );
+			} else {
+				// Unclosed brace, keep it as text
+				*rest = &saved[1..];
+				text.push('{');
+			}
+			continue
+		}
+		if let Some(name) = rest.strip_prefix("__").and_then( |r| r.split_once("__") ).map( |(name, _)| name ) {
+			if !name.is_empty() && name.chars().all( |c| c.is_alphanumeric() || matches!(c, '_' | '-' | '/' | '.') ) {
+				if !text.is_empty() {
+					parts.push( Part::Text( std::mem::take(&mut text) ) );
+				}
+				parts.push( Part::Wildcard( name.to_string() ) );
+				*rest = &rest[name.len() + 4..];
+				continue
+			}
+		}
+		if c == '\\' && rest[1..].starts_with( ['{', '}', '|'] ) {
+			// Escaped brace, literal for us and for the later parsers
+			text.push( rest[1..].chars().next().unwrap() );
+			*rest = &rest[2..];
+			continue
+		}
+		text.push(c);
+		*rest = &rest[c.len_utf8()..];
+	}
+	if !text.is_empty() {
+		parts.push( Part::Text(text) );
+	}
+	parts
+}
+
+/// Wildcard files of a directory
+#[derive(Debug, Clone, PartialEq)]
+pub struct Wildcards {
+	dir : PathBuf,
+}
+
+/// `./wildcards`
+impl Default for Wildcards {
+	fn default() -> Self {
+		Self::new("wildcards")
+	}
+}
+
+impl Wildcards {
+	pub fn new(dir:impl Into<PathBuf>) -> Self {
+		Self { dir : dir.into() }
+	}
+
+	/// Lines of `name.txt`
+	pub fn lines(&self, name:&str) -> Result<Vec<String>, String> {
+		// Only plain names below the wildcards directory, no `..`, root or drive prefixes
+		if !Path::new(name).components().all( |c| matches!( c, Component::Normal(_) ) ) {
+			return Err( format!("Invalid wildcard '{name}'") )
+		}
+		let path = self.dir.join( format!("{name}.txt") );
+		let text = std::fs::read_to_string(&path).map_err( |e| format!("Wildcard '{name}' : {e}") )?;
+		let lines = text.lines()
+			.map( str::trim )
+			.filter( |l| !l.is_empty() && !l.starts_with('#') )
+			.map( str::to_string )
+			.collect::<Vec<_>>();
+		match lines.is_empty() {
+			true => Err( format!("Wildcard '{name}' is empty") ),
+			false => Ok(lines),
+		}
+	}
+}
+
+/// Whether `prompt` has anything to expand
+pub fn is_dynamic(prompt:&str) -> bool {
+	let parts = parse( &mut &*prompt, &[] );
+	parts.iter().any( |p| !matches!(p, Part::Text(_)) )
+}
+
+/// One random expansion of `prompt`. The same seed always picks the same lines
+pub fn expand_random(prompt:&str, wildcards:&Wildcards, seed:u64) -> Result<String, String> {
+	fn expand(parts:&[Part], wildcards:&Wildcards, rng:&mut SplitMix, depth:usize, out:&mut String) -> Result<(), String> {
+		for part in parts {
+			match part {
+				Part::Text(t) => out.push_str(t),
+				Part::Choice(options) => {
+					let pick = &options[ (rng.next_u64() % options.len() as u64) as usize ];
+					expand( pick, wildcards, rng, depth, out )?;
+				}
+				Part::Wildcard(name) => {
+					if depth >= MAX_DEPTH {
+						return Err( format!("Wildcards nested deeper than {MAX_DEPTH} levels at '{name}'") )
+					}
+					let lines = wildcards.lines(name)?;
+					let line = &lines[ (rng.next_u64() % lines.len() as u64) as usize ];
+					expand( &parse( &mut line.as_str(), &[] ), wildcards, rng, depth + 1, out )?;
+				}
+			}
+		}
+		Ok(())
+	}
+	let mut out = String::new();
+	expand( &parse( &mut &*prompt, &[] ), wildcards, &mut SplitMix::new(seed), 0, &mut out )?;
+	Ok(out)
+}
+
+/// Every expansion of `prompt`, in order. Fails past [`MAX_COMBINATIONS`]
+pub fn expand_all(prompt:&str, wildcards:&Wildcards) -> Result<Vec<String>, String> {
+	fn expand(parts:&[Part], wildcards:&Wildcards, depth:usize) -> Result<Vec<String>, String> {
+		let mut out = vec![String::new()];
+		for part in parts {
+			let options = match part {
+				Part::Text(t) => vec![t.clone()],
+				Part::Choice(options) => {
+					let mut all = vec![];
+					for o in options {
+						all.extend( expand( o, wildcards, depth )? );
+					}
+					all
+				}
+				Part::Wildcard(name) => {
+					if depth >= MAX_DEPTH {
+						return Err( format!("Wildcards nested deeper than {MAX_DEPTH} levels at '{name}'") )
+					}
+					let mut all = vec![];
+					for line in wildcards.lines(name)? {
+						all.extend( expand( &parse( &mut line.as_str(), &[] ), wildcards, depth + 1 )? );
+					}
+					all
+				}
+			};
+			if out.len() * options.len() > MAX_COMBINATIONS {
+				return Err( format!("More than {MAX_COMBINATIONS} prompt combinations") )
+			}
+			out = out.iter().flat_map( |o| options.iter().map( move |p| format!("{o}{p}") ) ).collect();
+		}
+		Ok(out)
+	}
+	expand( &parse( &mut &*prompt, &[] ), wildcards, 0 )
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn wildcard_names_stay_in_the_directory() {
+		let dir = std::env::temp_dir().join( format!("sd-freya-wildcards-{}", std::process::id()) );
+		std::fs::create_dir_all( dir.join("styles") ).unwrap();
+		std::fs::write( dir.join("styles/oil.txt"), "# comment\n\noil painting\n" ).unwrap();
+		let wildcards = Wildcards::new(&dir);
+		let lines = wildcards.lines("styles/oil");
+		let escapes = ["../oil", "styles/../../oil", "/etc/passwd", "./styles/oil"].map( |name| wildcards.lines(name) );
+		std::fs::remove_dir_all(&dir).unwrap();
+
+		assert_eq!( lines.unwrap(), ["oil painting"] );
+		for result in escapes {
+			assert!( result.unwrap_err().starts_with("Invalid wildcard") );
+		}
+	}
+
+	#[test]
+	fn random_expansion_follows_the_seed() {
+		let wildcards = Wildcards::new("missing");
+		let prompt = "a {red|green|blue} {cat|dog}";
+		let first = expand_random( prompt, &wildcards, 42 ).unwrap();
+		assert_eq!( expand_random( prompt, &wildcards, 42 ).unwrap(), first );
+		assert!( expand_all( prompt, &wildcards ).unwrap().contains(&first) );
+	}
+
+	#[test]
+	fn combinations() {
+		let wildcards = Wildcards::new("missing");
+		assert_eq!( expand_all( "{a|b} {c|{d|e}}", &wildcards ).unwrap(), ["a c", "a d", "a e", "b c", "b d", "b e"] );
+		let too_many = "{0|1|2|3|4|5|6|7|8|9}".repeat(4);
+		assert!( expand_all( &too_many, &wildcards ).is_err() );
+	}
+}
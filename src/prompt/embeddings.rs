@@ -0,0 +1,57 @@
+/// Textual inversion embeddings on disk.
+///
+/// Every `.pt`, `.bin` or `.safetensors` file of the embeddings directory is one embedding, its
+/// file stem is the trigger word. Only the file list lives here, the vectors are read by the
+/// backend which knows the text encoder they must fit.
+
+use std::path::{Path, PathBuf};
+
+pub const EXTENSIONS:[&str;3] = ["pt", "bin", "safetensors"];
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct EmbeddingFile {
+	/// Trigger word
+	pub name : String,
+	pub path : PathBuf,
+}
+
+/// Embedding files of `dir` and its sub directories, sorted by name. Missing directories are empty
+pub fn list(dir:&Path) -> Vec<EmbeddingFile> {
+	fn walk(dir:&Path, out:&mut Vec<EmbeddingFile>) {
+		let Ok(entries) = std::fs::read_dir(dir) else { return };
+		for path in entries.flatten().map( |e| e.path() ) {
+			if path.is_dir() {
+				walk( &path, out );
+			} else if path.extension().map_or( false, |e| EXTENSIONS.iter().any( |x| e == *x ) ) {
+				let Some(name) = path.file_stem().map( |s| s.to_string_lossy().into_owned() ) else { continue };
+				out.push( EmbeddingFile { name, path } );
+			}
+		}
+	}
+	let mut out = vec![];
+	walk( dir, &mut out );
+	out.sort_by( |a, b| a.name.cmp(&b.name) );
+	out
+}
+
+/// Byte range of every whole word match of `word` in `text`, ignoring ASCII case
+pub fn find_word(text:&str, word:&str) -> Vec<(usize, usize)> {
+	if word.is_empty() {
+		return vec![]
+	}
+	let is_word = |c:char| c.is_alphanumeric() || c == '_' || c == '-';
+	let lower = text.to_ascii_lowercase();
+	let word = word.to_ascii_lowercase();
+	let mut out = vec![];
+	let mut from = 0;
+	while let Some(i) = lower[from..].find(&word).map( |i| i + from ) {
+		let end = i + word.len();
+		let before = lower[..i].chars().next_back().map_or( true, |c| !is_word(c) );
+		let after = lower[end..].chars().next().map_or( true, |c| !is_word(c) );
+		if before && after {
+			out.push( (i, end) );
+		}
+		from = end;
+	}
+	out
+}
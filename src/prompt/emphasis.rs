@@ -0,0 +1,184 @@
+/// Automatic1111 emphasis syntax.
+///
+/// ```text
+/// a (red:1.3) ((cat)) on a [blurry] mat \(not emphasized\)
+/// -> [("a ", 1.0), ("red", 1.3), (" ", 1.0), ("cat", 1.21), (" on a ", 1.0), ("blurry", 0.909), (" mat (not emphasized)", 1.0)]
+/// ```
+///
+/// `(x)` multiplies by 1.1, `[x]` divides by 1.1 and `(x:w)` multiplies by `w`. Brackets nest and
+/// multiply. Brackets left open are closed at the end of the prompt, stray closing ones are kept as
+/// text. `\(`, `\)`, `\[`, `\]` and `\\` are literal.
+
+const SPECIAL:[char;6] = ['\\', '(', ')', '[', ']', ':'];
+
+/// Multiplier of one level of `()`
+pub const ROUND_WEIGHT:f32 = 1.1;
+
+/// Text with one weight
+#[derive(Debug, Clone, PartialEq)]
+pub struct Weighted {
+	pub text : String,
+	pub weight : f32,
+}
+
+/// Parse `prompt` into runs of equal weight. Never empty, an empty prompt is one empty run
+pub fn parse(prompt:&str) -> Vec<Weighted> {
+	let mut out:Vec<Weighted> = vec![];
+	let mut round:Vec<usize> = vec![];
+	let mut square:Vec<usize> = vec![];
+
+	fn multiply(out:&mut [Weighted], from:usize, by:f32) {
+		out[from..].iter_mut().for_each( |w| w.weight *= by );
+	}
+	fn push(out:&mut Vec<Weighted>, text:&str) {
+		out.push( Weighted { text : text.to_string(), weight : 1. } );
+	}
+
+	let mut rest = prompt;
+	while let Some(c) = rest.chars().next() {
+		match c {
+			'\\' => {
+				// Escaped character, or a lone backslash at the end
+				let mut chars = rest.chars();
+				chars.next();
+				match chars.next() {
+					Some(e) => {
+						push( &mut out, &e.to_string() );
+						rest = &rest[1 + e.len_utf8()..];
+					}
+					None => {
+						push( &mut out, "\\" );
+						rest = "";
+					}
+				}
+				continue
+			}
+			'(' => round.push( out.len() ),
+			'[' => square.push( out.len() ),
+			')' if !round.is_empty() => multiply( &mut out, round.pop().unwrap(), ROUND_WEIGHT ),
+			']' if !square.is_empty() => multiply( &mut out, square.pop().unwrap(), 1. / ROUND_WEIGHT ),
+			':' if !round.is_empty() => {
+				// `:1.3)` closes the innermost round bracket with an explicit weight
+				if let Some((weight, len)) = explicit_weight(rest) {
+					multiply( &mut out, round.pop().unwrap(), weight );
+					rest = &rest[len..];
+					continue
+				}
+				push( &mut out, ":" );
+			}
+			// Stray closing bracket or colon
+			_ if SPECIAL.contains(&c) => push( &mut out, &c.to_string() ),
+			_ => {
+				let end = rest.find(SPECIAL).unwrap_or( rest.len() );
+				push( &mut out, &rest[..end] );
+				rest = &rest[end..];
+				continue
+			}
+		}
+		rest = &rest[c.len_utf8()..];
+	}
+
+	for start in round.into_iter().rev() {
+		multiply( &mut out, start, ROUND_WEIGHT );
+	}
+	for start in square.into_iter().rev() {
+		multiply( &mut out, start, 1. / ROUND_WEIGHT );
+	}
+
+	// Merge neighbours of equal weight
+	let mut merged:Vec<Weighted> = vec![];
+	for w in out {
+		match merged.last_mut() {
+			Some(last) if last.weight == w.weight => last.text.push_str(&w.text),
+			_ => merged.push(w),
+		}
+	}
+	if merged.is_empty() {
+		merged.push( Weighted { text : String::new(), weight : 1. } );
+	}
+	merged
+}
+
+/// `:` `number` `)` with optional spaces. Returns the weight and the length consumed
+fn explicit_weight(s:&str) -> Option<(f32, usize)> {
+	let body = s.strip_prefix(':')?;
+	let close = body.find(')')?;
+	let weight = body[..close].trim();
+	if !weight.chars().all( |c| c.is_ascii_digit() || matches!(c, '.' | '+' | '-') ) {
+		return None
+	}
+	let weight = weight.parse::<f32>().ok()?;
+	Some( (weight, 1 + close + 1) )
+}
+
+/// Prompt text with the emphasis syntax removed
+pub fn strip(prompt:&str) -> String {
+	parse(prompt).into_iter().map( |w| w.text ).collect()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	const TOLERANCE:f32 = 1e-5;
+
+	fn check(prompt:&str, expected:&[(&str, f32)]) {
+		let parsed = parse(prompt);
+		let texts = parsed.iter().map( |w| w.text.as_str() ).collect::<Vec<_>>();
+		assert_eq!( texts, expected.iter().map( |e| e.0 ).collect::<Vec<_>>(), "{prompt}" );
+		for (w, (_, weight)) in parsed.iter().zip(expected) {
+			assert!( (w.weight - weight).abs() < TOLERANCE, "{prompt} : '{}' has weight {}, expected {weight}", w.text, w.weight );
+		}
+	}
+
+	#[test]
+	fn round() {
+		check( "(a)", &[("a", 1.1)] );
+		check( "((a))", &[("a", 1.21)] );
+		check( "x (a) y", &[("x ", 1.), ("a", 1.1), (" y", 1.)] );
+	}
+
+	#[test]
+	fn explicit_weight() {
+		check( "(a:1.3)", &[("a", 1.3)] );
+		check( "(a: 0.5 )", &[("a", 0.5)] );
+		// Not a number, the colon is text
+		check( "(a:b)", &[("a:b", 1.1)] );
+	}
+
+	#[test]
+	fn square() {
+		check( "[a]", &[("a", 1. / 1.1)] );
+		check( "[[a]]", &[("a", 1. / 1.21)] );
+	}
+
+	#[test]
+	fn escaped() {
+		check( r"\(a\)", &[("(a)", 1.)] );
+		check( r"\[a\] \\ b\", &[(r"[a] \ b\", 1.)] );
+	}
+
+	#[test]
+	fn unbalanced() {
+		// Left open : closed at the end
+		check( "(a", &[("a", 1.1)] );
+		check( "[a", &[("a", 1. / 1.1)] );
+		check( "(a:1.3", &[("a:1.3", 1.1)] );
+		// Stray closing brackets are text
+		check( "a)", &[("a)", 1.)] );
+		check( "a] b", &[("a] b", 1.)] );
+	}
+
+	#[test]
+	fn nested_weights_multiply() {
+		check( "((a:1.5) b)", &[("a", 1.65), (" b", 1.1)] );
+		check( "[(a:2)]", &[("a", 2. / 1.1)] );
+		check( "(a [b] (c:0.5))", &[("a ", 1.1), ("b", 1.), (" ", 1.1), ("c", 0.55)] );
+	}
+
+	#[test]
+	fn empty() {
+		check( "", &[("", 1.)] );
+		assert_eq!( strip("a (red:1.3) [cat]"), "a red cat" );
+	}
+}
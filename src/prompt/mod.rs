@@ -0,0 +1,9 @@
+//! Prompt syntax, turned into plain text and per-token data before it reaches the tokenizer.
+
+pub mod chunks;
+pub mod clip;
+pub mod dynamic;
+pub mod embeddings;
+pub mod emphasis;
+pub mod schedule;
+pub mod tags;
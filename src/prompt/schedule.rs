@@ -0,0 +1,275 @@
+/// Prompt editing and alternation, Automatic1111 syntax.
+///
+/// ```text
+/// a [dog:cat:10] in a field     dog for steps 0 to 10, then cat
+/// a [dog:cat:0.25] in a field   switch after a quarter of the steps
+/// a [cat:5] / a [dog::5]        cat added / dog removed after step 5
+/// a [dog|cat] in a field        alternates between dog and cat
+/// ```
+///
+/// A `when` below 1 is a fraction of the steps, anything else a step number. Square brackets
+/// that are neither stay as they are for the emphasis parser, like `(round ones)`. The result is
+/// a list of prompts, each active up to and including its `end` step. Steps are the 0 based
+/// denoising step indices of the whole run, skipped img2img steps included.
+
+#[derive(Debug, Clone, PartialEq)]
+enum Node {
+	Text(String),
+
+	/// Brackets that aren't ours, with their content
+	Group { open : char, inner : Vec<Node>, close : Option<char> },
+
+	Edit { from : Vec<Node>, to : Vec<Node>, when : f64 },
+
+	Alternate(Vec<Vec<Node>>),
+}
+
+/// Prompt of a range of steps
+#[derive(Debug, Clone, PartialEq)]
+pub struct Scheduled {
+	/// Last step the prompt is used for
+	pub end : usize,
+	pub prompt : String,
+}
+
+struct Parser<'a> {
+	rest : &'a str,
+}
+
+impl<'a> Parser<'a> {
+	fn peek(&self) -> Option<char> {
+		self.rest.chars().next()
+	}
+
+	fn bump(&mut self) -> Option<char> {
+		let c = self.peek()?;
+		self.rest = &self.rest[c.len_utf8()..];
+		Some(c)
+	}
+
+	/// Nodes up to one of `stop` or the end. `stop` is not consumed
+	fn sequence(&mut self, stop:&[char]) -> Vec<Node> {
+		let mut nodes = vec![];
+		let mut text = String::new();
+		while let Some(c) = self.peek() {
+			if stop.contains(&c) {
+				break
+			}
+			let node = match c {
+				'\\' => {
+					// Keep the escape for the emphasis parser
+					self.bump();
+					text.push('\\');
+					if let Some(e) = self.bump() {
+						text.push(e);
+					}
+					continue
+				}
+				'(' => {
+					self.bump();
+					let inner = self.sequence(&[')']);
+					Node::Group { open : '(', inner, close : self.bump() }
+				}
+				'[' => {
+					let saved = self.rest;
+					self.bump();
+					match self.square() {
+						Some(node) => node,
+						None => {
+							self.rest = saved;
+							self.bump();
+							text.push('[');
+							continue
+						}
+					}
+				}
+				_ => {
+					self.bump();
+					text.push(c);
+					continue
+				}
+			};
+			if !text.is_empty() {
+				nodes.push( Node::Text( std::mem::take(&mut text) ) );
+			}
+			nodes.push(node);
+		}
+		if !text.is_empty() {
+			nodes.push( Node::Text(text) );
+		}
+		nodes
+	}
+
+	/// Content of `[...]` after the opening bracket
+	fn square(&mut self) -> Option<Node> {
+		let first = self.sequence(&[':', '|', ']']);
+		match self.bump()? {
+			']' => Some( Node::Group { open : '[', inner : first, close : Some(']') } ),
+			'|' => {
+				let mut options = vec![first];
+				loop {
+					options.push( self.sequence(&['|', ']']) );
+					if self.bump()? == ']' {
+						return Some( Node::Alternate(options) )
+					}
+				}
+			}
+			_ => {
+				let second = self.sequence(&[':', ']']);
+				match self.bump()? {
+					']' => Some( Node::Edit { from : vec![], to : first, when : number(&second)? } ),
+					_ => {
+						let third = self.sequence(&[']']);
+						self.bump()?;
+						Some( Node::Edit { from : first, to : second, when : number(&third)? } )
+					}
+				}
+			}
+		}
+	}
+}
+
+fn number(nodes:&[Node]) -> Option<f64> {
+	match nodes {
+		[Node::Text(t)] => t.trim().parse().ok().filter( |w:&f64| w.is_finite() && *w >= 0. ),
+		_ => None,
+	}
+}
+
+/// Step a `when` switches at
+fn when_step(when:f64, steps:usize) -> usize {
+	if when < 1. { (when * steps as f64) as usize } else { when as usize }
+}
+
+/// Collect the switch steps, and whether anything alternates
+fn boundaries(nodes:&[Node], steps:usize, out:&mut Vec<usize>, alternates:&mut bool) {
+	for node in nodes {
+		match node {
+			Node::Text(_) => {}
+			Node::Group { inner, .. } => boundaries( inner, steps, out, alternates ),
+			Node::Edit { from, to, when } => {
+				out.push( when_step(*when, steps) );
+				boundaries( from, steps, out, alternates );
+				boundaries( to, steps, out, alternates );
+			}
+			Node::Alternate(options) => {
+				*alternates = true;
+				options.iter().for_each( |o| boundaries( o, steps, out, alternates ) );
+			}
+		}
+	}
+}
+
+/// Prompt text of `step`, in the numbering of the schedule ends
+fn render(nodes:&[Node], step:usize, steps:usize, out:&mut String) {
+	for node in nodes {
+		match node {
+			Node::Text(t) => out.push_str(t),
+			Node::Group { open, inner, close } => {
+				out.push(*open);
+				render( inner, step, steps, out );
+				out.extend(*close);
+			}
+			Node::Edit { from, to, when } => {
+				let active = if step <= when_step(*when, steps) { from } else { to };
+				render( active, step, steps, out );
+			}
+			Node::Alternate(options) => render( &options[(step + options.len() - 1) % options.len()], step, steps, out ),
+		}
+	}
+}
+
+/// Prompts of a run of `steps` steps. Never empty, the last entry ends at `steps`
+pub fn schedule(prompt:&str, steps:usize) -> Vec<Scheduled> {
+	let nodes = Parser { rest : prompt }.sequence(&[]);
+
+	let mut ends = vec![];
+	let mut alternates = false;
+	boundaries( &nodes, steps, &mut ends, &mut alternates );
+	if alternates {
+		ends.extend( 1..=steps );
+	}
+	ends.push(steps);
+	ends.retain( |&s| s > 0 && s <= steps );
+	ends.sort_unstable();
+	ends.dedup();
+	if ends.is_empty() {
+		ends.push(steps);
+	}
+
+	let mut out:Vec<Scheduled> = vec![];
+	for end in ends {
+		let mut prompt = String::new();
+		render( &nodes, end, steps, &mut prompt );
+		match out.last_mut() {
+			// Same text as the previous range, extend it
+			Some(last) if last.prompt == prompt => last.end = end,
+			_ => out.push( Scheduled { end, prompt } ),
+		}
+	}
+	out
+}
+
+/// Index of the entry active at denoising step `step`
+pub fn index_at(schedule:&[Scheduled], step:usize) -> usize {
+	schedule.iter().position( |s| step <= s.end ).unwrap_or( schedule.len().saturating_sub(1) )
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn prompts(prompt:&str, steps:usize) -> Vec<(usize, String)> {
+		schedule(prompt, steps).into_iter().map( |s| (s.end, s.prompt) ).collect()
+	}
+
+	fn s(end:usize, prompt:&str) -> (usize, String) {
+		(end, prompt.to_string())
+	}
+
+	#[test]
+	fn edit_at_step() {
+		assert_eq!( prompts( "a [dog:cat:10] here", 20 ), vec![s(10, "a dog here"), s(20, "a cat here")] );
+		assert_eq!( prompts( "a [cat:5]", 20 ), vec![s(5, "a "), s(20, "a cat")] );
+		assert_eq!( prompts( "a [dog::5]", 20 ), vec![s(5, "a dog"), s(20, "a ")] );
+		// Past the last step : never switches
+		assert_eq!( prompts( "[dog:cat:30]", 20 ), vec![s(20, "dog")] );
+	}
+
+	#[test]
+	fn edit_at_fraction() {
+		assert_eq!( prompts( "[dog:cat:0.5]", 20 ), vec![s(10, "dog"), s(20, "cat")] );
+		assert_eq!( prompts( "[dog:cat:0.25]", 10 ), vec![s(2, "dog"), s(10, "cat")] );
+	}
+
+	#[test]
+	fn alternate() {
+		assert_eq!( prompts( "[a|b]", 4 ), vec![s(1, "a"), s(2, "b"), s(3, "a"), s(4, "b")] );
+		assert_eq!( prompts( "[a|b|c] x", 3 ), vec![s(1, "a x"), s(2, "b x"), s(3, "c x")] );
+	}
+
+	#[test]
+	fn nested() {
+		assert_eq!( prompts( "[[a|b]:c:2]", 4 ), vec![s(1, "a"), s(2, "b"), s(4, "c")] );
+		assert_eq!( prompts( "[a:[b:c:6]:3]", 10 ), vec![s(3, "a"), s(6, "b"), s(10, "c")] );
+		// Emphasis inside an edit is left for the emphasis parser
+		assert_eq!( prompts( "[(dog:1.2):[cat]:5]", 10 ), vec![s(5, "(dog:1.2)"), s(10, "[cat]")] );
+	}
+
+	#[test]
+	fn not_ours() {
+		assert_eq!( prompts( "[blurry] (cat) [a:b]", 10 ), vec![s(10, "[blurry] (cat) [a:b]")] );
+		assert_eq!( prompts( r"\[a:b:1\] [x", 10 ), vec![s(10, r"\[a:b:1\] [x")] );
+	}
+
+	#[test]
+	fn index() {
+		let schedule = schedule( "[dog:cat:0.5]", 20 );
+		assert_eq!( index_at( &schedule, 0 ), 0 );
+		assert_eq!( index_at( &schedule, 10 ), 0 );
+		assert_eq!( index_at( &schedule, 11 ), 1 );
+		assert_eq!( index_at( &schedule, 20 ), 1 );
+		// Past the end stays on the last prompt
+		assert_eq!( index_at( &schedule, 99 ), 1 );
+	}
+}
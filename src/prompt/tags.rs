@@ -0,0 +1,68 @@
+/// Extra network tags, `<lora:name>` or `<lora:name:weight>`.
+///
+/// Tags are taken out of the prompt before anything else reads it, so they never reach the
+/// tokenizer. The prompt keeps them in the saved metadata.
+
+/// Strength of a tag without a weight
+pub const DEFAULT_WEIGHT:f64 = 1.;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LoraTag {
+	pub name : String,
+	pub weight : f64,
+}
+
+/// Prompt without its `<lora:...>` tags, and the tags in order. Malformed tags stay in the text
+pub fn extract_loras(prompt:&str) -> (String, Vec<LoraTag>) {
+	let mut text = String::with_capacity( prompt.len() );
+	let mut tags = vec![];
+	let mut rest = prompt;
+	while let Some(start) = rest.find("<lora:") {
+		let Some(len) = rest[start..].find('>') else { break };
+		let body = &rest[start + "<lora:".len() .. start + len];
+		let tag = match body.rsplit_once(':') {
+			Some((name, weight)) => weight.trim().parse().ok().map( |weight| LoraTag { name : name.trim().to_string(), weight } ),
+			None => Some( LoraTag { name : body.trim().to_string(), weight : DEFAULT_WEIGHT } ),
+		};
+		match tag.filter( |t| !t.name.is_empty() ) {
+			Some(tag) => {
+				text.push_str( &rest[..start] );
+				tags.push(tag);
+			}
+			None => text.push_str( &rest[..=start + len] ),
+		}
+		rest = &rest[start + len + 1..];
+	}
+	text.push_str(rest);
+	(text, tags)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn tag(name:&str, weight:f64) -> LoraTag {
+		LoraTag { name : name.into(), weight }
+	}
+
+	#[test]
+	fn tags_and_weights() {
+		assert_eq!( extract_loras("a cat"), ("a cat".into(), vec![]) );
+		assert_eq!( extract_loras("a cat <lora:detail>"), ("a cat ".into(), vec![tag("detail", DEFAULT_WEIGHT)]) );
+		assert_eq!(
+			extract_loras("<lora:detail:0.6>a <lora:style v2 : -0.25 > cat"),
+			("a  cat".into(), vec![tag("detail", 0.6), tag("style v2", -0.25)]),
+		);
+		// Only the last colon separates the weight
+		assert_eq!( extract_loras("<lora:sub:dir:2>"), ("".into(), vec![tag("sub:dir", 2.)]) );
+	}
+
+	#[test]
+	fn malformed_tags_stay() {
+		assert_eq!( extract_loras("a <lora:detail:strong> cat"), ("a <lora:detail:strong> cat".into(), vec![]) );
+		assert_eq!( extract_loras("a <lora:> <lora::1> cat"), ("a <lora:> <lora::1> cat".into(), vec![]) );
+		assert_eq!( extract_loras("a <lora:detail cat"), ("a <lora:detail cat".into(), vec![]) );
+		assert_eq!( extract_loras("<lora:x:y> <lora:ok>"), ("<lora:x:y> ".into(), vec![tag("ok", 1.)]) );
+		assert_eq!( extract_loras("<hypernet:x:1>"), ("<hypernet:x:1>".into(), vec![]) );
+	}
+}
@@ -0,0 +1,454 @@
+/// Background job queue.
+///
+/// One worker thread owns the [`GenerationBackend`] and runs queued jobs in order.
+/// Every state change is reported through the listener given to [`JobQueue::spawn`],
+/// the UI uses it to schedule a repaint. The listener is never called with the queue locked, so
+/// it may use the queue. Events it causes are delivered once it returns.
+
+use std::collections::VecDeque;
+use std::ops::ControlFlow;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+
+use crate::generation::{Cancelled, GenerationBackend, GenerationRequest, GeneratedImage, Progress};
+
+pub type JobId = u64;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum JobState {
+	Queued,
+	Running(Progress),
+	Done,
+	Failed(String),
+	Cancelled,
+}
+
+impl JobState {
+	pub fn is_finished(&self) -> bool {
+		matches!( self, Self::Done | Self::Failed(_) | Self::Cancelled )
+	}
+}
+
+#[derive(Debug, Clone)]
+pub struct Job {
+	pub id : JobId,
+	pub request : GenerationRequest,
+	pub state : JobState,
+	cancel : Arc<AtomicBool>,
+}
+
+impl PartialEq for Job {
+	fn eq(&self, other:&Self) -> bool {
+		self.id == other.id && self.state == other.state && self.request == other.request
+	}
+}
+
+/// Reported to the listener from the worker thread
+#[derive(Debug)]
+pub enum QueueEvent {
+	/// Some job changed state or made progress
+	Changed,
+
+	/// A job finished successfully
+	Finished { id : JobId, request : GenerationRequest, images : Vec<GeneratedImage> },
+
+	/// A job ended without images : it failed, or was cancelled or removed before finishing
+	Dropped { id : JobId },
+}
+
+#[derive(Default)]
+struct State {
+	jobs : Vec<Job>,
+	next_id : JobId,
+	shutdown : bool,
+}
+
+struct Shared {
+	state : Mutex<State>,
+	wakeup : Condvar,
+	listener : Mutex<Box<dyn FnMut(QueueEvent) + Send>>,
+
+	/// Events waiting for the listener
+	events : Mutex<VecDeque<QueueEvent>>,
+}
+
+impl Shared {
+	/// Send `event` to the listener. Whoever holds the listener delivers every waiting event, so a
+	/// call from inside the listener, or while another thread is in it, only queues the event
+	fn notify(&self, event:QueueEvent) {
+		self.events.lock().unwrap().push_back(event);
+		while let Ok(mut listener) = self.listener.try_lock() {
+			loop {
+				let next = self.events.lock().unwrap().pop_front();
+				let Some(event) = next else { break };
+				listener(event);
+			}
+			drop(listener);
+			// An event queued after the last pop but before the unlock would be left behind
+			if self.events.lock().unwrap().is_empty() {
+				return
+			}
+		}
+	}
+}
+
+/// Handle to the queue. Cheap to clone, the worker stops when the last handle is dropped.
+#[derive(Clone)]
+pub struct JobQueue {
+	shared : Arc<Shared>,
+	_owner : Arc<Owner>,
+}
+
+/// Stops the worker when dropped
+struct Owner(Arc<Shared>);
+
+impl Drop for Owner {
+	fn drop(&mut self) {
+		self.0.state.lock().unwrap().shutdown = true;
+		self.0.wakeup.notify_all();
+	}
+}
+
+impl JobQueue {
+	pub fn spawn(backend:Box<dyn GenerationBackend>, listener:impl FnMut(QueueEvent) + Send + 'static) -> Self {
+		let shared = Arc::new( Shared {
+			state : Mutex::new( State::default() ),
+			wakeup : Condvar::new(),
+			listener : Mutex::new( Box::new(listener) ),
+			events : Default::default(),
+		} );
+		let worker = shared.clone();
+		std::thread::Builder::new()
+			.name( "generation".to_string() )
+			.spawn( move || run(worker, backend) )
+			.expect("failed to spawn generation thread");
+		Self { _owner : Arc::new( Owner(shared.clone()) ), shared }
+	}
+
+	/// Add a job to the end of the queue
+	pub fn push(&self, request:GenerationRequest) -> JobId {
+		self.push_all( vec![request], |_| {} )[0]
+	}
+
+	/// Add jobs to the end of the queue. `register` gets their ids before any of them can start,
+	/// so events of these jobs always come after it. It must not use the queue
+	pub fn push_all(&self, requests:Vec<GenerationRequest>, register:impl FnOnce(&[JobId])) -> Vec<JobId> {
+		let ids = {
+			let mut state = self.shared.state.lock().unwrap();
+			let mut ids = vec![];
+			for request in requests {
+				let id = state.next_id;
+				state.next_id += 1;
+				state.jobs.push( Job { id, request, state : JobState::Queued, cancel : Default::default() } );
+				ids.push(id);
+			}
+			register(&ids);
+			ids
+		};
+		self.shared.wakeup.notify_all();
+		self.shared.notify( QueueEvent::Changed );
+		ids
+	}
+
+	/// Snapshot of all jobs in queue order
+	pub fn jobs(&self) -> Vec<Job> {
+		self.shared.state.lock().unwrap().jobs.clone()
+	}
+
+	/// Cancel a job. A queued job is cancelled right away, a running one at its next step
+	pub fn cancel(&self, id:JobId) {
+		let mut dropped = false;
+		self.update( |state| {
+			if let Some(job) = state.jobs.iter_mut().find( |j| j.id == id ) {
+				job.cancel.store( true, Ordering::Relaxed );
+				if job.state == JobState::Queued {
+					job.state = JobState::Cancelled;
+					dropped = true;
+				}
+			}
+		} );
+		if dropped {
+			self.shared.notify( QueueEvent::Dropped { id } );
+		}
+	}
+
+	/// Remove a job that isn't running. A running job is cancelled instead
+	pub fn remove(&self, id:JobId) {
+		let mut dropped = false;
+		self.update( |state| {
+			match state.jobs.iter().position( |j| j.id == id ) {
+				Some(i) if matches!( state.jobs[i].state, JobState::Running(_) ) => state.jobs[i].cancel.store( true, Ordering::Relaxed ),
+				Some(i) => dropped = !state.jobs.remove(i).state.is_finished(),
+				None => {}
+			}
+		} );
+		if dropped {
+			self.shared.notify( QueueEvent::Dropped { id } );
+		}
+	}
+
+	/// Move a queued job `offset` places among the other queued jobs
+	pub fn move_job(&self, id:JobId, offset:isize) {
+		self.update( |state| {
+			let queued = state.jobs.iter().enumerate()
+				.filter( |(_, j)| j.state == JobState::Queued )
+				.map( |(i, _)| i )
+				.collect::<Vec<_>>();
+			let Some(from) = queued.iter().position( |&i| state.jobs[i].id == id ) else { return };
+			let to = (from as isize + offset).clamp( 0, queued.len() as isize - 1 ) as usize;
+			if from != to {
+				let job = state.jobs.remove( queued[from] );
+				state.jobs.insert( queued[to], job );
+			}
+		} );
+	}
+
+	/// Drop every finished job from the list
+	pub fn clear_finished(&self) {
+		self.update( |state| state.jobs.retain( |j| !j.state.is_finished() ) );
+	}
+
+	fn update(&self, f:impl FnOnce(&mut State)) {
+		f( &mut self.shared.state.lock().unwrap() );
+		self.shared.notify( QueueEvent::Changed );
+	}
+}
+
+fn set_state(shared:&Shared, id:JobId, new_state:JobState) {
+	if let Some(job) = shared.state.lock().unwrap().jobs.iter_mut().find( |j| j.id == id ) {
+		job.state = new_state;
+	}
+	shared.notify( QueueEvent::Changed );
+}
+
+fn run(shared:Arc<Shared>, mut backend:Box<dyn GenerationBackend>) {
+	loop {
+		let (id, request, cancel) = {
+			let mut state = shared.state.lock().unwrap();
+			loop {
+				if state.shutdown {
+					return
+				}
+				if let Some(job) = state.jobs.iter_mut().find( |j| j.state == JobState::Queued ) {
+					job.state = JobState::Running( Progress::Loading );
+					break (job.id, job.request.clone(), job.cancel.clone())
+				}
+				state = shared.wakeup.wait(state).unwrap();
+			}
+		};
+		shared.notify( QueueEvent::Changed );
+
+		let result = backend.generate( &request, &mut |p| {
+			if cancel.load( Ordering::Relaxed ) {
+				return ControlFlow::Break(())
+			}
+			set_state( &shared, id, JobState::Running(p) );
+			ControlFlow::Continue(())
+		} );
+
+		match result {
+			Ok(images) => {
+				set_state( &shared, id, JobState::Done );
+				shared.notify( QueueEvent::Finished { id, request, images } );
+			}
+			Err(e) => {
+				let state = match e.is::<Cancelled>() {
+					true => JobState::Cancelled,
+					false => JobState::Failed( format!("{e:#}") ),
+				};
+				set_state( &shared, id, state );
+				shared.notify( QueueEvent::Dropped { id } );
+			}
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::sync::mpsc::{self, Receiver, Sender};
+	use std::time::{Duration, Instant};
+
+	use crate::generation::{report, RgbImage};
+
+	use super::*;
+
+	const TIMEOUT:Duration = Duration::from_secs(5);
+
+	/// Runs one step per message of the test, then returns a 1x1 image
+	struct Gated {
+		steps : usize,
+		gate : Receiver<()>,
+	}
+
+	impl GenerationBackend for Gated {
+		fn name(&self) -> &str {
+			"gated"
+		}
+
+		fn generate(&mut self, _:&GenerationRequest, progress:&mut dyn FnMut(Progress) -> ControlFlow<()>) -> anyhow::Result<Vec<GeneratedImage>> {
+			for step in 1..=self.steps {
+				self.gate.recv()?;
+				report( progress, Progress::Step { step, total : self.steps } )?;
+			}
+			Ok( vec![ GeneratedImage { image : RgbImage::new(1, 1), seed : 0, info : vec![] } ] )
+		}
+	}
+
+	#[derive(Debug, PartialEq)]
+	enum Seen {
+		Changed,
+		Finished(JobId),
+		Dropped(JobId),
+	}
+
+	/// Queue on a [`Gated`] backend, the sender opening its gate and the events it reports
+	fn queue(steps:usize) -> (JobQueue, Sender<()>, Receiver<Seen>) {
+		let (gate_tx, gate) = mpsc::channel();
+		let (events_tx, events) = mpsc::channel();
+		let queue = JobQueue::spawn( Box::new( Gated { steps, gate } ), move |event| {
+			let _ = events_tx.send( match event {
+				QueueEvent::Changed => Seen::Changed,
+				QueueEvent::Finished { id, .. } => Seen::Finished(id),
+				QueueEvent::Dropped { id } => Seen::Dropped(id),
+			} );
+		} );
+		(queue, gate_tx, events)
+	}
+
+	/// Events up to and including the first one that isn't [`Seen::Changed`]
+	fn until_end(events:&Receiver<Seen>) -> Vec<Seen> {
+		let mut out = vec![];
+		while out.last().map_or( true, |e| *e == Seen::Changed ) {
+			out.push( events.recv_timeout(TIMEOUT).expect("no event") );
+		}
+		out
+	}
+
+	fn wait_for(queue:&JobQueue, id:JobId, done:impl Fn(&JobState) -> bool) {
+		let start = Instant::now();
+		while !queue.jobs().iter().any( |j| j.id == id && done(&j.state) ) {
+			assert!( start.elapsed() < TIMEOUT, "job {id} stuck in {:?}", queue.jobs() );
+			std::thread::sleep( Duration::from_millis(1) );
+		}
+	}
+
+	fn ids(queue:&JobQueue) -> Vec<JobId> {
+		queue.jobs().iter().map( |j| j.id ).collect()
+	}
+
+	#[test]
+	fn event_order() {
+		let (queue, gate, events) = queue(2);
+		let id = queue.push( GenerationRequest::default() );
+		gate.send(()).unwrap();
+		gate.send(()).unwrap();
+		// Pushed, started, 2 steps, done
+		assert_eq!( until_end(&events), [Seen::Changed, Seen::Changed, Seen::Changed, Seen::Changed, Seen::Changed, Seen::Finished(id)] );
+		assert_eq!( queue.jobs()[0].state, JobState::Done );
+	}
+
+	#[test]
+	fn jobs_run_in_order() {
+		let (queue, gate, events) = queue(1);
+		let pushed = queue.push_all( vec![GenerationRequest::default(); 3], |_| {} );
+		let mut finished = vec![];
+		for _ in &pushed {
+			gate.send(()).unwrap();
+			match until_end(&events).pop() {
+				Some(Seen::Finished(id)) => finished.push(id),
+				other => panic!("{other:?}"),
+			}
+		}
+		assert_eq!( finished, pushed );
+	}
+
+	#[test]
+	fn cancel_queued_and_running() {
+		let (queue, gate, events) = queue(2);
+		let running = queue.push( GenerationRequest::default() );
+		let queued = queue.push( GenerationRequest::default() );
+		wait_for( &queue, running, |s| matches!( s, JobState::Running(_) ) );
+
+		// A queued job is cancelled right away
+		queue.cancel(queued);
+		assert_eq!( queue.jobs()[1].state, JobState::Cancelled );
+		// A running one at its next step
+		queue.cancel(running);
+		assert!( matches!( queue.jobs()[0].state, JobState::Running(_) ) );
+		gate.send(()).unwrap();
+		wait_for( &queue, running, |s| *s == JobState::Cancelled );
+
+		let dropped = std::iter::from_fn( || events.recv_timeout(TIMEOUT).ok() )
+			.filter( |e| *e != Seen::Changed )
+			.take(2)
+			.collect::<Vec<_>>();
+		assert_eq!( dropped, [Seen::Dropped(queued), Seen::Dropped(running)] );
+	}
+
+	#[test]
+	fn remove() {
+		let (queue, gate, events) = queue(1);
+		let done = queue.push( GenerationRequest::default() );
+		gate.send(()).unwrap();
+		wait_for( &queue, done, |s| *s == JobState::Done );
+		let running = queue.push( GenerationRequest::default() );
+		let queued = queue.push( GenerationRequest::default() );
+		wait_for( &queue, running, |s| matches!( s, JobState::Running(_) ) );
+
+		queue.remove(done);
+		queue.remove(queued);
+		queue.remove(running);
+		// The running job stays until it notices
+		assert_eq!( ids(&queue), [running] );
+		gate.send(()).unwrap();
+		wait_for( &queue, running, |s| *s == JobState::Cancelled );
+		queue.remove(running);
+		assert!( queue.jobs().is_empty() );
+
+		// Finished jobs were already reported, removing them reports nothing
+		let reported = std::iter::from_fn( || events.recv_timeout( Duration::from_millis(100) ).ok() )
+			.filter( |e| *e != Seen::Changed )
+			.collect::<Vec<_>>();
+		assert_eq!( reported, [Seen::Finished(done), Seen::Dropped(queued), Seen::Dropped(running)] );
+	}
+
+	#[test]
+	fn move_within_queued_jobs() {
+		let (queue, _gate, _events) = queue(1);
+		let running = queue.push( GenerationRequest::default() );
+		wait_for( &queue, running, |s| matches!( s, JobState::Running(_) ) );
+		let [a, b, c] = <[JobId;3]>::try_from( queue.push_all( vec![GenerationRequest::default(); 3], |_| {} ) ).unwrap();
+
+		queue.move_job( c, -10 );
+		assert_eq!( ids(&queue), [running, c, a, b] );
+		queue.move_job( c, 10 );
+		assert_eq!( ids(&queue), [running, a, b, c] );
+		queue.move_job( a, 1 );
+		assert_eq!( ids(&queue), [running, b, a, c] );
+		// The running job and unknown ids stay put
+		queue.move_job( running, 2 );
+		queue.move_job( 99, -1 );
+		assert_eq!( ids(&queue), [running, b, a, c] );
+	}
+
+	#[test]
+	fn listener_can_use_the_queue() {
+		let (gate_tx, gate) = mpsc::channel();
+		let (seen_tx, seen) = mpsc::channel();
+		let handle = Arc::new( Mutex::new( None::<JobQueue> ) );
+		let listener_handle = handle.clone();
+		let queue = JobQueue::spawn( Box::new( Gated { steps : 1, gate } ), move |event| {
+			let Some(queue) = listener_handle.lock().unwrap().clone() else { return };
+			if let QueueEvent::Finished { id, .. } = event {
+				// Finished jobs are cleared from inside the listener, which reports a change of its own
+				queue.clear_finished();
+				let _ = seen_tx.send( (id, queue.jobs().len()) );
+			}
+		} );
+		*handle.lock().unwrap() = Some( queue.clone() );
+		let id = queue.push( GenerationRequest::default() );
+		gate_tx.send(()).unwrap();
+		assert_eq!( seen.recv_timeout(TIMEOUT), Ok( (id, 0) ) );
+		*handle.lock().unwrap() = None;
+	}
+}